@@ -1,10 +1,14 @@
 // server.js - Mock OpenAI with first-byte delay (forces 504 in the gateway)
 import http from 'http';
-import { randomUUID } from 'crypto';
+import { createHash, randomUUID } from 'crypto';
 
 const PORT = Number(process.env.PORT ?? 4000);
 // Set this > TIMEOUT_SECS*1000 in the gateway to trigger a 504, e.g. 5000 if TIMEOUT_SECS=2
 const LATENCY_MS = Number(process.env.LATENCY_MS ?? 0);
+// Fail the first FAIL_FIRST chat requests with FAIL_STATUS (and RETRY_AFTER, if set) to exercise retries
+let failuresLeft = Number(process.env.FAIL_FIRST ?? 0);
+const FAIL_STATUS = Number(process.env.FAIL_STATUS ?? 503);
+const RETRY_AFTER = process.env.RETRY_AFTER;
 
 const MOCK_REPLY = "Hi, I am a mock OpenAI server. I can help you test your gateway without spending credits.";
 const TOKENS = Array.from(MOCK_REPLY.match(/\s*[^\s]+/g) ?? []);
@@ -13,15 +17,513 @@ function logSafe(req, payload, stream) {
   const sanitizedHeaders = {
     ...req.headers,
     authorization: req.headers.authorization ? '[redacted]' : undefined,
+    'api-key': req.headers['api-key'] ? '[redacted]' : undefined,
   };
-  console.log('--- Incoming /v1/chat/completions ---');
+  console.log(`--- Incoming ${req.url} ---`);
   console.log('Headers:', sanitizedHeaders);
   console.log('Body:', JSON.stringify(payload, null, 2));
   console.log(`Simulating first-byte latency: ${LATENCY_MS} ms (stream=${stream})`);
 }
 
+function readJson(req, res, onPayload) {
+  let body = '';
+  req.on('data', (chunk) => { body += chunk; });
+  req.on('end', () => {
+    try {
+      onPayload(body.length ? JSON.parse(body) : {});
+    } catch {
+      res.writeHead(400, { 'Content-Type': 'application/json' });
+      res.end(JSON.stringify({ error: 'invalid_json', message: 'Could not parse request body.' }));
+    }
+  });
+}
+
+// ---- Anthropic Messages API (/v1/messages) ----
+function handleAnthropic(req, res, payload) {
+  const model = payload.model ?? 'claude-3-5-sonnet-latest';
+  const id = `msg_mock_${randomUUID()}`;
+  const stream = Boolean(payload.stream);
+  console.log('--- Incoming /v1/messages ---');
+  console.log('Body:', JSON.stringify(payload, null, 2));
+
+  setTimeout(() => {
+    if (!stream) {
+      res.writeHead(200, { 'Content-Type': 'application/json' });
+      res.end(JSON.stringify({
+        id,
+        type: 'message',
+        role: 'assistant',
+        model,
+        content: [{ type: 'text', text: MOCK_REPLY }],
+        stop_reason: 'end_turn',
+        usage: { input_tokens: 10, output_tokens: TOKENS.length },
+      }));
+      return;
+    }
+
+    res.writeHead(200, {
+      'Content-Type': 'text/event-stream',
+      'Cache-Control': 'no-cache',
+      Connection: 'keep-alive',
+    });
+    const send = (type, data) => res.write(`event: ${type}\ndata: ${JSON.stringify({ type, ...data })}\n\n`);
+    send('message_start', { message: { id, type: 'message', role: 'assistant', model, content: [], usage: { input_tokens: 10, output_tokens: 0 } } });
+    send('content_block_start', { index: 0, content_block: { type: 'text', text: '' } });
+    TOKENS.forEach((token, index) => {
+      setTimeout(() => {
+        try { send('content_block_delta', { index: 0, delta: { type: 'text_delta', text: token } }); } catch {}
+      }, index * 120);
+    });
+    setTimeout(() => {
+      try {
+        send('content_block_stop', { index: 0 });
+        send('message_delta', { delta: { stop_reason: 'end_turn' }, usage: { output_tokens: TOKENS.length } });
+        send('message_stop', {});
+        res.end();
+      } catch {}
+    }, TOKENS.length * 120 + 120);
+  }, LATENCY_MS);
+}
+
+// ---- Google Gemini (/v1beta/models/{model}:generateContent | :streamGenerateContent) ----
+function handleGemini(req, res, payload, model, stream) {
+  console.log(`--- Incoming Gemini ${model} (stream=${stream}) ---`);
+  console.log('Body:', JSON.stringify(payload, null, 2));
+  const responseId = `mock-${randomUUID()}`;
+  const usage = (done) => ({
+    promptTokenCount: 10,
+    candidatesTokenCount: done,
+    totalTokenCount: 10 + done,
+  });
+
+  setTimeout(() => {
+    res.writeHead(200, { 'Content-Type': 'application/json' });
+    if (!stream) {
+      res.end(JSON.stringify({
+        candidates: [{ index: 0, content: { role: 'model', parts: [{ text: MOCK_REPLY }] }, finishReason: 'STOP' }],
+        usageMetadata: usage(TOKENS.length),
+        modelVersion: model,
+        responseId,
+      }));
+      return;
+    }
+
+    // Real Gemini streams one JSON array, element by element, when alt=sse is not set.
+    res.write('[');
+    TOKENS.forEach((token, index) => {
+      setTimeout(() => {
+        const last = index === TOKENS.length - 1;
+        const element = {
+          candidates: [{
+            index: 0,
+            content: { role: 'model', parts: [{ text: token }] },
+            ...(last ? { finishReason: 'STOP' } : {}),
+          }],
+          usageMetadata: usage(index + 1),
+          modelVersion: model,
+          responseId,
+        };
+        try {
+          res.write(`${index === 0 ? '' : ',\r\n'}${JSON.stringify(element)}`);
+          if (last) res.end(']');
+        } catch {}
+      }, index * 120);
+    });
+  }, LATENCY_MS);
+}
+
+// ---- Ollama (/api/chat, NDJSON stream) ----
+function handleOllama(req, res, payload) {
+  const model = payload.model ?? 'llama3.1';
+  const stream = payload.stream !== false;
+  console.log('--- Incoming /api/chat ---');
+  console.log('Body:', JSON.stringify(payload, null, 2));
+  const done = { done: true, done_reason: 'stop', prompt_eval_count: 10, eval_count: TOKENS.length };
+
+  setTimeout(() => {
+    if (!stream) {
+      res.writeHead(200, { 'Content-Type': 'application/json' });
+      res.end(JSON.stringify({ model, message: { role: 'assistant', content: MOCK_REPLY }, ...done }));
+      return;
+    }
+    res.writeHead(200, { 'Content-Type': 'application/x-ndjson' });
+    TOKENS.forEach((token, index) => {
+      setTimeout(() => {
+        try { res.write(`${JSON.stringify({ model, message: { role: 'assistant', content: token }, done: false })}\n`); } catch {}
+      }, index * 120);
+    });
+    setTimeout(() => {
+      try {
+        res.end(`${JSON.stringify({ model, message: { role: 'assistant', content: '' }, ...done })}\n`);
+      } catch {}
+    }, TOKENS.length * 120 + 120);
+  }, LATENCY_MS);
+}
+
+// ---- llama.cpp server (/completion, SSE stream) ----
+function handleLlamaCpp(req, res, payload) {
+  const stream = Boolean(payload.stream);
+  console.log('--- Incoming /completion ---');
+  console.log('Body:', JSON.stringify(payload, null, 2));
+  const done = { stop: true, stop_type: 'eos', tokens_evaluated: 10, tokens_predicted: TOKENS.length };
+
+  setTimeout(() => {
+    if (!stream) {
+      res.writeHead(200, { 'Content-Type': 'application/json' });
+      res.end(JSON.stringify({ content: MOCK_REPLY, ...done }));
+      return;
+    }
+    res.writeHead(200, { 'Content-Type': 'text/event-stream', 'Cache-Control': 'no-cache' });
+    TOKENS.forEach((token, index) => {
+      setTimeout(() => {
+        try { res.write(`data: ${JSON.stringify({ content: token, stop: false })}\n\n`); } catch {}
+      }, index * 120);
+    });
+    setTimeout(() => {
+      try { res.end(`data: ${JSON.stringify({ content: '', ...done })}\n\n`); } catch {}
+    }, TOKENS.length * 120 + 120);
+  }, LATENCY_MS);
+}
+
+// ---- OpenAI Responses API (/v1/responses, typed SSE events) ----
+function handleResponses(req, res, payload) {
+  const model = payload.model ?? 'gpt-4.1-mini';
+  const id = `resp_mock_${randomUUID()}`;
+  const itemId = `msg_mock_${randomUUID()}`;
+  const stream = Boolean(payload.stream);
+  console.log('--- Incoming /v1/responses ---');
+  console.log('Body:', JSON.stringify(payload, null, 2));
+  const message = (text, status) => ({
+    id: itemId, type: 'message', role: 'assistant', status,
+    content: text === null ? [] : [{ type: 'output_text', text, annotations: [] }],
+  });
+  const response = (status, output) => ({
+    id, object: 'response', created_at: Math.floor(Date.now() / 1000), model, status, output,
+    usage: status === 'completed' ? { input_tokens: 10, output_tokens: TOKENS.length, total_tokens: 10 + TOKENS.length } : null,
+  });
+
+  setTimeout(() => {
+    if (!stream) {
+      res.writeHead(200, { 'Content-Type': 'application/json' });
+      res.end(JSON.stringify(response('completed', [message(MOCK_REPLY, 'completed')])));
+      return;
+    }
+    res.writeHead(200, { 'Content-Type': 'text/event-stream', 'Cache-Control': 'no-cache' });
+    let sequence = 0;
+    const send = (type, data) => res.write(`event: ${type}\ndata: ${JSON.stringify({ type, sequence_number: sequence++, ...data })}\n\n`);
+    send('response.created', { response: response('in_progress', []) });
+    send('response.output_item.added', { output_index: 0, item: message(null, 'in_progress') });
+    send('response.content_part.added', { item_id: itemId, output_index: 0, content_index: 0, part: { type: 'output_text', text: '' } });
+    TOKENS.forEach((token, index) => {
+      setTimeout(() => {
+        try { send('response.output_text.delta', { item_id: itemId, output_index: 0, content_index: 0, delta: token }); } catch {}
+      }, index * 120);
+    });
+    setTimeout(() => {
+      try {
+        send('response.output_text.done', { item_id: itemId, output_index: 0, content_index: 0, text: MOCK_REPLY });
+        send('response.output_item.done', { output_index: 0, item: message(MOCK_REPLY, 'completed') });
+        send('response.completed', { response: response('completed', [message(MOCK_REPLY, 'completed')]) });
+        res.end();
+      } catch {}
+    }, TOKENS.length * 120 + 120);
+  }, LATENCY_MS);
+}
+
+// ---- Embeddings (OpenAI/Azure, Ollama /api/embed, Gemini :batchEmbedContents) ----
+// Small deterministic vectors derived from the text, so equal inputs embed equally.
+// Content is a string, null, or an array of typed parts
+function messageText(msg) {
+  if (Array.isArray(msg.content)) {
+    return msg.content.filter((p) => p.type === 'text').map((p) => p.text).join('\n');
+  }
+  return msg.content ?? '';
+}
+
+// When the request offers tools, call the first one with the last user
+// message as its argument, streamed in small fragments like OpenAI does.
+function handleToolCall(res, payload, { id, created, model, stream }) {
+  const tool = payload.tools[0];
+  const lastUser = [...(payload.messages ?? [])].reverse().find((m) => m.role === 'user');
+  const args = JSON.stringify({ text: lastUser ? messageText(lastUser) : '' });
+  const call = { id: `call_${randomUUID().slice(0, 8)}`, type: 'function', function: { name: tool.function?.name ?? 'tool', arguments: args } };
+
+  if (!stream) {
+    res.writeHead(200, { 'Content-Type': 'application/json' });
+    res.end(JSON.stringify({
+      id, object: 'chat.completion', created, model,
+      choices: [{ index: 0, message: { role: 'assistant', content: null, tool_calls: [call] }, finish_reason: 'tool_calls' }],
+    }));
+    return;
+  }
+
+  res.writeHead(200, { 'Content-Type': 'text/event-stream', 'Cache-Control': 'no-cache', Connection: 'keep-alive' });
+  const write = (delta, finishReason = null) => res.write(`data: ${JSON.stringify({
+    id, object: 'chat.completion.chunk', created, model,
+    choices: [{ index: 0, delta, finish_reason: finishReason }],
+  })}\n\n`);
+  write({ role: 'assistant', content: null, tool_calls: [{ index: 0, ...call, function: { name: call.function.name, arguments: '' } }] });
+  for (let i = 0; i < args.length; i += 7) {
+    write({ tool_calls: [{ index: 0, function: { arguments: args.slice(i, i + 7) } }] });
+  }
+  write({}, 'tool_calls');
+  res.write('data: [DONE]\n\n');
+  res.end();
+}
+
+function fakeEmbedding(text) {
+  const vector = new Array(8).fill(0);
+  Array.from(String(text)).forEach((ch, i) => { vector[i % 8] += ch.charCodeAt(0) / 1000; });
+  return vector;
+}
+
+function countWords(inputs) {
+  return inputs.reduce((acc, text) => acc + String(text).split(/\s+/).filter(Boolean).length, 0);
+}
+
+function handleEmbeddings(req, res, payload) {
+  const inputs = Array.isArray(payload.input) ? payload.input : [payload.input ?? ''];
+  console.log(`--- Incoming ${req.url} ---`);
+  console.log('Body:', JSON.stringify(payload, null, 2));
+  const tokens = countWords(inputs.map((i) => (Array.isArray(i) || typeof i === 'number' ? 'tok' : i)));
+  res.writeHead(200, { 'Content-Type': 'application/json' });
+  res.end(JSON.stringify({
+    object: 'list',
+    data: inputs.map((input, index) => ({ object: 'embedding', index, embedding: fakeEmbedding(input) })),
+    model: payload.model ?? 'text-embedding-3-small',
+    usage: { prompt_tokens: tokens, total_tokens: tokens },
+  }));
+}
+
+function handleOllamaEmbed(req, res, payload) {
+  const inputs = Array.isArray(payload.input) ? payload.input : [payload.input ?? ''];
+  console.log('--- Incoming /api/embed ---');
+  console.log('Body:', JSON.stringify(payload, null, 2));
+  res.writeHead(200, { 'Content-Type': 'application/json' });
+  res.end(JSON.stringify({
+    model: payload.model ?? 'nomic-embed-text',
+    embeddings: inputs.map(fakeEmbedding),
+    prompt_eval_count: countWords(inputs),
+  }));
+}
+
+function handleGeminiEmbed(req, res, payload) {
+  console.log(`--- Incoming ${req.url} ---`);
+  console.log('Body:', JSON.stringify(payload, null, 2));
+  const requests = payload.requests ?? [];
+  res.writeHead(200, { 'Content-Type': 'application/json' });
+  res.end(JSON.stringify({
+    embeddings: requests.map((r) => ({ values: fakeEmbedding(r.content?.parts?.map((p) => p.text).join('') ?? '') })),
+  }));
+}
+
+// ---- Files and Batches APIs, kept in memory ----
+const files = new Map();
+const batches = new Map();
+// Batch results mention a contact address so downloads have something to redact
+const BATCH_REPLY = `${MOCK_REPLY} Questions? Write to help@mock-openai.example.`;
+
+function sendJson(res, status, body) {
+  res.writeHead(status, { 'Content-Type': 'application/json' });
+  res.end(JSON.stringify(body));
+}
+
+function fileObject(file) {
+  const { content, ...meta } = file;
+  return meta;
+}
+
+function storeFile(purpose, filename, content) {
+  const file = {
+    id: `file-mock-${randomUUID()}`,
+    object: 'file',
+    bytes: Buffer.byteLength(content),
+    created_at: Math.floor(Date.now() / 1000),
+    filename,
+    purpose,
+    content,
+  };
+  files.set(file.id, file);
+  return file;
+}
+
+// Just enough multipart/form-data parsing for the gateway's uploads. Values are
+// latin1 strings so binary parts keep their byte length.
+function readMultipart(req, onFields) {
+  const boundary = (req.headers['content-type'] ?? '').match(/boundary=(.+)$/)?.[1];
+  const chunks = [];
+  req.on('data', (chunk) => chunks.push(chunk));
+  req.on('end', () => {
+    const fields = {};
+    for (const part of Buffer.concat(chunks).toString('latin1').split(`--${boundary}`)) {
+      const [head, ...rest] = part.split('\r\n\r\n');
+      const name = head.match(/name="([^"]+)"/)?.[1];
+      if (!name) continue;
+      fields[name] = {
+        value: rest.join('\r\n\r\n').replace(/\r\n$/, ''),
+        filename: head.match(/filename="([^"]+)"/)?.[1],
+      };
+    }
+    onFields(fields);
+  });
+}
+
+function handleFileUpload(req, res) {
+  readMultipart(req, (fields) => {
+    const content = Buffer.from(fields.file?.value ?? '', 'latin1').toString('utf8');
+    console.log('--- Incoming /v1/files ---');
+    console.log('File:', content);
+    if (!fields.file) {
+      sendJson(res, 400, { error: { message: 'missing file', type: 'invalid_request_error' } });
+      return;
+    }
+    sendJson(res, 200, fileObject(storeFile(fields.purpose?.value, fields.file.filename, content)));
+  });
+}
+
+function handleFiles(req, res, path) {
+  const [, id, content] = path.match(/^\/v1\/files(?:\/([^/]+))?(\/content)?$/) ?? [];
+  if (!id) {
+    sendJson(res, 200, { object: 'list', data: [...files.values()].map(fileObject) });
+    return;
+  }
+  const file = files.get(id);
+  if (!file) {
+    sendJson(res, 404, { error: { message: `No such File object: ${id}`, type: 'invalid_request_error' } });
+    return;
+  }
+  if (req.method === 'DELETE') {
+    files.delete(id);
+    sendJson(res, 200, { id, object: 'file', deleted: true });
+  } else if (content) {
+    res.writeHead(200, { 'Content-Type': 'application/octet-stream' });
+    res.end(file.content);
+  } else {
+    sendJson(res, 200, fileObject(file));
+  }
+}
+
+// Batches complete as soon as they are created
+function handleCreateBatch(res, payload) {
+  const input = files.get(payload.input_file_id);
+  if (!input) {
+    sendJson(res, 404, { error: { message: `No such File object: ${payload.input_file_id}`, type: 'invalid_request_error' } });
+    return;
+  }
+  const lines = input.content.split('\n').filter((l) => l.trim()).map((l) => JSON.parse(l));
+  console.log('--- Incoming /v1/batches ---');
+  console.log('Requests:', JSON.stringify(lines, null, 2));
+  const output = lines.map((line) => JSON.stringify({
+    id: `batch_req_${randomUUID()}`,
+    custom_id: line.custom_id,
+    response: {
+      status_code: 200,
+      request_id: randomUUID(),
+      body: {
+        id: `chatcmpl-mock-${randomUUID()}`,
+        object: 'chat.completion',
+        model: line.body?.model,
+        choices: [{ index: 0, message: { role: 'assistant', content: BATCH_REPLY }, finish_reason: 'stop' }],
+      },
+    },
+    error: null,
+  })).join('\n');
+  const now = Math.floor(Date.now() / 1000);
+  const batch = {
+    id: `batch_mock_${randomUUID()}`,
+    object: 'batch',
+    endpoint: payload.endpoint,
+    input_file_id: input.id,
+    completion_window: payload.completion_window ?? '24h',
+    status: 'completed',
+    output_file_id: storeFile('batch_output', 'batch_output.jsonl', `${output}\n`).id,
+    created_at: now,
+    completed_at: now,
+    request_counts: { total: lines.length, completed: lines.length, failed: 0 },
+    metadata: payload.metadata ?? null,
+  };
+  batches.set(batch.id, batch);
+  sendJson(res, 200, batch);
+}
+
+function handleBatches(req, res, path) {
+  const [, id, cancel] = path.match(/^\/v1\/batches(?:\/([^/]+))?(\/cancel)?$/) ?? [];
+  if (!id) {
+    sendJson(res, 200, { object: 'list', data: [...batches.values()], has_more: false });
+    return;
+  }
+  const batch = batches.get(id);
+  if (!batch) {
+    sendJson(res, 404, { error: { message: `No such Batch object: ${id}`, type: 'invalid_request_error' } });
+    return;
+  }
+  if (cancel && batch.status !== 'completed') batch.status = 'cancelled';
+  sendJson(res, 200, batch);
+}
+
+// ---- Audio: transcripts mention an address so redaction is visible ----
+function handleTranscription(req, res) {
+  readMultipart(req, (fields) => {
+    const bytes = fields.file?.value.length ?? 0;
+    // Pretend the upload is 16 kB/s audio
+    const seconds = Math.max(1, Math.ceil(bytes / 16000));
+    const format = fields.response_format?.value ?? 'json';
+    const text = `Transcript of ${fields.file?.filename ?? 'audio'}: please email jane.doe@example.com.`;
+    console.log('--- Incoming /v1/audio/transcriptions ---');
+    console.log('Fields:', { model: fields.model?.value, prompt: fields.prompt?.value, format, bytes });
+    if (format === 'verbose_json') {
+      sendJson(res, 200, {
+        task: 'transcribe',
+        language: 'english',
+        duration: seconds,
+        text,
+        segments: [{ id: 0, start: 0, end: seconds, text }],
+      });
+    } else if (format === 'json') {
+      sendJson(res, 200, { text, usage: { type: 'duration', seconds } });
+    } else {
+      res.writeHead(200, { 'Content-Type': 'text/plain; charset=utf-8' });
+      res.end(format === 'text' ? text : `1\n00:00:00,000 --> 00:00:0${Math.min(seconds, 9)},000\n${text}\n`);
+    }
+  });
+}
+
+// Streams a few chunks of fake audio, longer for longer input
+function handleSpeech(res, payload) {
+  console.log('--- Incoming /v1/audio/speech ---');
+  console.log('Body:', JSON.stringify(payload, null, 2));
+  res.writeHead(200, { 'Content-Type': 'audio/mpeg' });
+  let chunks = Math.max(1, Math.ceil(String(payload.input ?? '').length / 20));
+  const timer = setInterval(() => {
+    res.write(Buffer.alloc(1024, chunks));
+    if (--chunks === 0) {
+      clearInterval(timer);
+      res.end();
+    }
+  }, 20);
+}
+
+// ---- Images: a 1x1 PNG, inline or "hosted" ----
+const PIXEL_PNG = 'iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNk+M9QDwADhgGAWjR9awAAAABJRU5ErkJggg==';
+
+function handleImages(res, payload) {
+  console.log('--- Incoming /v1/images/generations ---');
+  console.log('Body:', JSON.stringify(payload, null, 2));
+  const inline = payload.response_format === 'b64_json' || String(payload.model ?? '').startsWith('gpt-image');
+  sendJson(res, 200, {
+    created: Math.floor(Date.now() / 1000),
+    data: Array.from({ length: payload.n ?? 1 }, (_, i) => ({
+      ...(inline ? { b64_json: PIXEL_PNG } : { url: `https://images.mock-openai.example/${randomUUID()}.png` }),
+      revised_prompt: `${payload.prompt} (variation ${i + 1})`,
+    })),
+  });
+}
+
 const server = http.createServer((req, res) => {
-  if (req.method === 'POST' && req.url === '/v1/chat/completions') {
+  // Azure OpenAI serves the same payloads from /openai/deployments/{deployment}/chat/completions
+  const chatPath = req.url === '/v1/chat/completions'
+    || /^\/openai\/deployments\/[^/]+\/chat\/completions(\?|$)/.test(req.url);
+  if (req.method === 'POST' && chatPath) {
     let body = '';
 
     req.on('data', (chunk) => { body += chunk; });
@@ -43,8 +545,31 @@ const server = http.createServer((req, res) => {
 
       logSafe(req, payload, stream);
 
+      if (failuresLeft > 0) {
+        failuresLeft -= 1;
+        console.log(`[mock] Failing with ${FAIL_STATUS} (${failuresLeft} failures left)`);
+        res.writeHead(FAIL_STATUS, {
+          'Content-Type': 'application/json',
+          ...(RETRY_AFTER ? { 'Retry-After': RETRY_AFTER } : {}),
+        });
+        res.end(JSON.stringify({ error: 'mock_failure', message: 'Injected failure.' }));
+        return;
+      }
+
       // Do not send ANYTHING before this delay.
       setTimeout(() => {
+        if (Array.isArray(payload.tools) && payload.tools.length > 0) {
+          handleToolCall(res, payload, { id, created, model, stream });
+          return;
+        }
+
+        const promptTokens = Array.isArray(payload.messages)
+          ? payload.messages.reduce(
+              (acc, msg) => acc + messageText(msg).split(/\s+/).filter(Boolean).length,
+              0,
+            )
+          : 0;
+
         if (stream) {
           // ---- STREAMING (SSE) ----
           console.log('[mock] Sending headers SSE after delay…');
@@ -60,6 +585,7 @@ const server = http.createServer((req, res) => {
               object: 'chat.completion.chunk',
               created,
               model,
+              system_fingerprint: 'fp_mock',
               choices: [{
                 index: 0,
                 delta: index === 0 ? { role: 'assistant', content: token } : { content: token },
@@ -82,6 +608,14 @@ const server = http.createServer((req, res) => {
                 choices: [{ index: 0, delta: {}, finish_reason: 'stop' }],
               };
               res.write(`data: ${JSON.stringify(finalChunk)}\n\n`);
+              if (payload.stream_options?.include_usage) {
+                const usage = {
+                  prompt_tokens: promptTokens,
+                  completion_tokens: TOKENS.length,
+                  total_tokens: promptTokens + TOKENS.length,
+                };
+                res.write(`data: ${JSON.stringify({ id, object: 'chat.completion.chunk', created, model, choices: [], usage })}\n\n`);
+              }
               res.write('data: [DONE]\n\n');
               res.end();
             } catch {}
@@ -91,18 +625,13 @@ const server = http.createServer((req, res) => {
         }
 
         // ---- NO-STREAM ----
-        const promptTokens = Array.isArray(payload.messages)
-          ? payload.messages.reduce(
-              (acc, msg) => acc + (msg.content?.split(/\s+/).filter(Boolean).length ?? 0),
-              0,
-            )
-          : 0;
 
         const responseBody = {
           id,
           object: 'chat.completion',
           created,
           model,
+          system_fingerprint: 'fp_mock',
           choices: [{
             index: 0,
             message: { role: 'assistant', content: MOCK_REPLY },
@@ -132,11 +661,186 @@ const server = http.createServer((req, res) => {
     return;
   }
 
+  if (req.method === 'POST' && req.url === '/v1/messages') {
+    readJson(req, res, (payload) => handleAnthropic(req, res, payload));
+    return;
+  }
+
+  const embeddingsPath = req.url === '/v1/embeddings'
+    || /^\/openai\/deployments\/[^/]+\/embeddings(\?|$)/.test(req.url);
+  if (req.method === 'POST' && embeddingsPath) {
+    readJson(req, res, (payload) => handleEmbeddings(req, res, payload));
+    return;
+  }
+
+  if (req.method === 'POST' && req.url === '/v1/responses') {
+    readJson(req, res, (payload) => handleResponses(req, res, payload));
+    return;
+  }
+
+  if (req.method === 'POST' && req.url === '/api/embed') {
+    readJson(req, res, (payload) => handleOllamaEmbed(req, res, payload));
+    return;
+  }
+
+  if (req.method === 'POST' && /^\/v1beta\/models\/[^:?]+:batchEmbedContents/.test(req.url)) {
+    readJson(req, res, (payload) => handleGeminiEmbed(req, res, payload));
+    return;
+  }
+
+  if (req.method === 'POST' && req.url === '/api/chat') {
+    readJson(req, res, (payload) => handleOllama(req, res, payload));
+    return;
+  }
+
+  if (req.method === 'POST' && req.url === '/completion') {
+    readJson(req, res, (payload) => handleLlamaCpp(req, res, payload));
+    return;
+  }
+
+  const gemini = req.method === 'POST' && req.url.match(/^\/v1beta\/models\/([^:?]+):(generateContent|streamGenerateContent)/);
+  if (gemini) {
+    readJson(req, res, (payload) => handleGemini(req, res, payload, gemini[1], gemini[2] === 'streamGenerateContent'));
+    return;
+  }
+
+  const path = req.url.split('?')[0];
+  if (req.method === 'POST' && path === '/v1/audio/transcriptions') {
+    handleTranscription(req, res);
+    return;
+  }
+  if (req.method === 'POST' && path === '/v1/audio/speech') {
+    readJson(req, res, (payload) => handleSpeech(res, payload));
+    return;
+  }
+  if (req.method === 'POST' && path === '/v1/images/generations') {
+    readJson(req, res, (payload) => handleImages(res, payload));
+    return;
+  }
+  if (req.method === 'POST' && path === '/v1/files') {
+    handleFileUpload(req, res);
+    return;
+  }
+  if (path.startsWith('/v1/files')) {
+    handleFiles(req, res, path);
+    return;
+  }
+  if (req.method === 'POST' && path === '/v1/batches') {
+    readJson(req, res, (payload) => handleCreateBatch(res, payload));
+    return;
+  }
+  if (path.startsWith('/v1/batches')) {
+    handleBatches(req, res, path);
+    return;
+  }
+
+  // Cheap read-only routes the gateway's health probes call
+  if (req.method === 'GET') {
+    const listings = {
+      '/v1/models': { object: 'list', data: [{ id: 'gpt-4o-mini', object: 'model', owned_by: 'mock' }] },
+      '/openai/models': { data: [{ id: 'gpt-4o-mini', object: 'model' }] },
+      '/v1beta/models': { models: [{ name: 'models/gemini-1.5-flash' }] },
+      '/api/tags': { models: [{ name: 'llama3.1' }] },
+      '/health': { status: 'ok' },
+    };
+    if (listings[path]) {
+      res.writeHead(200, { 'Content-Type': 'application/json' });
+      res.end(JSON.stringify(listings[path]));
+      return;
+    }
+  }
+
   res.writeHead(404, { 'Content-Type': 'application/json' });
   res.end(JSON.stringify({ error: 'not_found', message: 'Route not found in the OpenAI mock.' }));
 
 });
 
+// ---- Realtime API (/v1/realtime): a bare-bones WebSocket server ----
+function wsSend(socket, text) {
+  const payload = Buffer.from(text);
+  const len = payload.length;
+  const header = len < 126
+    ? Buffer.from([0x81, len])
+    : len < 65536
+      ? Buffer.from([0x81, 126, len >> 8, len & 0xff])
+      : Buffer.concat([Buffer.from([0x81, 127]), (() => { const b = Buffer.alloc(8); b.writeBigUInt64BE(BigInt(len)); return b; })()]);
+  socket.write(Buffer.concat([header, payload]));
+}
+
+// Yields the complete (unfragmented) client frames in `buffer`, and what is left over
+function wsFrames(buffer) {
+  const frames = [];
+  for (;;) {
+    if (buffer.length < 2) break;
+    const opcode = buffer[0] & 0x0f;
+    let len = buffer[1] & 0x7f;
+    let offset = 2;
+    if (len === 126) { len = buffer.readUInt16BE(2); offset = 4; }
+    else if (len === 127) { len = Number(buffer.readBigUInt64BE(2)); offset = 10; }
+    if (buffer.length < offset + 4 + len) break;
+    const mask = buffer.subarray(offset, offset + 4);
+    const payload = Buffer.from(buffer.subarray(offset + 4, offset + 4 + len));
+    for (let i = 0; i < payload.length; i++) payload[i] ^= mask[i % 4];
+    frames.push({ opcode, payload });
+    buffer = buffer.subarray(offset + 4 + len);
+  }
+  return [frames, buffer];
+}
+
+function handleRealtimeEvent(socket, event) {
+  const send = (e) => wsSend(socket, JSON.stringify({ event_id: `event_${randomUUID()}`, ...e }));
+  if (event.type === 'input_audio_buffer.commit') {
+    send({ type: 'conversation.item.input_audio_transcription.completed', item_id: 'item_in', content_index: 0, transcript: 'This is jane.doe@example.com speaking.' });
+  }
+  if (event.type !== 'response.create') return;
+  const responseId = `resp_${randomUUID()}`;
+  send({ type: 'response.created', response: { id: responseId, status: 'in_progress', output: [] } });
+  for (const token of TOKENS) {
+    send({ type: 'response.text.delta', response_id: responseId, item_id: 'item_out', output_index: 0, content_index: 0, delta: token });
+  }
+  const text = `${MOCK_REPLY} Reach me at help@mock-openai.example.`;
+  send({ type: 'response.audio_transcript.delta', response_id: responseId, item_id: 'item_out', delta: 'Reach me at help@mock-openai.example.' });
+  send({ type: 'response.audio.delta', response_id: responseId, item_id: 'item_out', delta: Buffer.from('fake pcm16 audio').toString('base64') });
+  send({ type: 'response.text.done', response_id: responseId, item_id: 'item_out', text });
+  send({
+    type: 'response.done',
+    response: { id: responseId, status: 'completed', output: [{ id: 'item_out', type: 'message', role: 'assistant', content: [{ type: 'text', text }] }] },
+  });
+}
+
+server.on('upgrade', (req, socket) => {
+  if (!req.url.startsWith('/v1/realtime')) {
+    socket.end('HTTP/1.1 404 Not Found\r\n\r\n');
+    return;
+  }
+  const accept = createHash('sha1')
+    .update(`${req.headers['sec-websocket-key']}258EAFA5-E914-47DA-95CA-C5AB0DC85B11`)
+    .digest('base64');
+  socket.write(`HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: ${accept}\r\n\r\n`);
+  console.log(`--- Incoming realtime session ${req.url} ---`);
+  const model = new URL(req.url, 'http://mock').searchParams.get('model');
+  wsSend(socket, JSON.stringify({ type: 'session.created', session: { id: `sess_${randomUUID()}`, model, modalities: ['text', 'audio'] } }));
+  let pending = Buffer.alloc(0);
+  socket.on('data', (chunk) => {
+    const [frames, rest] = wsFrames(Buffer.concat([pending, chunk]));
+    pending = rest;
+    for (const { opcode, payload } of frames) {
+      if (opcode === 0x8) {
+        socket.end(Buffer.from([0x88, 0]));
+        return;
+      }
+      if (opcode !== 0x1) continue;
+      console.log('Event:', payload.toString());
+      try {
+        handleRealtimeEvent(socket, JSON.parse(payload.toString()));
+      } catch {
+        wsSend(socket, JSON.stringify({ type: 'error', error: { type: 'invalid_request_error', message: 'Could not parse event.' } }));
+      }
+    }
+  });
+  socket.on('error', () => {});
+});
+
 server.listen(PORT, () => {
   console.log(`Mock OpenAI API listening on http://localhost:${PORT} (LATENCY_MS=${LATENCY_MS}ms)`);
 });
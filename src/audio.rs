@@ -0,0 +1,458 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::{multipart::Field, Multipart, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::fallback::{self, Served};
+use crate::provider::{
+    peek_first, AudioUpload, SpeechRequest, SpooledFile, Transcript, TranscriptionRequest,
+};
+use crate::redact::{redact_text, redaction_spans, RedactionStats};
+use crate::telemetry::track_http_metrics;
+use crate::{
+    handle_quota_error, model_forbidden, record_upstream, tenant, upstream_error_status,
+    with_upstream_header, AppState,
+};
+
+const TRANSCRIPTIONS_ROUTE: &str = "/v1/audio/transcriptions";
+const SPEECH_ROUTE: &str = "/v1/audio/speech";
+/// Speaking rate used to estimate the length of generated speech up front.
+const CHARS_PER_SECOND: f64 = 15.0;
+/// Bitrate assumed for compressed uploads whose transcript carries no
+/// duration: 128 kbit/s.
+const UPLOAD_BYTES_PER_SECOND: f64 = 16_000.0;
+/// Bytes of a WAV file before its samples.
+const WAV_HEADER_LEN: usize = 44;
+
+/// Transcribes an uploaded recording. One request is charged up front and
+/// the rest of the audio's `AUDIO_QUOTA_SECS` units once it is transcribed,
+/// by the duration the upstream reports or, for formats that carry none, an
+/// estimate from the upload. The `prompt` field and the transcript are
+/// redacted.
+#[instrument(skip_all, fields(tenant = %tenant(&headers),
+                              model = tracing::field::Empty,
+                              route = tracing::field::Empty,
+                              provider = tracing::field::Empty,
+                              upstream_model = tracing::field::Empty))]
+pub async fn transcriptions_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+    metrics::counter!("requests_total", "route" => TRANSCRIPTIONS_ROUTE).increment(1);
+
+    let mut model = None;
+    let mut file = None;
+    let mut fields = Vec::new();
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return e.into_response(),
+        };
+        let Some(name) = field.name().map(str::to_string) else {
+            continue;
+        };
+        if name == "file" {
+            let filename = field.file_name().unwrap_or("audio").to_string();
+            let content_type = field.content_type().map(str::to_string);
+            match spool(field, state.cfg.audio_max_bytes).await {
+                Ok(upload) => file = Some((filename, content_type, upload)),
+                Err(resp) => return resp,
+            }
+            continue;
+        }
+        let value = match field.text().await {
+            Ok(value) => value,
+            Err(e) => return e.into_response(),
+        };
+        if name == "model" {
+            model = Some(value);
+        } else {
+            fields.push((name, value));
+        }
+    }
+    let Some(model) = model else {
+        return error(StatusCode::BAD_REQUEST, "missing model");
+    };
+    let Some((filename, content_type, (file, header))) = file else {
+        return error(StatusCode::BAD_REQUEST, "missing file");
+    };
+    tracing::Span::current().record("model", model.as_str());
+
+    if !state.permissions.allows(tenant(&headers), &model) {
+        return model_forbidden(TRANSCRIPTIONS_ROUTE, &model);
+    }
+    if let Some(quota) = state.quota.as_ref() {
+        if let Err(err) = quota.check_and_increment(tenant(&headers)).await {
+            return handle_quota_error(err);
+        }
+    }
+
+    let mut redaction_stats = RedactionStats::default();
+    for (_, prompt) in fields.iter_mut().filter(|(name, _)| name == "prompt") {
+        let (redacted, stats) = redact_text(prompt);
+        *prompt = redacted;
+        redaction_stats += stats;
+    }
+    metrics::histogram!("audio_upload_bytes", "route" => TRANSCRIPTIONS_ROUTE)
+        .record(file.len() as f64);
+    let estimate = upload_seconds(&header, file.len());
+
+    let route = state.router.resolve(&model);
+    tracing::Span::current().record("route", route.route.as_str());
+    let request = TranscriptionRequest {
+        model: model.clone(),
+        file,
+        filename,
+        content_type,
+        fields,
+    };
+    let served = fallback::dispatch(&state.providers, &route, None, |provider, model| {
+        let request = TranscriptionRequest {
+            model,
+            ..request.clone()
+        };
+        async move { provider.transcription(request).await }
+    })
+    .await;
+    let Served {
+        value: mut transcript,
+        upstream,
+    } = match served {
+        Ok(served) => served,
+        Err(e) => {
+            track_http_metrics(
+                TRANSCRIPTIONS_ROUTE,
+                &model,
+                &route.route,
+                route.primary(),
+                &request_id,
+            );
+            return error(upstream_error_status(&e), e.to_string());
+        }
+    };
+    record_upstream(&upstream);
+
+    redaction_stats += redact_transcript(&mut transcript);
+    metrics::counter!("redactions_total").increment(redaction_stats.matches as u64);
+
+    let seconds = transcript_seconds(&transcript).unwrap_or(estimate);
+    metrics::histogram!("audio_duration_seconds", "route" => TRANSCRIPTIONS_ROUTE).record(seconds);
+    if let Some(quota) = state.quota.as_ref() {
+        let units = quota_units(seconds, state.cfg.audio_quota_secs);
+        if let Err(e) = quota.add(tenant(&headers), units - 1).await {
+            tracing::error!(error = %e, "failed to charge audio duration");
+        }
+    }
+
+    track_http_metrics(
+        TRANSCRIPTIONS_ROUTE,
+        &model,
+        &route.route,
+        &upstream,
+        &request_id,
+    );
+    let response = match transcript {
+        Transcript::Json(value) => Json(value).into_response(),
+        Transcript::Text(text) => {
+            ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], text).into_response()
+        }
+    };
+    with_upstream_header(response, &upstream)
+}
+
+/// Writes an uploaded file to disk as it arrives, refusing it with 413 once
+/// it grows past `max_bytes`. Returns it with its first `WAV_HEADER_LEN`
+/// bytes.
+async fn spool(mut field: Field<'_>, max_bytes: usize) -> Result<(AudioUpload, Vec<u8>), Response> {
+    let (spooled, mut out) = SpooledFile::create().await.map_err(spool_failed)?;
+    let mut header = Vec::with_capacity(WAV_HEADER_LEN);
+    let mut len = 0;
+    loop {
+        let chunk = match field.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => return Err(e.into_response()),
+        };
+        len += chunk.len();
+        if len > max_bytes {
+            return Err(error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("audio file is larger than {max_bytes} bytes"),
+            ));
+        }
+        let missing = WAV_HEADER_LEN - header.len();
+        header.extend_from_slice(&chunk[..chunk.len().min(missing)]);
+        out.write_all(&chunk).await.map_err(spool_failed)?;
+    }
+    out.flush().await.map_err(spool_failed)?;
+    Ok((AudioUpload::new(spooled, len as u64), header))
+}
+
+fn spool_failed(err: std::io::Error) -> Response {
+    tracing::error!(error = %err, "failed to spool audio upload");
+    error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "failed to store audio upload",
+    )
+}
+
+/// Synthesizes speech from redacted `input`, streaming the audio back as it
+/// is produced. The quota is charged before the request from an estimate of
+/// how long the speech will run.
+#[instrument(skip(state, headers, req), fields(tenant = %tenant(&headers),
+                                               model = %req.model,
+                                               route = tracing::field::Empty,
+                                               provider = tracing::field::Empty,
+                                               upstream_model = tracing::field::Empty))]
+pub async fn speech_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(mut req): Json<SpeechRequest>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+    metrics::counter!("requests_total", "route" => SPEECH_ROUTE).increment(1);
+
+    if !state.permissions.allows(tenant(&headers), &req.model) {
+        return model_forbidden(SPEECH_ROUTE, &req.model);
+    }
+    let seconds = req.input.chars().count() as f64 / CHARS_PER_SECOND;
+    if let Some(quota) = state.quota.as_ref() {
+        let units = quota_units(seconds, state.cfg.audio_quota_secs);
+        if let Err(err) = quota.check_and_add(tenant(&headers), units).await {
+            return handle_quota_error(err);
+        }
+    }
+
+    let (input, mut redaction_stats) = redact_text(&req.input);
+    req.input = input;
+    if let Some(Value::String(instructions)) = req.extra.get_mut("instructions") {
+        let (redacted, stats) = redact_text(instructions);
+        *instructions = redacted;
+        redaction_stats += stats;
+    }
+    metrics::counter!("redactions_total").increment(redaction_stats.matches as u64);
+
+    let route = state.router.resolve(&req.model);
+    tracing::Span::current().record("route", route.route.as_str());
+    let model = req.model.clone();
+    let served = fallback::dispatch(
+        &state.providers,
+        &route,
+        state.cfg.first_byte_timeout_ms.map(Duration::from_millis),
+        |provider, model| {
+            let req = SpeechRequest {
+                model,
+                ..req.clone()
+            };
+            async move { peek_first(provider.speech(req).await?).await }
+        },
+    )
+    .await;
+    let Served {
+        value: audio,
+        upstream,
+    } = match served {
+        Ok(served) => served,
+        Err(e) => {
+            track_http_metrics(
+                SPEECH_ROUTE,
+                &model,
+                &route.route,
+                route.primary(),
+                &request_id,
+            );
+            return error(upstream_error_status(&e), e.to_string());
+        }
+    };
+    record_upstream(&upstream);
+    metrics::histogram!("audio_duration_seconds", "route" => SPEECH_ROUTE).record(seconds);
+
+    track_http_metrics(SPEECH_ROUTE, &model, &route.route, &upstream, &request_id);
+    let content_type = speech_content_type(req.response_format.as_deref());
+    with_upstream_header(
+        (
+            [(header::CONTENT_TYPE, content_type)],
+            Body::from_stream(audio),
+        )
+            .into_response(),
+        &upstream,
+    )
+}
+
+/// Redacts the transcript text, and the segments and words of `verbose_json`.
+/// Words are matched as the text they spell out together, since an address
+/// or a number is often split over several of them.
+fn redact_transcript(transcript: &mut Transcript) -> RedactionStats {
+    let mut stats = RedactionStats::default();
+    let mut redact = |value: Option<&mut Value>| {
+        if let Some(Value::String(text)) = value {
+            let (redacted, s) = redact_text(text);
+            *text = redacted;
+            stats += s;
+        }
+    };
+    match transcript {
+        Transcript::Text(text) => {
+            let (redacted, s) = redact_text(text);
+            *text = redacted;
+            return s;
+        }
+        Transcript::Json(value) => {
+            redact(value.get_mut("text"));
+            if let Some(Value::Array(segments)) = value.get_mut("segments") {
+                for segment in segments {
+                    redact(segment.get_mut("text"));
+                }
+            }
+            if let Some(Value::Array(words)) = value.get_mut("words") {
+                stats += redact_words(words);
+            }
+        }
+    }
+    stats
+}
+
+/// Masks every entry of `words` that overlaps a value `redact_text` would
+/// mask in the words joined by spaces.
+fn redact_words(words: &mut [Value]) -> RedactionStats {
+    let mut text = String::new();
+    let mut ranges = Vec::with_capacity(words.len());
+    for word in words.iter() {
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        let start = text.len();
+        text.push_str(word["word"].as_str().unwrap_or_default());
+        ranges.push(start..text.len());
+    }
+    let spans = redaction_spans(&text);
+    for (word, range) in words.iter_mut().zip(ranges) {
+        if !spans
+            .iter()
+            .any(|s| s.start < range.end && range.start < s.end)
+        {
+            continue;
+        }
+        if let Some(Value::String(word)) = word.get_mut("word") {
+            *word = "*".repeat(word.chars().count());
+        }
+    }
+    RedactionStats {
+        matches: spans.len(),
+    }
+}
+
+/// Length of the transcribed audio: `usage.seconds` for duration-billed
+/// models, or the `duration` of `verbose_json`. Plain-text formats carry neither.
+fn transcript_seconds(transcript: &Transcript) -> Option<f64> {
+    let Transcript::Json(value) = transcript else {
+        return None;
+    };
+    value["usage"]["seconds"]
+        .as_f64()
+        .or_else(|| value["duration"].as_f64())
+}
+
+/// Length of an upload of `len` bytes starting with `header`: exact for WAV,
+/// whose header gives the byte rate, and assuming `UPLOAD_BYTES_PER_SECOND`
+/// for anything else.
+fn upload_seconds(header: &[u8], len: u64) -> f64 {
+    if header.len() == WAV_HEADER_LEN
+        && header.starts_with(b"RIFF")
+        && &header[8..16] == b"WAVEfmt "
+    {
+        let byte_rate = u32::from_le_bytes([header[28], header[29], header[30], header[31]]);
+        if byte_rate > 0 {
+            return len.saturating_sub(WAV_HEADER_LEN as u64) as f64 / byte_rate as f64;
+        }
+    }
+    len as f64 / UPLOAD_BYTES_PER_SECOND
+}
+
+/// Requests charged for `seconds` of audio; never less than one.
+fn quota_units(seconds: f64, secs_per_unit: u64) -> u32 {
+    (seconds / secs_per_unit as f64).ceil().max(1.0) as u32
+}
+
+fn speech_content_type(format: Option<&str>) -> &'static str {
+    match format.unwrap_or("mp3") {
+        "opus" => "audio/ogg",
+        "aac" => "audio/aac",
+        "flac" => "audio/flac",
+        "wav" => "audio/wav",
+        "pcm" => "audio/pcm",
+        _ => "audio/mpeg",
+    }
+}
+
+fn error(status: StatusCode, message: impl Into<String>) -> Response {
+    (status, Json(json!({ "error": message.into() }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transcripts_are_redacted_and_charged_by_duration() {
+        let mut transcript = Transcript::Json(json!({
+            "text": "mail jane.doe@example.com please",
+            "duration": 61.5,
+            "segments": [{ "id": 0, "text": "mail jane.doe@example.com please" }],
+            "words": [{ "word": "jane.doe@example.com", "start": 0.4, "end": 1.9 }],
+        }));
+        assert_eq!(redact_transcript(&mut transcript).matches, 3);
+        let Transcript::Json(value) = &transcript else {
+            unreachable!()
+        };
+        assert!(!value.to_string().contains("jane.doe@"));
+
+        let seconds = transcript_seconds(&transcript).unwrap();
+        assert_eq!(quota_units(seconds, 60), 2);
+        assert_eq!(quota_units(0.0, 60), 1);
+        let usage =
+            Transcript::Json(json!({ "text": "", "usage": { "type": "duration", "seconds": 3 } }));
+        assert_eq!(transcript_seconds(&usage), Some(3.0));
+        assert_eq!(transcript_seconds(&Transcript::Text("hi".into())), None);
+    }
+
+    #[test]
+    fn uploads_without_a_reported_duration_are_estimated() {
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        wav.resize(WAV_HEADER_LEN, 0);
+        // 16 kHz mono 16-bit PCM: 32000 bytes a second.
+        wav[28..32].copy_from_slice(&32_000u32.to_le_bytes());
+        assert_eq!(upload_seconds(&wav, WAV_HEADER_LEN as u64 + 96_000), 3.0);
+        assert_eq!(upload_seconds(b"ID3", 160_000), 10.0);
+    }
+
+    #[test]
+    fn values_split_across_words_are_redacted() {
+        let mut words = json!([
+            { "word": "call", "start": 0.0, "end": 0.3 },
+            { "word": "555", "start": 0.3, "end": 0.6 },
+            { "word": "123", "start": 0.6, "end": 0.9 },
+            { "word": "4567", "start": 0.9, "end": 1.4 },
+            { "word": "today", "start": 1.4, "end": 1.8 },
+        ]);
+        let Value::Array(items) = &mut words else {
+            unreachable!()
+        };
+        assert_eq!(redact_words(items).matches, 1);
+        let words: Vec<&str> = items.iter().map(|w| w["word"].as_str().unwrap()).collect();
+        assert_eq!(words, ["call", "***", "***", "****", "today"]);
+        assert_eq!(items[1]["start"], 0.3);
+    }
+}
@@ -0,0 +1,546 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Multipart, Path, State},
+    http::{header, HeaderMap, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Value};
+use tracing::instrument;
+
+use crate::image_policy::{ImagePolicy, ImageRejected};
+use crate::provider::{
+    ChatMessage, FilesBody, FilesRequest, Provider, ResponsesRequest, UpstreamError,
+};
+use crate::redact::{redact_text, RedactionStats};
+use crate::responses::{input_images, redact_request, redact_response};
+use crate::{handle_quota_error, redact_message, tenant, upstream_error_status, AppState};
+
+const FILES_ROUTE: &str = "/v1/files";
+const BATCHES_ROUTE: &str = "/v1/batches";
+
+/// Why a line of a batch input file was refused.
+#[derive(Debug, thiserror::Error)]
+pub enum LineError {
+    #[error("not a batch request line")]
+    Malformed,
+    #[error("endpoint {0} cannot be batched through the gateway")]
+    Endpoint(String),
+    #[error("model {0} is not available to this tenant")]
+    Forbidden(String),
+    #[error(transparent)]
+    Image(#[from] ImageRejected),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("line {line}: {error}")]
+pub struct LineRejected {
+    pub line: usize,
+    pub error: LineError,
+}
+
+impl LineRejected {
+    fn into_response(self, route: &'static str) -> Response {
+        let status = match &self.error {
+            LineError::Forbidden(_) => {
+                metrics::counter!("model_access_denied_total", "route" => route).increment(1);
+                StatusCode::FORBIDDEN
+            }
+            LineError::Image(err) => {
+                metrics::counter!("image_rejected_total", "route" => route, "reason" => err.reason())
+                    .increment(1);
+                StatusCode::BAD_REQUEST
+            }
+            _ => StatusCode::BAD_REQUEST,
+        };
+        error(status, self.to_string())
+    }
+}
+
+/// Uploads a batch input file. Every request line is checked against the
+/// tenant's model permissions and image policy and redacted like live
+/// traffic before the file reaches the upstream.
+#[instrument(skip_all, fields(tenant = %tenant(&headers)))]
+pub async fn upload_file_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Response {
+    metrics::counter!("requests_total", "route" => FILES_ROUTE).increment(1);
+    let Some(provider) = batch_upstream(&state) else {
+        return not_enabled(&state);
+    };
+
+    let mut purpose = None;
+    let mut file = None;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return e.into_response(),
+        };
+        match field.name() {
+            Some("purpose") => match field.text().await {
+                Ok(text) => purpose = Some(text),
+                Err(e) => return e.into_response(),
+            },
+            Some("file") => {
+                let filename = field.file_name().unwrap_or("batch.jsonl").to_string();
+                match field.bytes().await {
+                    Ok(bytes) => file = Some((filename, bytes)),
+                    Err(e) => return e.into_response(),
+                }
+            }
+            _ => {}
+        }
+    }
+    // Other purposes would reach the upstream without being scanned
+    if purpose.as_deref() != Some("batch") {
+        return error(
+            StatusCode::BAD_REQUEST,
+            "only files with purpose=batch can be uploaded",
+        );
+    }
+    let Some((filename, bytes)) = file else {
+        return error(StatusCode::BAD_REQUEST, "missing file");
+    };
+    let Ok(content) = std::str::from_utf8(&bytes) else {
+        return error(StatusCode::BAD_REQUEST, "batch files must be UTF-8 JSONL");
+    };
+
+    let mut redacted = String::with_capacity(content.len());
+    let mut stats = RedactionStats::default();
+    let mut lines = 0;
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match scan_line(&state, tenant(&headers), line) {
+            Ok((line, s)) => {
+                redacted.push_str(&line);
+                redacted.push('\n');
+                stats += s;
+                lines += 1;
+            }
+            Err(error) => {
+                return LineRejected { line: i + 1, error }.into_response(FILES_ROUTE);
+            }
+        }
+    }
+    metrics::counter!("redactions_total").increment(stats.matches as u64);
+    metrics::counter!("batch_file_lines_total").increment(lines);
+
+    let request = FilesRequest {
+        method: reqwest::Method::POST,
+        path: "files".to_string(),
+        query: None,
+        body: FilesBody::Upload {
+            purpose: "batch".to_string(),
+            filename,
+            content: redacted.into(),
+        },
+    };
+    match provider.files(request).await {
+        Ok(res) => match json_reply(res).await {
+            Ok((status, file)) => match state.owners.record(tenant(&headers), &file).await {
+                Ok(()) => (status, Json(file)).into_response(),
+                Err(e) => owners_unavailable(e),
+            },
+            Err(resp) => resp,
+        },
+        Err(e) => upstream_failure(e),
+    }
+}
+
+/// Downloads a file's content. Batch output is redacted line by line, the
+/// same way live responses are; input files are redacted again.
+pub async fn file_content_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    metrics::counter!("requests_total", "route" => "/v1/files/{id}/content").increment(1);
+    let Some(provider) = batch_upstream(&state) else {
+        return not_enabled(&state);
+    };
+    match state.owners.owns(tenant(&headers), &id).await {
+        Ok(true) => {}
+        Ok(false) => return not_found(&id),
+        Err(e) => return owners_unavailable(e),
+    }
+    let content = match fetch_content(&*provider, &id).await {
+        Ok(content) => content,
+        Err(e) => return upstream_failure(e),
+    };
+    let mut stats = RedactionStats::default();
+    let mut redacted = String::with_capacity(content.len());
+    for line in content.lines() {
+        let (line, s) = redact_result_line(line);
+        redacted.push_str(&line);
+        redacted.push('\n');
+        stats += s;
+    }
+    metrics::counter!("redactions_total").increment(stats.matches as u64);
+    ([(header::CONTENT_TYPE, "application/jsonl")], redacted).into_response()
+}
+
+/// Creates a batch, charging the tenant's quota one request per line of the
+/// input file. The file is read back so the charge (and the model
+/// permissions) match what will actually run.
+#[instrument(skip_all, fields(tenant = %tenant(&headers)))]
+pub async fn create_batch_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    metrics::counter!("requests_total", "route" => BATCHES_ROUTE).increment(1);
+    let Some(provider) = batch_upstream(&state) else {
+        return not_enabled(&state);
+    };
+    let Some(file_id) = body["input_file_id"].as_str() else {
+        return error(StatusCode::BAD_REQUEST, "missing input_file_id");
+    };
+    match state.owners.owns(tenant(&headers), file_id).await {
+        Ok(true) => {}
+        Ok(false) => return not_found(file_id),
+        Err(e) => return owners_unavailable(e),
+    }
+    let content = match fetch_content(&*provider, file_id).await {
+        Ok(content) => content,
+        Err(e) => return upstream_failure(e),
+    };
+
+    let mut lines = 0;
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Err(error) = scan_line(&state, tenant(&headers), line) {
+            return LineRejected { line: i + 1, error }.into_response(BATCHES_ROUTE);
+        }
+        lines += 1;
+    }
+
+    if let Some(quota) = state.quota.as_ref() {
+        if let Err(err) = quota.check_and_add(tenant(&headers), lines).await {
+            return handle_quota_error(err);
+        }
+    }
+    metrics::counter!("batch_lines_total").increment(lines as u64);
+
+    let request = FilesRequest {
+        method: reqwest::Method::POST,
+        path: "batches".to_string(),
+        query: None,
+        body: FilesBody::Json(body),
+    };
+    match provider.files(request).await {
+        Ok(res) => match json_reply(res).await {
+            Ok((status, batch)) => match state.owners.record(tenant(&headers), &batch).await {
+                Ok(()) => (status, Json(batch)).into_response(),
+                Err(e) => owners_unavailable(e),
+            },
+            Err(resp) => resp,
+        },
+        Err(e) => upstream_failure(e),
+    }
+}
+
+/// Listing, retrieving, deleting and cancelling carry no prompt text, so
+/// they are forwarded as they are. Lists only show the caller's own files
+/// and batches, and other tenants' ids are answered as unknown.
+pub async fn passthrough_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    route: MatchedPath,
+    id: Option<Path<String>>,
+    method: Method,
+    uri: Uri,
+) -> Response {
+    let route = route.as_str().replace(":id", "{id}");
+    metrics::counter!("requests_total", "route" => route).increment(1);
+    let Some(provider) = batch_upstream(&state) else {
+        return not_enabled(&state);
+    };
+    let tenant = tenant(&headers);
+    if let Some(Path(id)) = &id {
+        match state.owners.owns(tenant, id).await {
+            Ok(true) => {}
+            Ok(false) => return not_found(id),
+            Err(e) => return owners_unavailable(e),
+        }
+    }
+    let request = FilesRequest {
+        method,
+        path: uri.path().trim_start_matches("/v1/").to_string(),
+        query: uri.query().map(str::to_string),
+        body: FilesBody::Empty,
+    };
+    let res = match provider.files(request).await {
+        Ok(res) => res,
+        Err(e) => return upstream_failure(e),
+    };
+    let (status, mut body) = match json_reply(res).await {
+        Ok(reply) => reply,
+        Err(resp) => return resp,
+    };
+    let recorded = match &id {
+        None => state.owners.retain_owned(tenant, &mut body).await,
+        Some(Path(id)) if body["deleted"] == true => state.owners.forget(id).await,
+        Some(_) => state.owners.record(tenant, &body).await,
+    };
+    match recorded {
+        Ok(()) => (status, Json(body)).into_response(),
+        Err(e) => owners_unavailable(e),
+    }
+}
+
+fn batch_upstream(state: &AppState) -> Option<Arc<dyn Provider>> {
+    state.providers.get(&state.cfg.batch_upstream)
+}
+
+fn not_enabled(state: &AppState) -> Response {
+    error(
+        StatusCode::NOT_FOUND,
+        format!(
+            "batch upstream {} is not configured",
+            state.cfg.batch_upstream
+        ),
+    )
+}
+
+fn not_found(id: &str) -> Response {
+    error(
+        StatusCode::NOT_FOUND,
+        format!("no such file or batch: {id}"),
+    )
+}
+
+async fn fetch_content(provider: &dyn Provider, id: &str) -> anyhow::Result<String> {
+    let res = provider
+        .files(FilesRequest {
+            method: reqwest::Method::GET,
+            path: format!("files/{id}/content"),
+            query: None,
+            body: FilesBody::Empty,
+        })
+        .await?;
+    Ok(res.text().await?)
+}
+
+/// Checks one request line of an input file and returns it redacted.
+fn scan_line(
+    state: &AppState,
+    tenant: &str,
+    line: &str,
+) -> Result<(String, RedactionStats), LineError> {
+    let mut value: Value = serde_json::from_str(line).map_err(|_| LineError::Malformed)?;
+    let url = value["url"]
+        .as_str()
+        .ok_or(LineError::Malformed)?
+        .to_string();
+    let body = value
+        .get_mut("body")
+        .filter(|b| b.is_object())
+        .ok_or(LineError::Malformed)?;
+    let model = body["model"].as_str().ok_or(LineError::Malformed)?;
+    if !state.permissions.allows(tenant, model) {
+        return Err(LineError::Forbidden(model.to_string()));
+    }
+    let stats = redact_body(&url, body, Some(&state.images))?;
+    Ok((value.to_string(), stats))
+}
+
+/// Redacts a request body by endpoint, checking its images when a policy is given.
+fn redact_body(
+    url: &str,
+    body: &mut Value,
+    images: Option<&ImagePolicy>,
+) -> Result<RedactionStats, LineError> {
+    let mut stats = RedactionStats::default();
+    match url {
+        "/v1/chat/completions" => {
+            let messages = body.get_mut("messages").ok_or(LineError::Malformed)?;
+            let mut parsed: Vec<ChatMessage> =
+                serde_json::from_value(messages.take()).map_err(|_| LineError::Malformed)?;
+            if let Some(images) = images {
+                images.check_messages(&parsed)?;
+            }
+            for message in &mut parsed {
+                stats += redact_message(message);
+            }
+            *messages = serde_json::to_value(parsed).map_err(|_| LineError::Malformed)?;
+        }
+        "/v1/responses" => {
+            let mut req: ResponsesRequest =
+                serde_json::from_value(body.take()).map_err(|_| LineError::Malformed)?;
+            if let Some(images) = images {
+                input_images(&req).try_for_each(|url| images.check(url))?;
+            }
+            stats += redact_request(&mut req);
+            *body = serde_json::to_value(req).map_err(|_| LineError::Malformed)?;
+        }
+        "/v1/embeddings" => redact_strings(body.get_mut("input"), &mut stats),
+        "/v1/completions" => redact_strings(body.get_mut("prompt"), &mut stats),
+        other => return Err(LineError::Endpoint(other.to_string())),
+    }
+    Ok(stats)
+}
+
+/// Redacts one line of a downloaded file: a batch result or error, a request
+/// line, or as plain text when it is neither.
+fn redact_result_line(line: &str) -> (String, RedactionStats) {
+    let Ok(mut value) = serde_json::from_str::<Value>(line) else {
+        return redact_text(line);
+    };
+    let mut stats = RedactionStats::default();
+    if let Some(body) = value.pointer_mut("/response/body") {
+        if let Some(Value::Array(choices)) = body.get_mut("choices") {
+            for choice in choices {
+                if let Some(message) = choice.get_mut("message") {
+                    if let Ok(mut parsed) = serde_json::from_value::<ChatMessage>(message.clone()) {
+                        stats += redact_message(&mut parsed);
+                        *message = serde_json::to_value(parsed).unwrap_or_default();
+                    }
+                }
+                redact_strings(choice.get_mut("text"), &mut stats);
+            }
+        }
+        if body.get("output").is_some() {
+            redact_response(body);
+        }
+    }
+    redact_strings(
+        value.pointer_mut("/response/body/error/message"),
+        &mut stats,
+    );
+    redact_strings(value.pointer_mut("/error/message"), &mut stats);
+    if let Some(url) = value["url"].as_str().map(str::to_string) {
+        if let Some(body) = value.get_mut("body") {
+            if let Ok(s) = redact_body(&url, body, None) {
+                stats += s;
+            }
+        }
+    }
+    (value.to_string(), stats)
+}
+
+/// Redacts a string or each string of an array; token ids pass through.
+fn redact_strings(value: Option<&mut Value>, stats: &mut RedactionStats) {
+    match value {
+        Some(Value::String(text)) => {
+            let (redacted, s) = redact_text(text);
+            *text = redacted;
+            *stats += s;
+        }
+        Some(Value::Array(items)) => {
+            for item in items {
+                redact_strings(Some(item), stats);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Parses a successful JSON reply so its ids can be checked or recorded;
+/// anything else is relayed as it came.
+async fn json_reply(res: reqwest::Response) -> Result<(StatusCode, Value), Response> {
+    let status = res.status();
+    let content_type = res.headers().get(header::CONTENT_TYPE).cloned();
+    let bytes = match res.bytes().await {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(upstream_failure(e.into())),
+    };
+    if status.is_success() {
+        if let Ok(body) = serde_json::from_slice::<Value>(&bytes) {
+            return Ok((status, body));
+        }
+    }
+    let mut resp = (status, Body::from(bytes)).into_response();
+    if let Some(value) = content_type {
+        resp.headers_mut().insert(header::CONTENT_TYPE, value);
+    }
+    Err(resp)
+}
+
+fn owners_unavailable(err: anyhow::Error) -> Response {
+    tracing::error!("ownership store error: {err:?}");
+    error(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "ownership store unavailable",
+    )
+}
+
+/// Client errors from the upstream (an unknown file id, a bad batch) are the
+/// caller's to see; anything else is a gateway failure.
+fn upstream_failure(err: anyhow::Error) -> Response {
+    if let Some(e) = err.downcast_ref::<UpstreamError>() {
+        if e.status.is_client_error() {
+            return (
+                e.status,
+                [(header::CONTENT_TYPE, "application/json")],
+                e.body.clone(),
+            )
+                .into_response();
+        }
+    }
+    error(upstream_error_status(&err), err.to_string())
+}
+
+fn error(status: StatusCode, message: impl Into<String>) -> Response {
+    (status, Json(json!({ "error": message.into() }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMAIL: &str = "write to jane.doe@example.com";
+
+    #[test]
+    fn request_lines_are_redacted_by_endpoint() {
+        let mut chat = json!({
+            "model": "gpt-4o-mini",
+            "messages": [{ "role": "user", "content": EMAIL }],
+            "max_tokens": 5,
+        });
+        let stats = redact_body("/v1/chat/completions", &mut chat, None).unwrap();
+        assert_eq!(stats.matches, 1);
+        assert!(!chat.to_string().contains("jane.doe@"));
+        assert_eq!(chat["max_tokens"], 5);
+
+        let mut embeddings = json!({ "model": "text-embedding-3-small", "input": [EMAIL, EMAIL] });
+        let stats = redact_body("/v1/embeddings", &mut embeddings, None).unwrap();
+        assert_eq!(stats.matches, 2);
+
+        assert!(matches!(
+            redact_body("/v1/moderations", &mut json!({}), None),
+            Err(LineError::Endpoint(_))
+        ));
+    }
+
+    #[test]
+    fn result_lines_are_redacted() {
+        let result = json!({
+            "id": "batch_req_1",
+            "custom_id": "r1",
+            "response": {
+                "status_code": 200,
+                "body": { "choices": [{ "index": 0, "message": { "role": "assistant", "content": EMAIL } }] },
+            },
+            "error": null,
+        });
+        let (line, stats) = redact_result_line(&result.to_string());
+        assert_eq!(stats.matches, 1);
+        assert!(!line.contains("jane.doe@"));
+        assert!(line.contains("\"custom_id\":\"r1\""));
+
+        let failed = json!({ "custom_id": "r2", "response": null, "error": { "message": EMAIL } });
+        assert!(!redact_result_line(&failed.to_string())
+            .0
+            .contains("jane.doe@"));
+        assert!(!redact_result_line(EMAIL).0.contains("jane.doe@"));
+    }
+}
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
 
 use governor::middleware::NoOpMiddleware;
 use http::Request;
@@ -11,9 +11,62 @@ use tower_governor::{
 #[derive(Debug, Clone, Deserialize)]
 pub struct AppConfig {
     pub listen_addr: String,
-    pub openai_api_key: String,
+
+    // upstream providers
+    #[serde(default)]
+    pub openai_api_keys: KeySource,
     #[serde(default)]
     pub openai_base_url: Option<String>,
+    #[serde(default)]
+    pub anthropic_api_keys: KeySource,
+    #[serde(default)]
+    pub anthropic_base_url: Option<String>,
+    #[serde(default)]
+    pub anthropic_version: Option<String>,
+    #[serde(default)]
+    pub gemini_api_keys: KeySource,
+    #[serde(default)]
+    pub gemini_base_url: Option<String>,
+    #[serde(default)]
+    pub ollama_base_url: Option<String>,
+    #[serde(default)]
+    pub llamacpp_base_url: Option<String>,
+    #[serde(default)]
+    pub openai_upstreams: Vec<OpenAIUpstreamConfig>,
+    #[serde(default)]
+    pub upstream_pools: Vec<PoolConfig>,
+    /// Pool name to hedge delay: streams that have not produced a first chunk
+    /// after this long are raced against a second member.
+    #[serde(default)]
+    pub pool_hedge_ms: HashMap<String, u64>,
+    #[serde(default)]
+    pub azure_openai_endpoint: Option<String>,
+    #[serde(default)]
+    pub azure_openai_api_keys: KeySource,
+    #[serde(default)]
+    pub azure_openai_api_version: Option<String>,
+    #[serde(default)]
+    pub azure_openai_deployments: HashMap<String, AzureDeployment>,
+    #[serde(default)]
+    pub default_provider: Option<String>,
+    #[serde(default)]
+    pub model_routes: Vec<RouteConfig>,
+    /// How long a fallback tier gets to produce its first chunk (or, without
+    /// streaming, its whole response) before the next tier is tried.
+    #[serde(default)]
+    pub first_byte_timeout_ms: Option<u64>,
+
+    /// How long a key is benched after a 429 without `Retry-After`; a 401
+    /// benches it ten times as long.
+    #[serde(default = "default_key_quarantine_secs")]
+    pub key_quarantine_secs: u64,
+    #[serde(default = "default_key_file_poll_secs")]
+    pub key_file_poll_secs: u64,
+    /// Interval of the background health probes; unset disables them.
+    #[serde(default)]
+    pub health_check_secs: Option<u64>,
+    #[serde(default)]
+    pub health_probes: HashMap<String, HealthProbe>,
 
     // rate limit
     #[serde(default = "default_rps")]
@@ -30,12 +83,60 @@ pub struct AppConfig {
     pub quota_window_secs: u64,
     #[serde(default)]
     pub tenant_quotas: HashMap<String, u32>,
+    /// Images each tenant may generate per quota window.
+    #[serde(default = "default_image_quota")]
+    pub default_image_quota: u32,
+    #[serde(default)]
+    pub tenant_image_quotas: HashMap<String, u32>,
+    /// Largest base64 image accepted from an image generation upstream.
+    #[serde(default = "default_generated_image_max_bytes")]
+    pub generated_image_max_bytes: usize,
+
+    /// Which chat request fields beyond the ones the gateway models are
+    /// forwarded upstream, from `CHAT_FIELDS_ALLOW` / `CHAT_FIELDS_DENY`.
+    #[serde(default)]
+    pub chat_fields: FieldFilter,
+    #[serde(default)]
+    pub images: ImageConfig,
+    /// Upstream that holds uploaded batch files and runs batches.
+    #[serde(default = "default_batch_upstream")]
+    pub batch_upstream: String,
+    /// Largest batch input file accepted by `POST /v1/files`.
+    #[serde(default = "default_batch_max_file_bytes")]
+    pub batch_max_file_bytes: usize,
+    /// How long the owner of a file or batch is remembered after it was last seen.
+    #[serde(default = "default_batch_owner_ttl_secs")]
+    pub batch_owner_ttl_secs: u64,
+    /// Realtime sessions each tenant may hold open at once.
+    #[serde(default = "default_realtime_max_sessions")]
+    pub realtime_max_sessions: u32,
+    /// How long a realtime session may last before the gateway closes it.
+    #[serde(default = "default_realtime_max_session_secs")]
+    pub realtime_max_session_secs: u64,
+    /// Largest upload accepted by `POST /v1/audio/transcriptions`.
+    #[serde(default = "default_audio_max_bytes")]
+    pub audio_max_bytes: usize,
+    /// Seconds of audio charged as one request against tenant quotas.
+    #[serde(default = "default_audio_quota_secs")]
+    pub audio_quota_secs: u64,
+
+    // model access
+    /// Model name globs each tenant may use, from `TENANT_MODELS`.
+    #[serde(default)]
+    pub tenant_models: HashMap<String, Vec<String>>,
+    /// Globs for tenants without an entry; everything by default.
+    #[serde(default = "default_models")]
+    pub default_models: Vec<String>,
 
     // circuit-breaker lite
     #[serde(default)]
     pub timeout_secs: Option<u64>,
     #[serde(default)]
     pub max_concurrency: Option<usize>,
+    #[serde(default)]
+    pub circuit_breaker: BreakerConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
 
     // telemetry
     #[serde(default)]
@@ -44,6 +145,210 @@ pub struct AppConfig {
     pub service_name: String,
 }
 
+/// API keys for one provider: `{PREFIX}_API_KEY`, the comma separated
+/// `{PREFIX}_API_KEYS`, and `{PREFIX}_API_KEY_FILE` with one key per line
+/// (reloaded while running).
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct KeySource {
+    #[serde(default)]
+    pub keys: Vec<String>,
+    #[serde(default)]
+    pub file: Option<String>,
+}
+
+impl KeySource {
+    fn from_env(prefix: &str) -> Self {
+        let mut keys: Vec<String> = std::env::var(format!("{prefix}_API_KEY"))
+            .ok()
+            .into_iter()
+            .collect();
+        if let Ok(list) = std::env::var(format!("{prefix}_API_KEYS")) {
+            keys.extend(list.split(',').map(|k| k.trim().to_string()));
+        }
+        keys.retain(|k| !k.is_empty());
+        let file = std::env::var(format!("{prefix}_API_KEY_FILE"))
+            .ok()
+            .filter(|f| !f.is_empty());
+        Self { keys, file }
+    }
+
+    pub fn is_configured(&self) -> bool {
+        !self.keys.is_empty() || self.file.is_some()
+    }
+}
+
+/// Allow/deny list over request field names. Without an allow list every
+/// field not denied passes.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FieldFilter {
+    #[serde(default)]
+    pub allow: Option<Vec<String>>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+impl FieldFilter {
+    pub fn permits(&self, field: &str) -> bool {
+        self.allow
+            .as_ref()
+            .is_none_or(|allow| allow.iter().any(|f| f == field))
+            && !self.deny.iter().any(|f| f == field)
+    }
+}
+
+/// What image parts of chat messages may reference.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ImageConfig {
+    /// Reject inline `data:` images outright (`IMAGE_DATA_URLS=block`).
+    #[serde(default)]
+    pub block_data_urls: bool,
+    /// Largest decoded inline image; unset means no limit.
+    #[serde(default)]
+    pub max_data_url_bytes: Option<usize>,
+    /// Host globs remote image URLs must match; unset allows any host.
+    #[serde(default)]
+    pub url_hosts: Option<Vec<String>>,
+}
+
+/// What a background health probe sends, from `HEALTH_PROBES`. Upstreams
+/// without an entry get their provider's cheapest read-only call.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthProbe {
+    /// `GET` this path on the upstream, authenticated like any request.
+    Path(String),
+    /// A one-token chat completion against this model.
+    Chat(String),
+}
+
+/// Target of an inbound model name on Azure OpenAI.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AzureDeployment {
+    pub deployment: String,
+    #[serde(default)]
+    pub api_version: Option<String>,
+}
+
+/// One `MODEL_ROUTES` entry: requests whose model matches `pattern` go to the
+/// first of `targets`, falling back to the next one when an upstream fails.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct RouteConfig {
+    pub pattern: String,
+    pub targets: Vec<RouteTarget>,
+}
+
+/// A provider, optionally under a different upstream `model` name.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct RouteTarget {
+    pub provider: String,
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// An additional named OpenAI-compatible endpoint from `OPENAI_UPSTREAMS`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OpenAIUpstreamConfig {
+    pub name: String,
+    pub base_url: String,
+    /// Falls back to the `OPENAI_API_KEY(S)` pool when unset.
+    #[serde(default)]
+    pub api_key: Option<String>,
+}
+
+/// A named group of upstreams from `UPSTREAM_POOLS`, routable like a provider.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PoolConfig {
+    pub name: String,
+    pub strategy: PoolStrategy,
+    pub members: Vec<PoolMemberConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PoolMemberConfig {
+    pub upstream: String,
+    #[serde(default = "default_pool_weight")]
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolStrategy {
+    WeightedRoundRobin,
+    LeastOutstanding,
+    PowerOfTwoChoices,
+}
+
+impl FromStr for PoolStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wrr" | "weighted_round_robin" => Ok(Self::WeightedRoundRobin),
+            "least" | "least_outstanding" => Ok(Self::LeastOutstanding),
+            "p2c" | "power_of_two_choices" => Ok(Self::PowerOfTwoChoices),
+            other => anyhow::bail!("unknown pool strategy {other}"),
+        }
+    }
+}
+
+/// Per-upstream circuit breaker thresholds.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct BreakerConfig {
+    /// Failure ratio over the window that opens the breaker.
+    pub failure_rate: f64,
+    /// Requests needed in the window before the ratio is trusted.
+    pub min_requests: u32,
+    pub window_secs: u64,
+    /// How long the breaker stays open before letting a probe through.
+    pub open_secs: u64,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_rate: 0.5,
+            min_requests: 20,
+            window_secs: 30,
+            open_secs: 30,
+        }
+    }
+}
+
+/// Retries of transient upstream failures, per upstream.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct RetryConfig {
+    /// Extra attempts per request; 0 disables retries.
+    pub max_retries: u32,
+    pub base_delay_ms: u64,
+    /// Cap on a single backoff; a longer `Retry-After` is not waited out.
+    pub max_delay_ms: u64,
+    /// Retries allowed as a fraction of requests, on top of one per second.
+    pub budget_ratio: f64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            base_delay_ms: 100,
+            max_delay_ms: 2000,
+            budget_ratio: 0.2,
+        }
+    }
+}
+
+fn default_pool_weight() -> u32 {
+    1
+}
+
+fn default_key_quarantine_secs() -> u64 {
+    60
+}
+
+fn default_key_file_poll_secs() -> u64 {
+    30
+}
+
 fn default_rps() -> u32 {
     5
 }
@@ -59,6 +364,46 @@ fn default_quota_window_secs() -> u64 {
     60
 }
 
+fn default_image_quota() -> u32 {
+    20
+}
+
+fn default_generated_image_max_bytes() -> usize {
+    8 * 1024 * 1024
+}
+
+fn default_batch_upstream() -> String {
+    "openai".to_string()
+}
+
+fn default_batch_max_file_bytes() -> usize {
+    100 * 1024 * 1024
+}
+
+fn default_batch_owner_ttl_secs() -> u64 {
+    30 * 24 * 60 * 60
+}
+
+fn default_realtime_max_sessions() -> u32 {
+    2
+}
+
+fn default_realtime_max_session_secs() -> u64 {
+    30 * 60
+}
+
+fn default_audio_max_bytes() -> usize {
+    25 * 1024 * 1024
+}
+
+fn default_audio_quota_secs() -> u64 {
+    60
+}
+
+fn default_models() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
 fn default_service_name() -> String {
     "secure-llm-gateway".to_string()
 }
@@ -67,9 +412,70 @@ impl AppConfig {
     pub fn from_env() -> anyhow::Result<Self> {
         let listen_addr =
             std::env::var("LISTEN_ADDR").unwrap_or_else(|_| "0.0.0.0:8080".to_string());
-        let openai_api_key = std::env::var("OPENAI_API_KEY")
-            .map_err(|_| anyhow::anyhow!("OPENAI_API_KEY not set"))?;
+        let openai_api_keys = KeySource::from_env("OPENAI");
         let openai_base_url = std::env::var("OPENAI_BASE_URL").ok();
+        let anthropic_api_keys = KeySource::from_env("ANTHROPIC");
+        let anthropic_base_url = std::env::var("ANTHROPIC_BASE_URL").ok();
+        let anthropic_version = std::env::var("ANTHROPIC_VERSION").ok();
+        let gemini_api_keys = KeySource::from_env("GEMINI");
+        let gemini_base_url = std::env::var("GEMINI_BASE_URL").ok();
+        let ollama_base_url = std::env::var("OLLAMA_BASE_URL").ok();
+        let llamacpp_base_url = std::env::var("LLAMACPP_BASE_URL").ok();
+        let openai_upstreams = std::env::var("OPENAI_UPSTREAMS")
+            .ok()
+            .map(parse_openai_upstreams)
+            .transpose()?
+            .unwrap_or_default();
+        let upstream_pools = std::env::var("UPSTREAM_POOLS")
+            .ok()
+            .map(parse_upstream_pools)
+            .transpose()?
+            .unwrap_or_default();
+        let pool_hedge_ms = std::env::var("POOL_HEDGE_MS")
+            .ok()
+            .map(parse_pool_hedges)
+            .unwrap_or_default();
+        if let Some(name) = pool_hedge_ms
+            .keys()
+            .find(|name| !upstream_pools.iter().any(|p| &p.name == *name))
+        {
+            anyhow::bail!("POOL_HEDGE_MS references unknown pool {name}");
+        }
+        let azure_openai_endpoint = std::env::var("AZURE_OPENAI_ENDPOINT").ok();
+        let azure_openai_api_keys = KeySource::from_env("AZURE_OPENAI");
+        let azure_openai_api_version = std::env::var("AZURE_OPENAI_API_VERSION").ok();
+        let azure_openai_deployments = std::env::var("AZURE_OPENAI_DEPLOYMENTS")
+            .ok()
+            .map(parse_azure_deployments)
+            .transpose()?
+            .unwrap_or_default();
+        let default_provider = std::env::var("DEFAULT_PROVIDER").ok();
+        let model_routes = std::env::var("MODEL_ROUTES")
+            .ok()
+            .map(parse_model_routes)
+            .transpose()?
+            .unwrap_or_default();
+        let first_byte_timeout_ms = std::env::var("FIRST_BYTE_TIMEOUT_MS")
+            .ok()
+            .and_then(|s| s.parse().ok());
+        let key_quarantine_secs = std::env::var("KEY_QUARANTINE_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(default_key_quarantine_secs);
+        let key_file_poll_secs = std::env::var("KEY_FILE_POLL_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or_else(default_key_file_poll_secs);
+        let health_check_secs = std::env::var("HEALTH_CHECK_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|v| *v > 0);
+        let health_probes = std::env::var("HEALTH_PROBES")
+            .ok()
+            .map(parse_health_probes)
+            .transpose()?
+            .unwrap_or_default();
         let rps = std::env::var("RPS")
             .ok()
             .and_then(|s| s.parse().ok())
@@ -91,27 +497,173 @@ impl AppConfig {
             .ok()
             .map(parse_tenant_quotas)
             .unwrap_or_default();
+        let default_image_quota = std::env::var("DEFAULT_IMAGE_QUOTA")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(default_image_quota);
+        let tenant_image_quotas = std::env::var("TENANT_IMAGE_QUOTAS")
+            .ok()
+            .map(parse_tenant_quotas)
+            .unwrap_or_default();
+        let generated_image_max_bytes = std::env::var("GENERATED_IMAGE_MAX_BYTES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(default_generated_image_max_bytes);
+        let field_list = |name: &str| -> Option<Vec<String>> {
+            std::env::var(name).ok().map(|s| {
+                s.split(',')
+                    .map(str::trim)
+                    .filter(|f| !f.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+        };
+        let chat_fields = FieldFilter {
+            allow: field_list("CHAT_FIELDS_ALLOW"),
+            deny: field_list("CHAT_FIELDS_DENY").unwrap_or_default(),
+        };
+        let images = ImageConfig {
+            block_data_urls: match std::env::var("IMAGE_DATA_URLS").as_deref() {
+                Err(_) | Ok("allow") => false,
+                Ok("block") => true,
+                Ok(other) => anyhow::bail!("IMAGE_DATA_URLS must be allow or block, got {other}"),
+            },
+            max_data_url_bytes: std::env::var("IMAGE_MAX_BYTES")
+                .ok()
+                .and_then(|s| s.parse().ok()),
+            url_hosts: field_list("IMAGE_URL_HOSTS"),
+        };
+        let batch_upstream =
+            std::env::var("BATCH_UPSTREAM").unwrap_or_else(|_| default_batch_upstream());
+        let batch_max_file_bytes = std::env::var("BATCH_MAX_FILE_BYTES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(default_batch_max_file_bytes);
+        let batch_owner_ttl_secs = std::env::var("BATCH_OWNER_TTL_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(default_batch_owner_ttl_secs);
+        let realtime_max_sessions = std::env::var("REALTIME_MAX_SESSIONS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(default_realtime_max_sessions);
+        let realtime_max_session_secs = std::env::var("REALTIME_MAX_SESSION_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or_else(default_realtime_max_session_secs);
+        let audio_max_bytes = std::env::var("AUDIO_MAX_BYTES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(default_audio_max_bytes);
+        let audio_quota_secs = std::env::var("AUDIO_QUOTA_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or_else(default_audio_quota_secs);
+        let tenant_models = std::env::var("TENANT_MODELS")
+            .ok()
+            .map(parse_tenant_models)
+            .unwrap_or_default();
+        let default_models = std::env::var("DEFAULT_MODELS")
+            .ok()
+            .map(|s| parse_model_list(&s))
+            .unwrap_or_else(default_models);
         let timeout_secs = std::env::var("TIMEOUT_SECS")
             .ok()
             .and_then(|s| s.parse().ok());
         let max_concurrency = std::env::var("MAX_CONCURRENCY")
             .ok()
             .and_then(|s| s.parse().ok());
+        let circuit_breaker = {
+            let d = BreakerConfig::default();
+            let var = |name: &str| std::env::var(name).ok();
+            BreakerConfig {
+                failure_rate: var("CB_FAILURE_RATE")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(d.failure_rate),
+                min_requests: var("CB_MIN_REQUESTS")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(d.min_requests),
+                window_secs: var("CB_WINDOW_SECS")
+                    .and_then(|s| s.parse().ok())
+                    .filter(|v| *v > 0)
+                    .unwrap_or(d.window_secs),
+                open_secs: var("CB_OPEN_SECS")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(d.open_secs),
+            }
+        };
+        let retry = {
+            let d = RetryConfig::default();
+            let var = |name: &str| std::env::var(name).ok();
+            RetryConfig {
+                max_retries: var("RETRY_MAX")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(d.max_retries),
+                base_delay_ms: var("RETRY_BASE_DELAY_MS")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(d.base_delay_ms),
+                max_delay_ms: var("RETRY_MAX_DELAY_MS")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(d.max_delay_ms),
+                budget_ratio: var("RETRY_BUDGET_RATIO")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(d.budget_ratio),
+            }
+        };
         let otlp_endpoint = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok();
         let service_name =
             std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| default_service_name());
         Ok(Self {
             listen_addr,
-            openai_api_key,
+            openai_api_keys,
             openai_base_url,
+            anthropic_api_keys,
+            anthropic_base_url,
+            anthropic_version,
+            gemini_api_keys,
+            gemini_base_url,
+            ollama_base_url,
+            llamacpp_base_url,
+            openai_upstreams,
+            upstream_pools,
+            pool_hedge_ms,
+            azure_openai_endpoint,
+            azure_openai_api_keys,
+            azure_openai_api_version,
+            azure_openai_deployments,
+            default_provider,
+            model_routes,
+            first_byte_timeout_ms,
+            key_quarantine_secs,
+            key_file_poll_secs,
+            health_check_secs,
+            health_probes,
             rps,
             burst,
             redis_url,
             default_quota,
             quota_window_secs,
             tenant_quotas,
+            default_image_quota,
+            tenant_image_quotas,
+            generated_image_max_bytes,
+            chat_fields,
+            images,
+            batch_upstream,
+            batch_max_file_bytes,
+            batch_owner_ttl_secs,
+            realtime_max_sessions,
+            realtime_max_session_secs,
+            audio_max_bytes,
+            audio_quota_secs,
+            tenant_models,
+            default_models,
             timeout_secs,
             max_concurrency,
+            circuit_breaker,
+            retry,
             otlp_endpoint,
             service_name,
         })
@@ -154,6 +706,180 @@ fn parse_tenant_quotas(s: String) -> HashMap<String, u32> {
         .collect()
 }
 
+// tenant=glob|glob, comma separated
+fn parse_tenant_models(s: String) -> HashMap<String, Vec<String>> {
+    s.split(',')
+        .filter_map(|pair| {
+            let (tenant, models) = pair.split_once('=')?;
+            let tenant = tenant.trim();
+            if tenant.is_empty() {
+                return None;
+            }
+            Some((tenant.to_string(), parse_model_list(models)))
+        })
+        .collect()
+}
+
+fn parse_model_list(s: &str) -> Vec<String> {
+    s.split('|')
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// upstream=/path or upstream=chat:model, comma separated
+fn parse_health_probes(s: String) -> anyhow::Result<HashMap<String, HealthProbe>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let invalid = || anyhow::anyhow!("invalid HEALTH_PROBES entry: {entry}");
+            let (upstream, probe) = entry.split_once('=').ok_or_else(invalid)?;
+            let probe = probe.trim();
+            let probe = match probe.strip_prefix("chat:") {
+                Some(model) if !model.is_empty() => HealthProbe::Chat(model.to_string()),
+                None if probe.starts_with('/') => HealthProbe::Path(probe.to_string()),
+                _ => return Err(invalid()),
+            };
+            Ok((upstream.trim().to_string(), probe))
+        })
+        .collect()
+}
+
+// pool=milliseconds, comma separated
+fn parse_pool_hedges(s: String) -> HashMap<String, u64> {
+    s.split(',')
+        .filter_map(|pair| {
+            let (pool, ms) = pair.split_once('=')?;
+            let pool = pool.trim();
+            if pool.is_empty() {
+                return None;
+            }
+            Some((pool.to_string(), ms.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+// model=deployment[@api-version], comma separated
+fn parse_azure_deployments(s: String) -> anyhow::Result<HashMap<String, AzureDeployment>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let invalid = || anyhow::anyhow!("invalid AZURE_OPENAI_DEPLOYMENTS entry: {entry}");
+            let (model, target) = entry.split_once('=').ok_or_else(invalid)?;
+            let (deployment, api_version) = match target.split_once('@') {
+                Some((d, v)) if !v.trim().is_empty() => (d.trim(), Some(v.trim().to_string())),
+                Some(_) => return Err(invalid()),
+                None => (target.trim(), None),
+            };
+            if model.trim().is_empty() || deployment.is_empty() {
+                return Err(invalid());
+            }
+            Ok((
+                model.trim().to_string(),
+                AzureDeployment {
+                    deployment: deployment.to_string(),
+                    api_version,
+                },
+            ))
+        })
+        .collect()
+}
+
+// name=base_url[|api_key], comma separated
+fn parse_openai_upstreams(s: String) -> anyhow::Result<Vec<OpenAIUpstreamConfig>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (name, target) = entry
+                .split_once('=')
+                // Don't echo the entry back, it may contain an API key
+                .ok_or_else(|| {
+                    anyhow::anyhow!("invalid OPENAI_UPSTREAMS entry, expected name=url[|key]")
+                })?;
+            let (base_url, api_key) = match target.split_once('|') {
+                Some((u, k)) => (u.trim(), Some(k.trim().to_string())),
+                None => (target.trim(), None),
+            };
+            Ok(OpenAIUpstreamConfig {
+                name: name.trim().to_string(),
+                base_url: base_url.to_string(),
+                api_key,
+            })
+        })
+        .collect()
+}
+
+// name=strategy:member[*weight]+member..., comma separated
+fn parse_upstream_pools(s: String) -> anyhow::Result<Vec<PoolConfig>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let invalid = || anyhow::anyhow!("invalid UPSTREAM_POOLS entry: {entry}");
+            let (name, spec) = entry.split_once('=').ok_or_else(invalid)?;
+            let (strategy, members) = spec.split_once(':').ok_or_else(invalid)?;
+            let members = members
+                .split('+')
+                .map(|m| {
+                    let (upstream, weight) = match m.split_once('*') {
+                        Some((u, w)) => (u.trim(), w.trim().parse().map_err(|_| invalid())?),
+                        None => (m.trim(), default_pool_weight()),
+                    };
+                    Ok(PoolMemberConfig {
+                        upstream: upstream.to_string(),
+                        weight,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            Ok(PoolConfig {
+                name: name.trim().to_string(),
+                strategy: strategy.trim().parse()?,
+                members,
+            })
+        })
+        .collect()
+}
+
+// pattern=provider[@model][>provider[@model]...], comma separated; order is
+// significant for globs, and `>` lists fallback tiers in the order tried
+fn parse_model_routes(s: String) -> anyhow::Result<Vec<RouteConfig>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let invalid = || anyhow::anyhow!("invalid MODEL_ROUTES entry: {entry}");
+            let (pattern, chain) = entry.split_once('=').ok_or_else(invalid)?;
+            let targets = chain
+                .split('>')
+                .map(|target| {
+                    let (provider, model) = match target.split_once('@') {
+                        Some((p, m)) => (p.trim(), Some(m.trim().to_string())),
+                        None => (target.trim(), None),
+                    };
+                    if provider.is_empty() {
+                        return Err(invalid());
+                    }
+                    Ok(RouteTarget {
+                        provider: provider.to_string(),
+                        model,
+                    })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            if pattern.trim().is_empty() {
+                return Err(invalid());
+            }
+            Ok(RouteConfig {
+                pattern: pattern.trim().to_string(),
+                targets,
+            })
+        })
+        .collect()
+}
+
 #[derive(Clone, Copy)]
 pub struct ApiKeyExtractor;
 
@@ -178,3 +904,26 @@ impl KeyExtractor for ApiKeyExtractor {
         Ok(format!("ip:{ip}:{path}"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn azure_deployments_are_parsed() {
+        let deployments =
+            parse_azure_deployments("gpt-4o=prod-4o@2024-10-21, gpt-4o-mini = mini".to_string())
+                .unwrap();
+        assert_eq!(deployments["gpt-4o"].deployment, "prod-4o");
+        assert_eq!(
+            deployments["gpt-4o"].api_version.as_deref(),
+            Some("2024-10-21")
+        );
+        assert_eq!(deployments["gpt-4o-mini"].deployment, "mini");
+        assert_eq!(deployments["gpt-4o-mini"].api_version, None);
+
+        for bad in ["gpt-4o", "=prod", "gpt-4o=", "gpt-4o=prod@"] {
+            assert!(parse_azure_deployments(bad.to_string()).is_err(), "{bad}");
+        }
+    }
+}
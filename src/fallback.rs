@@ -0,0 +1,137 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use crate::provider::{
+    breaker::CircuitOpen, is_transient, Provider, ProviderRegistry, Unsupported,
+};
+use crate::routing::{RouteMatch, Tier};
+
+/// A result together with the tier that produced it.
+pub struct Served<T> {
+    pub value: T,
+    pub upstream: Tier,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{provider} produced no output within {timeout:?}")]
+pub struct FirstByteTimeout {
+    provider: String,
+    timeout: Duration,
+}
+
+/// Runs `call` against each tier of `route` in order until one succeeds.
+///
+/// Only failures another upstream could plausibly avoid move on to the next
+/// tier (see [`should_fall_back`]); anything else, and the last tier's error,
+/// is returned as is.
+///
+/// `first_byte_timeout` is meant for calls that resolve on a stream's first
+/// chunk. Calls that wait for a whole response pass `None`: a slow but
+/// healthy upstream would otherwise be cut off, and its work done twice.
+pub async fn dispatch<T, F, Fut>(
+    providers: &ProviderRegistry,
+    route: &RouteMatch,
+    first_byte_timeout: Option<Duration>,
+    mut call: F,
+) -> anyhow::Result<Served<T>>
+where
+    F: FnMut(Arc<dyn Provider>, String) -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let last = route.tiers.len() - 1;
+    for (i, tier) in route.tiers.iter().enumerate() {
+        // Routes are validated against the registry at startup
+        let provider = providers
+            .get(&tier.provider)
+            .expect("routed provider is registered");
+        // Known-bad tiers are skipped up front, but the last one is always tried
+        if i < last && !provider.is_available() {
+            tracing::debug!(model_route = %route.route, upstream = %tier.provider, "skipping unavailable tier");
+            continue;
+        }
+        let attempt = call(provider, tier.model.clone());
+        let result = match first_byte_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, attempt)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(FirstByteTimeout {
+                        provider: tier.provider.clone(),
+                        timeout,
+                    }
+                    .into())
+                }),
+            None => attempt.await,
+        };
+        match result {
+            Ok(value) => {
+                if i > 0 {
+                    metrics::counter!(
+                        "fallback_total",
+                        "model_route" => route.route.clone(),
+                        "upstream" => tier.provider.clone(),
+                        "tier" => i.to_string()
+                    )
+                    .increment(1);
+                }
+                return Ok(Served {
+                    value,
+                    upstream: tier.clone(),
+                });
+            }
+            Err(e) if i < last && should_fall_back(&e) => {
+                tracing::warn!(
+                    model_route = %route.route,
+                    upstream = %tier.provider,
+                    error = %e,
+                    "upstream failed, trying next tier"
+                );
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!("routes always have at least one tier")
+}
+
+/// Transient upstream failures, stalls, open circuits and operations the
+/// upstream lacks are worth retrying elsewhere; other errors (bad requests,
+/// auth, parse failures) would fail the same way.
+fn should_fall_back(err: &anyhow::Error) -> bool {
+    is_transient(err)
+        || err.is::<FirstByteTimeout>()
+        || err.is::<CircuitOpen>()
+        || err.is::<Unsupported>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::UpstreamError;
+
+    fn status(code: u16) -> anyhow::Error {
+        anyhow::Error::from(UpstreamError {
+            provider: "openai",
+            status: reqwest::StatusCode::from_u16(code).unwrap(),
+            body: String::new(),
+            retry_after: None,
+        })
+        .context("upstream call failed")
+    }
+
+    #[test]
+    fn falls_back_only_on_transient_failures() {
+        assert!(should_fall_back(&status(503)));
+        assert!(should_fall_back(&status(429)));
+        assert!(!should_fall_back(&status(400)));
+        assert!(!should_fall_back(&status(401)));
+        assert!(should_fall_back(&anyhow::Error::from(FirstByteTimeout {
+            provider: "openai".to_string(),
+            timeout: Duration::from_millis(10),
+        })));
+        assert!(should_fall_back(&anyhow::Error::from(Unsupported {
+            provider: "anthropic",
+            operation: "embeddings",
+        })));
+        assert!(!should_fall_back(&anyhow::anyhow!(
+            "failed to parse response"
+        )));
+    }
+}
@@ -0,0 +1,120 @@
+use reqwest::Url;
+
+use crate::config::{AppConfig, ImageConfig};
+use crate::provider::{ChatMessage, DataUrl, MessageContent};
+use crate::routing::glob_matches;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImageRejected {
+    #[error("inline images are not allowed")]
+    DataUrlBlocked,
+    #[error("inline image of {size} bytes exceeds the {limit} byte limit")]
+    TooLarge { size: usize, limit: usize },
+    #[error("images from {0} are not allowed")]
+    HostNotAllowed(String),
+    #[error("invalid image url")]
+    InvalidUrl,
+}
+
+impl ImageRejected {
+    /// Label for `image_rejected_total`.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::DataUrlBlocked => "data_url_blocked",
+            Self::TooLarge { .. } => "too_large",
+            Self::HostNotAllowed(_) => "host_not_allowed",
+            Self::InvalidUrl => "invalid_url",
+        }
+    }
+}
+
+/// Checks the images a request references against `IMAGE_DATA_URLS`,
+/// `IMAGE_MAX_BYTES` and `IMAGE_URL_HOSTS`.
+#[derive(Debug, Clone)]
+pub struct ImagePolicy {
+    cfg: ImageConfig,
+}
+
+impl ImagePolicy {
+    pub fn new(cfg: &AppConfig) -> Self {
+        Self {
+            cfg: cfg.images.clone(),
+        }
+    }
+
+    pub fn check_messages(&self, messages: &[ChatMessage]) -> Result<(), ImageRejected> {
+        messages
+            .iter()
+            .flat_map(|m| m.content.iter().flat_map(MessageContent::images))
+            .try_for_each(|url| self.check(url))
+    }
+
+    pub fn check(&self, url: &str) -> Result<(), ImageRejected> {
+        if url.starts_with("data:") {
+            if self.cfg.block_data_urls {
+                return Err(ImageRejected::DataUrlBlocked);
+            }
+            let data = DataUrl::parse(url).ok_or(ImageRejected::InvalidUrl)?;
+            return match self.cfg.max_data_url_bytes {
+                Some(limit) if data.decoded_len() > limit => Err(ImageRejected::TooLarge {
+                    size: data.decoded_len(),
+                    limit,
+                }),
+                _ => Ok(()),
+            };
+        }
+        let url = Url::parse(url).map_err(|_| ImageRejected::InvalidUrl)?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(ImageRejected::InvalidUrl);
+        }
+        let host = url.host_str().ok_or(ImageRejected::InvalidUrl)?;
+        match &self.cfg.url_hosts {
+            Some(hosts) if !hosts.iter().any(|h| glob_matches(h, host)) => {
+                Err(ImageRejected::HostNotAllowed(host.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enforces_data_url_and_host_rules() {
+        let policy = ImagePolicy {
+            cfg: ImageConfig {
+                block_data_urls: false,
+                max_data_url_bytes: Some(6),
+                url_hosts: Some(vec!["*.example.com".to_string()]),
+            },
+        };
+        assert!(policy.check("data:image/png;base64,AAAAAAAA").is_ok());
+        assert!(matches!(
+            policy.check("data:image/png;base64,AAAAAAAAAAAA"),
+            Err(ImageRejected::TooLarge { size: 9, limit: 6 })
+        ));
+        assert!(policy.check("https://cdn.example.com/a.png").is_ok());
+        assert!(matches!(
+            policy.check("https://evil.test/a.png"),
+            Err(ImageRejected::HostNotAllowed(_))
+        ));
+        assert!(matches!(
+            policy.check("file:///etc/passwd"),
+            Err(ImageRejected::InvalidUrl)
+        ));
+
+        let blocking = ImagePolicy {
+            cfg: ImageConfig {
+                block_data_urls: true,
+                ..Default::default()
+            },
+        };
+        assert!(matches!(
+            blocking.check("data:image/png;base64,AAAA"),
+            Err(ImageRejected::DataUrlBlocked)
+        ));
+        assert!(blocking.check("https://anywhere.test/a.png").is_ok());
+    }
+}
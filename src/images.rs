@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::fallback::{self, Served};
+use crate::provider::{ImageGenerationRequest, ImagesResponse};
+use crate::redact::redact_text;
+use crate::telemetry::track_http_metrics;
+use crate::{
+    handle_quota_error, model_forbidden, record_upstream, tenant, upstream_error_status,
+    with_upstream_header, AppState,
+};
+
+const ROUTE: &str = "/v1/images/generations";
+
+/// Generates images from a redacted prompt. Besides the request itself, each
+/// image asked for is charged against the tenant's `DEFAULT_IMAGE_QUOTA` /
+/// `TENANT_IMAGE_QUOTAS`.
+#[instrument(skip(state, headers, req), fields(tenant = %tenant(&headers),
+                                               model = %req.model,
+                                               route = tracing::field::Empty,
+                                               provider = tracing::field::Empty,
+                                               upstream_model = tracing::field::Empty))]
+pub async fn generations_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(mut req): Json<ImageGenerationRequest>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+    metrics::counter!("requests_total", "route" => ROUTE).increment(1);
+
+    if !state.permissions.allows(tenant(&headers), &req.model) {
+        return model_forbidden(ROUTE, &req.model);
+    }
+    if let Some(quota) = state.quota.as_ref() {
+        let count = req.n.unwrap_or(1);
+        if let Err(err) = quota.check_and_add_images(tenant(&headers), count).await {
+            return handle_quota_error(err);
+        }
+        if let Err(err) = quota.check_and_increment(tenant(&headers)).await {
+            if let Err(refund) = quota.refund_images(tenant(&headers), count).await {
+                tracing::warn!(error = %refund, "failed to refund image quota");
+            }
+            return handle_quota_error(err);
+        }
+    }
+
+    let (prompt, stats) = redact_text(&req.prompt);
+    req.prompt = prompt;
+    metrics::counter!("redactions_total").increment(stats.matches as u64);
+
+    let route = state.router.resolve(&req.model);
+    tracing::Span::current().record("route", route.route.as_str());
+    let model = req.model.clone();
+    let served = fallback::dispatch(&state.providers, &route, None, |provider, model| {
+        let req = ImageGenerationRequest {
+            model,
+            ..req.clone()
+        };
+        async move { provider.image_generation(req).await }
+    })
+    .await;
+    let Served {
+        value: mut response,
+        upstream,
+    } = match served {
+        Ok(served) => served,
+        Err(e) => {
+            track_http_metrics(ROUTE, &model, &route.route, route.primary(), &request_id);
+            refund(&state, tenant(&headers), req.n.unwrap_or(1)).await;
+            return error(upstream_error_status(&e), e.to_string());
+        }
+    };
+    record_upstream(&upstream);
+    track_http_metrics(ROUTE, &model, &route.route, &upstream, &request_id);
+
+    if let Some(size) = oversized(&response, state.cfg.generated_image_max_bytes) {
+        metrics::counter!("generated_images_rejected_total", "upstream" => upstream.provider.clone())
+            .increment(1);
+        refund(&state, tenant(&headers), req.n.unwrap_or(1)).await;
+        return error(
+            StatusCode::BAD_GATEWAY,
+            format!(
+                "generated image of {size} bytes exceeds the {} byte limit",
+                state.cfg.generated_image_max_bytes
+            ),
+        );
+    }
+
+    let size = req.size.unwrap_or_else(|| "default".to_string());
+    metrics::counter!(
+        "images_generated_total",
+        "model_route" => route.route.clone(),
+        "upstream" => upstream.provider.clone(),
+        "size" => size
+    )
+    .increment(response.data.len() as u64);
+    for image in &mut response.data {
+        if let Some(data) = &image.b64_json {
+            metrics::histogram!("generated_image_bytes").record(data.decoded_len() as f64);
+        }
+        if let Some(revised) = image.revised_prompt.as_mut() {
+            let (redacted, stats) = redact_text(revised);
+            *revised = redacted;
+            metrics::counter!("redactions_total").increment(stats.matches as u64);
+        }
+    }
+    with_upstream_header(Json(response).into_response(), &upstream)
+}
+
+/// Decoded size of the first inline image over `limit`, if any.
+/// Gives back the request and the `count` images charged for a generation
+/// that produced nothing for the tenant.
+async fn refund(state: &AppState, tenant: &str, count: u32) {
+    let Some(quota) = state.quota.as_ref() else {
+        return;
+    };
+    if let Err(err) = quota.refund(tenant, 1).await {
+        tracing::warn!(error = %err, "failed to refund request quota");
+    }
+    if let Err(err) = quota.refund_images(tenant, count).await {
+        tracing::warn!(error = %err, "failed to refund image quota");
+    }
+}
+
+fn oversized(response: &ImagesResponse, limit: usize) -> Option<usize> {
+    response
+        .data
+        .iter()
+        .filter_map(|image| image.b64_json.as_ref())
+        .map(|data| data.decoded_len())
+        .find(|size| *size > limit)
+}
+
+fn error(status: StatusCode, message: impl Into<String>) -> Response {
+    (status, Json(json!({ "error": message.into() }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_images_are_capped_and_kept_out_of_debug_output() {
+        let response: ImagesResponse = serde_json::from_value(json!({
+            "created": 1,
+            "data": [
+                { "url": "https://example.com/a.png" },
+                { "b64_json": "A".repeat(4000), "revised_prompt": "a cat" },
+            ],
+        }))
+        .unwrap();
+        assert_eq!(oversized(&response, 4096), None);
+        assert_eq!(oversized(&response, 1000), Some(3000));
+
+        let debug = format!("{response:?}");
+        assert!(!debug.contains("AAAA"), "{debug}");
+        assert!(debug.contains("<4000 bytes of base64>"));
+        let round_trip = serde_json::to_value(&response).unwrap();
+        assert_eq!(round_trip["data"][0]["url"], "https://example.com/a.png");
+        assert_eq!(
+            round_trip["data"][1]["b64_json"].as_str().unwrap().len(),
+            4000
+        );
+    }
+}
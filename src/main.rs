@@ -1,8 +1,8 @@
 use async_stream::stream;
 use axum::{
     error_handling::HandleErrorLayer,
-    extract::State,
-    http::{HeaderMap, StatusCode},
+    extract::{DefaultBodyLimit, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
     response::{IntoResponse, Response, Sse},
     routing::{get, post},
     BoxError, Json, Router,
@@ -10,7 +10,14 @@ use axum::{
 use futures::StreamExt;
 use serde::Deserialize;
 use serde_json::json;
-use std::{convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    net::SocketAddr,
+    ops::Range,
+    sync::Arc,
+    time::Duration,
+};
 use tokio::net::TcpListener;
 use tokio::signal;
 use tower::{
@@ -22,32 +29,50 @@ use tracing::info;
 use tracing::instrument;
 use uuid::Uuid;
 
+mod audio;
+mod batches;
 mod config;
+mod fallback;
+mod image_policy;
+mod images;
+mod messages;
+mod models;
+mod ownership;
+mod permissions;
 mod provider;
 mod quota;
+mod realtime;
 mod redact;
+mod responses;
+mod routing;
 mod telemetry;
 
 use crate::config::AppConfig;
-use crate::provider::openai::{
-    ChatCompletionRequest, ChatMessage as OpenAIChatMessage, OpenAIChatCompletionResponse,
-    OpenAIProvider, OpenAIStreamChunk,
+use crate::fallback::Served;
+use crate::image_policy::{ImagePolicy, ImageRejected};
+use crate::ownership::Owners;
+use crate::permissions::ModelPermissions;
+use crate::provider::{
+    ChatCompletionRequest, ChatMessage, ChatStream, EmbeddingInput, EmbeddingsRequest,
+    FunctionCall, MessageContent, OpenAIChatCompletionResponse, OpenAIChoice, OpenAIDelta,
+    OpenAIStreamChunk, ProviderRegistry, ToolCall, Unsupported,
 };
 use crate::quota::{QuotaError, QuotaManager};
-use crate::redact::{redact_text, RedactionStats};
+use crate::realtime::SessionLimiter;
+use crate::redact::{redact_text, redaction_spans, FragmentRedactor, RedactionStats, TextRedactor};
+use crate::routing::{ModelRouter, RouteMatch, Tier};
 use crate::telemetry::{init_metrics, init_tracing, track_http_metrics};
 
 #[derive(Clone)]
 struct AppState {
     cfg: Arc<AppConfig>,
-    openai: Arc<OpenAIProvider>,
+    providers: ProviderRegistry,
+    router: ModelRouter,
+    permissions: ModelPermissions,
+    images: ImagePolicy,
     quota: Option<QuotaManager>,
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct ChatMessage {
-    role: String,
-    content: String,
+    realtime: SessionLimiter,
+    owners: Owners,
 }
 
 #[derive(Debug, Deserialize)]
@@ -62,7 +87,10 @@ struct ChatRequest {
     max_tokens: Option<u32>,
     #[serde(default)]
     stream: Option<bool>,
-    // pass-through for extra fields, ignored for MVP
+    /// Everything else (`tools`, `response_format`, `seed`, ...), forwarded
+    /// as sent subject to `CHAT_FIELDS_ALLOW` / `CHAT_FIELDS_DENY`.
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
 }
 
 #[tokio::main]
@@ -73,11 +101,14 @@ async fn main() -> anyhow::Result<()> {
     let handle = init_metrics()?;
 
     let quota = QuotaManager::maybe_new(&cfg).await?;
+    let providers = ProviderRegistry::from_config(&cfg)?;
     let state = AppState {
-        openai: Arc::new(OpenAIProvider::new(
-            cfg.openai_api_key.clone(),
-            cfg.openai_base_url.clone(),
-        )?),
+        router: ModelRouter::new(&cfg.model_routes, &providers)?,
+        permissions: ModelPermissions::new(&cfg),
+        images: ImagePolicy::new(&cfg),
+        realtime: SessionLimiter::new(&cfg),
+        owners: Owners::new(&cfg).await?,
+        providers,
         cfg: Arc::new(cfg),
         quota,
     };
@@ -109,8 +140,39 @@ async fn main() -> anyhow::Result<()> {
     // Define routes and handlers
     let app = Router::new()
         .route("/healthz", get(|| async { "ok" }))
+        .route("/readyz", get(readyz_handler))
         .route("/metrics", get(move || async move { handle.render() }))
+        .route("/v1/models", get(models::list_models_handler))
+        .route("/v1/models/*model", get(models::retrieve_model_handler))
         .route("/v1/chat/completions", post(chat_handler))
+        .route("/v1/embeddings", post(embeddings_handler))
+        .route("/v1/responses", post(responses::responses_handler))
+        .route("/v1/messages", post(messages::messages_handler))
+        .route(
+            "/v1/audio/transcriptions",
+            post(audio::transcriptions_handler)
+                .layer(DefaultBodyLimit::max(state.cfg.audio_max_bytes)),
+        )
+        .route("/v1/audio/speech", post(audio::speech_handler))
+        .route("/v1/images/generations", post(images::generations_handler))
+        .route("/v1/realtime", get(realtime::realtime_handler))
+        .route(
+            "/v1/files",
+            post(batches::upload_file_handler)
+                .layer(DefaultBodyLimit::max(state.cfg.batch_max_file_bytes))
+                .get(batches::passthrough_handler),
+        )
+        .route(
+            "/v1/files/:id",
+            get(batches::passthrough_handler).delete(batches::passthrough_handler),
+        )
+        .route("/v1/files/:id/content", get(batches::file_content_handler))
+        .route(
+            "/v1/batches",
+            post(batches::create_batch_handler).get(batches::passthrough_handler),
+        )
+        .route("/v1/batches/:id", get(batches::passthrough_handler))
+        .route("/v1/batches/:id/cancel", post(batches::passthrough_handler))
         .with_state(state.clone())
         .layer(middleware);
 
@@ -147,183 +209,757 @@ async fn shutdown_signal() {
     info!("signal received, starting graceful shutdown");
 }
 
-#[instrument(skip(state, headers, req), fields(tenant = %headers.get("x-api-key").and_then(|v| v.to_str().ok()).unwrap_or("anonymous"),
-                                               model  = %req.model))]
+/// Ready while at least one upstream passes its health probe (always, when
+/// health checks are disabled), with the status of each probed upstream.
+async fn readyz_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let health = state.providers.health();
+    let upstreams: serde_json::Map<String, serde_json::Value> = health
+        .iter()
+        .map(|h| {
+            let status = if h.is_healthy() {
+                "healthy"
+            } else {
+                "unhealthy"
+            };
+            (h.upstream().to_string(), json!(status))
+        })
+        .collect();
+    let ready = health.is_empty() || health.iter().any(|h| h.is_healthy());
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(json!({
+            "status": if ready { "ready" } else { "unavailable" },
+            "upstreams": upstreams,
+        })),
+    )
+}
+
+#[instrument(skip(state, headers, req), fields(tenant = %tenant(&headers),
+                                               model  = %req.model,
+                                               route = tracing::field::Empty,
+                                               provider = tracing::field::Empty,
+                                               upstream_model = tracing::field::Empty))]
 async fn chat_handler(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
-    Json(mut req): Json<ChatRequest>,
+    Json(req): Json<ChatRequest>,
 ) -> impl IntoResponse {
     let request_id = Uuid::new_v4().to_string();
     metrics::counter!("requests_total", "route" => "/v1/chat/completions").increment(1);
 
-    let tenant = headers
-        .get("x-api-key")
-        .and_then(|v| v.to_str().ok())
-        .filter(|v| !v.is_empty())
-        .unwrap_or("anonymous");
-
-    if let Some(quota) = state.quota.as_ref() {
-        if let Err(err) = quota.check_and_increment(tenant).await {
-            return handle_quota_error(err);
-        }
-    }
-
-    // Redact request messages
-    let mut redaction_stats = RedactionStats::default();
-    for m in &mut req.messages {
-        let (redacted, stats) = redact_text(&m.content);
-        m.content = redacted;
-        redaction_stats += stats;
-    }
-    metrics::counter!("redactions_total").increment(redaction_stats.matches as u64);
-
-    let provider = state.openai.clone();
-    let model = req.model.clone();
-    let stream_requested = req.stream.unwrap_or(true);
-    let openai_req = ChatCompletionRequest {
-        model: req.model.clone(),
-        messages: req
-            .messages
-            .iter()
-            .cloned()
-            .map(|m| OpenAIChatMessage {
-                role: m.role,
-                content: m.content,
-            })
-            .collect(),
+    let mut openai_req = ChatCompletionRequest {
+        model: req.model,
+        messages: req.messages,
         temperature: req.temperature,
         top_p: req.top_p,
         max_tokens: req.max_tokens,
-        stream: Some(stream_requested),
+        stream: Some(req.stream.unwrap_or(true)),
+        extra: req.extra,
     };
+    if let Err(refused) =
+        admit_chat(&state, &headers, "/v1/chat/completions", &mut openai_req).await
+    {
+        return refused;
+    }
+
+    let route = state.router.resolve(&openai_req.model);
+    tracing::Span::current().record("route", route.route.as_str());
+    let model = std::mem::replace(&mut openai_req.model, route.primary().model.clone());
 
-    if !stream_requested {
-        let mut response = match provider.chat_completion(openai_req).await {
-            Ok(resp) => resp,
+    if openai_req.stream == Some(false) {
+        let Served {
+            value: mut response,
+            upstream,
+        } = match complete_chat(&state, &route, &openai_req).await {
+            Ok(served) => served,
             Err(e) => {
-                track_http_metrics("/v1/chat/completions", &model, &request_id);
+                track_http_metrics(
+                    "/v1/chat/completions",
+                    &model,
+                    &route.route,
+                    route.primary(),
+                    &request_id,
+                );
                 return (
-                    StatusCode::BAD_GATEWAY,
+                    upstream_error_status(&e),
                     Json(json!({ "error": e.to_string() })),
                 )
                     .into_response();
             }
         };
 
+        record_upstream(&upstream);
         redact_completion(&mut response);
-        track_http_metrics("/v1/chat/completions", &model, &request_id);
-        return Json(response).into_response();
+        track_http_metrics(
+            "/v1/chat/completions",
+            &model,
+            &route.route,
+            &upstream,
+            &request_id,
+        );
+        return with_upstream_header(Json(response).into_response(), &upstream);
     }
 
-    // Stream from OpenAI and re-redact delta chunks before forwarding
-    let mut upstream = match provider.chat_stream(openai_req).await {
-        Ok(s) => s,
+    let Served {
+        value: (first_chunk, mut upstream_stream),
+        upstream,
+    } = match open_chat_stream(&state, &route, &openai_req).await {
+        Ok(served) => served,
         Err(e) => {
-            let err = format!(r#"{{"error":"{}"}}"#, e);
-            let stream = stream! {
-                yield Ok::<_, Infallible>(axum::response::sse::Event::default().data(err));
+            track_http_metrics(
+                "/v1/chat/completions",
+                &model,
+                &route.route,
+                route.primary(),
+                &request_id,
+            );
+            return (
+                upstream_error_status(&e),
+                Json(json!({ "error": e.to_string() })),
+            )
+                .into_response();
+        }
+    };
+    record_upstream(&upstream);
+
+    // Stream from the provider and re-redact delta chunks before forwarding
+    let stream = stream! {
+        let Some(chunk) = first_chunk else {
+            yield Ok::<_, Infallible>(axum::response::sse::Event::default().data("[DONE]"));
+            return;
+        };
+        let mut redaction = StreamRedaction::default();
+        yield Ok(axum::response::sse::Event::default().data(chunk_data(&redaction.process(chunk))));
+
+        while let Some(item) = upstream_stream.next().await {
+            match item {
+                Ok(chunk) => {
+                    yield Ok(axum::response::sse::Event::default().data(chunk_data(&redaction.process(chunk))));
+                }
+                Err(e) => {
+                    let err = format!(r#"{{"error":"stream error: {}"}}"#, e);
+                    yield Ok(axum::response::sse::Event::default().data(err));
+                    return;
+                }
+            }
+        }
+        if let Some(rest) = redaction.finish() {
+            yield Ok(axum::response::sse::Event::default().data(chunk_data(&rest)));
+        }
+        yield Ok(axum::response::sse::Event::default().data("[DONE]"));
+    };
+
+    let sse = Sse::new(stream)
+        .keep_alive(axum::response::sse::KeepAlive::new().interval(Duration::from_secs(10)));
+    track_http_metrics(
+        "/v1/chat/completions",
+        &model,
+        &route.route,
+        &upstream,
+        &request_id,
+    );
+    with_upstream_header(sse.into_response(), &upstream)
+}
+
+/// Everything a chat-shaped request goes through before routing: model
+/// permissions, image policy and quota, then redaction of its messages and
+/// the `CHAT_FIELDS_*` filter. On refusal, returns the response to send.
+async fn admit_chat(
+    state: &AppState,
+    headers: &HeaderMap,
+    route: &'static str,
+    req: &mut ChatCompletionRequest,
+) -> Result<(), Response> {
+    if !state.permissions.allows(tenant(headers), &req.model) {
+        return Err(model_forbidden(route, &req.model));
+    }
+    if let Err(err) = state.images.check_messages(&req.messages) {
+        return Err(image_rejected(route, err));
+    }
+    if let Some(quota) = state.quota.as_ref() {
+        quota
+            .check_and_increment(tenant(headers))
+            .await
+            .map_err(handle_quota_error)?;
+    }
+
+    let mut redaction_stats = RedactionStats::default();
+    for m in &mut req.messages {
+        redaction_stats += redact_message(m);
+    }
+    metrics::counter!("redactions_total").increment(redaction_stats.matches as u64);
+
+    req.extra.retain(|field, _| {
+        let permitted = state.cfg.chat_fields.permits(field);
+        if !permitted {
+            tracing::debug!(field, "dropping chat request field");
+            metrics::counter!("chat_fields_dropped_total").increment(1);
+        }
+        permitted
+    });
+    Ok(())
+}
+
+/// Serves `req` as a single response from the first tier of `route` that can.
+async fn complete_chat(
+    state: &AppState,
+    route: &RouteMatch,
+    req: &ChatCompletionRequest,
+) -> anyhow::Result<Served<OpenAIChatCompletionResponse>> {
+    fallback::dispatch(&state.providers, route, None, |provider, model| {
+        let req = ChatCompletionRequest {
+            model,
+            ..req.clone()
+        };
+        async move { provider.chat_completion(req).await }
+    })
+    .await
+}
+
+/// Opens a chat stream on the first tier of `route` that can, returning its
+/// first chunk and the rest of the stream.
+///
+/// Each tier must produce its first chunk before we commit to it, so an
+/// upstream that accepts the request and then stalls can still fail over.
+async fn open_chat_stream(
+    state: &AppState,
+    route: &RouteMatch,
+    req: &ChatCompletionRequest,
+) -> anyhow::Result<Served<(Option<OpenAIStreamChunk>, ChatStream)>> {
+    fallback::dispatch(
+        &state.providers,
+        route,
+        state.cfg.first_byte_timeout_ms.map(Duration::from_millis),
+        |provider, model| {
+            let req = ChatCompletionRequest {
+                model,
+                ..req.clone()
             };
-            let sse = Sse::new(stream).keep_alive(
-                axum::response::sse::KeepAlive::new().interval(Duration::from_secs(10)),
+            async move {
+                // Backends without native streaming are served as a single-chunk stream
+                let mut stream = if provider.capabilities().streaming {
+                    provider.chat_stream(req).await?
+                } else {
+                    let chunk = provider.chat_completion(req).await?.into_stream_chunk();
+                    Box::pin(futures::stream::once(async move { Ok(chunk) })) as ChatStream
+                };
+                let first = stream.next().await.transpose()?;
+                Ok((first, stream))
+            }
+        },
+    )
+    .await
+}
+
+#[instrument(skip(state, headers, req), fields(tenant = %tenant(&headers),
+                                               model = %req.model,
+                                               route = tracing::field::Empty,
+                                               provider = tracing::field::Empty,
+                                               upstream_model = tracing::field::Empty,
+                                               prompt_tokens = tracing::field::Empty))]
+async fn embeddings_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(mut req): Json<EmbeddingsRequest>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+    metrics::counter!("requests_total", "route" => "/v1/embeddings").increment(1);
+
+    if !state.permissions.allows(tenant(&headers), &req.model) {
+        return model_forbidden("/v1/embeddings", &req.model);
+    }
+    if let Some(quota) = state.quota.as_ref() {
+        if let Err(err) = quota.check_and_increment(tenant(&headers)).await {
+            return handle_quota_error(err);
+        }
+    }
+
+    // Token-id inputs carry no text we could scan, so only text is redacted
+    let mut redaction_stats = RedactionStats::default();
+    let mut redact = |text: &mut String| {
+        let (redacted, stats) = redact_text(text);
+        *text = redacted;
+        redaction_stats += stats;
+    };
+    match &mut req.input {
+        EmbeddingInput::Text(text) => redact(text),
+        EmbeddingInput::Texts(texts) => texts.iter_mut().for_each(redact),
+        EmbeddingInput::Tokens(_) | EmbeddingInput::TokenBatches(_) => {}
+    }
+    metrics::counter!("redactions_total").increment(redaction_stats.matches as u64);
+
+    let route = state.router.resolve(&req.model);
+    tracing::Span::current().record("route", route.route.as_str());
+    let model = req.model.clone();
+    let served = fallback::dispatch(&state.providers, &route, None, |provider, model| {
+        let req = EmbeddingsRequest {
+            model,
+            ..req.clone()
+        };
+        async move { provider.embeddings(req).await }
+    })
+    .await;
+    let Served {
+        value: response,
+        upstream,
+    } = match served {
+        Ok(served) => served,
+        Err(e) => {
+            track_http_metrics(
+                "/v1/embeddings",
+                &model,
+                &route.route,
+                route.primary(),
+                &request_id,
             );
-            track_http_metrics("/v1/chat/completions", &model, &request_id);
-            return sse.into_response();
+            return (
+                upstream_error_status(&e),
+                Json(json!({ "error": e.to_string() })),
+            )
+                .into_response();
         }
     };
 
-    let first_item = upstream.next().await;
+    record_upstream(&upstream);
+    if let Some(usage) = response.usage {
+        tracing::Span::current().record("prompt_tokens", usage.prompt_tokens);
+        metrics::counter!(
+            "embedding_tokens_total",
+            "model_route" => route.route.clone(),
+            "upstream" => upstream.provider.clone()
+        )
+        .increment(usage.prompt_tokens as u64);
+    }
+    track_http_metrics(
+        "/v1/embeddings",
+        &model,
+        &route.route,
+        &upstream,
+        &request_id,
+    );
+    with_upstream_header(Json(response).into_response(), &upstream)
+}
+
+/// Status for a request no tier could serve. A route none of whose upstreams
+/// offer the operation is the caller's mistake rather than an upstream failure.
+fn upstream_error_status(err: &anyhow::Error) -> StatusCode {
+    if err.is::<Unsupported>() {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::BAD_GATEWAY
+    }
+}
+
+/// Rejects a model outside the tenant's `TENANT_MODELS`, before any quota is charged.
+fn model_forbidden(route: &'static str, model: &str) -> Response {
+    metrics::counter!("model_access_denied_total", "route" => route).increment(1);
+    (
+        StatusCode::FORBIDDEN,
+        Json(json!({ "error": format!("model {model} is not available to this tenant") })),
+    )
+        .into_response()
+}
 
-    let mut buffered_events = Vec::new();
-    let mut stream_done = false;
+/// Rejects a request whose image parts break `IMAGE_*` policy, before any
+/// quota is charged.
+fn image_rejected(route: &'static str, err: ImageRejected) -> Response {
+    metrics::counter!("image_rejected_total", "route" => route, "reason" => err.reason())
+        .increment(1);
+    (
+        StatusCode::BAD_REQUEST,
+        Json(json!({ "error": err.to_string() })),
+    )
+        .into_response()
+}
 
-    match first_item {
-        Some(Ok(line)) => {
-            if let Some((data, should_break)) = process_stream_line(&line) {
-                buffered_events.push(axum::response::sse::Event::default().data(data));
-                if should_break {
-                    stream_done = true;
+/// The caller's API key, which quotas and model permissions are tracked against.
+fn tenant(headers: &HeaderMap) -> &str {
+    headers
+        .get("x-api-key")
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .unwrap_or("anonymous")
+}
+
+fn record_upstream(upstream: &Tier) {
+    let span = tracing::Span::current();
+    span.record("provider", upstream.provider.as_str());
+    span.record("upstream_model", upstream.model.as_str());
+}
+
+/// Tells the caller which upstream actually served the request.
+fn with_upstream_header(mut resp: Response, upstream: &Tier) -> Response {
+    if let Ok(value) = HeaderValue::from_str(&upstream.provider) {
+        resp.headers_mut().insert("x-gateway-upstream", value);
+    }
+    resp
+}
+
+/// Message and delta fields outside the modelled ones that carry model text:
+/// refusals, and reasoning from DeepSeek-style and OpenRouter-style upstreams.
+const EXTRA_TEXT_FIELDS: &[&str] = &["refusal", "reasoning_content", "reasoning"];
+
+/// Delta fields streamed as running text: `content`, `refusal`, reasoning,
+/// and `audio` for its transcript.
+const STREAM_TEXT_FIELDS: &[&str] = &[
+    "content",
+    "refusal",
+    "reasoning_content",
+    "reasoning",
+    "audio",
+];
+
+/// Redaction state for one chat stream, covering every choice of `n > 1`
+/// streams. Text fields and tool call arguments are held back per choice
+/// until a value split across deltas can be redacted whole; held-back text
+/// goes out with the chunk that starts a tool call or ends its choice.
+#[derive(Default)]
+struct StreamRedaction {
+    // Keyed by choice index, then delta field
+    texts: HashMap<(u32, &'static str), HeldText>,
+    // Keyed by choice index, then tool call index
+    tool_args: HashMap<(u32, u32), FragmentRedactor>,
+    // Envelope of the last chunk, reused if held-back text outlives the stream
+    envelope: Option<OpenAIStreamChunk>,
+}
+
+impl StreamRedaction {
+    fn process(&mut self, mut chunk: OpenAIStreamChunk) -> OpenAIStreamChunk {
+        for choice in &mut chunk.choices {
+            let index = choice.index.unwrap_or(0);
+            // Text said before a tool call must not come out after it
+            let calls_tool = choice
+                .delta
+                .as_ref()
+                .and_then(|d| d.tool_calls.as_ref())
+                .is_some_and(|calls| !calls.is_empty());
+            for field in STREAM_TEXT_FIELDS {
+                let held = self.texts.entry((index, field)).or_default();
+                if let Some(text) = choice.delta.as_mut().and_then(|d| delta_text(d, field)) {
+                    *text = held.text.push(text).0;
+                }
+                if calls_tool {
+                    let (rest, _) = held.text.finish();
+                    if !rest.is_empty() {
+                        let delta = choice.delta.get_or_insert_with(Default::default);
+                        append_delta_text(delta, field, &rest);
+                    }
                 }
+                held.swap_logprobs(&mut choice.extra, field, calls_tool);
+            }
+            for call in choice
+                .delta
+                .iter_mut()
+                .flat_map(|d| d.tool_calls.iter_mut().flatten())
+            {
+                let Some(args) = call.function.as_mut().and_then(|f| f.arguments.as_mut()) else {
+                    continue;
+                };
+                let redactor = self
+                    .tool_args
+                    .entry((index, call.index.unwrap_or(0)))
+                    .or_default();
+                let (red, _) = redactor.push(args);
+                *args = red;
+            }
+            if choice.finish_reason.is_some() {
+                self.flush(index, choice);
             }
         }
-        Some(Err(e)) => {
-            let err = format!(r#"{{"error":"stream error: {}"}}"#, e);
-            buffered_events.push(axum::response::sse::Event::default().data(err));
-            stream_done = true;
-        }
-        None => {
-            stream_done = true;
+        self.envelope = Some(OpenAIStreamChunk {
+            id: chunk.id.clone(),
+            object: chunk.object.clone(),
+            created: chunk.created,
+            model: chunk.model.clone(),
+            ..Default::default()
+        });
+        chunk
+    }
+
+    /// A final chunk carrying whatever is still held back, one choice per
+    /// choice the upstream left without a `finish_reason`.
+    fn finish(&mut self) -> Option<OpenAIStreamChunk> {
+        let texts = self.texts.keys().map(|(choice, _)| *choice);
+        let mut open: Vec<u32> = texts
+            .chain(self.tool_args.keys().map(|(choice, _)| *choice))
+            .collect();
+        open.sort_unstable();
+        open.dedup();
+        let choices: Vec<OpenAIChoice> = open
+            .into_iter()
+            .map(|index| {
+                let mut choice = OpenAIChoice {
+                    index: Some(index),
+                    ..Default::default()
+                };
+                self.flush(index, &mut choice);
+                choice
+            })
+            .filter(|choice| choice.delta.is_some() || !choice.extra.is_empty())
+            .collect();
+        if choices.is_empty() {
+            return None;
         }
+        Some(OpenAIStreamChunk {
+            choices,
+            ..self.envelope.take().unwrap_or_default()
+        })
     }
 
-    let stream = stream! {
-        for event in buffered_events {
-            yield Ok::<_, Infallible>(event);
+    /// Moves everything held back for choice `index` into `choice`.
+    fn flush(&mut self, index: u32, choice: &mut OpenAIChoice) {
+        for field in STREAM_TEXT_FIELDS {
+            let Some(mut held) = self.texts.remove(&(index, *field)) else {
+                continue;
+            };
+            let (rest, _) = held.text.finish();
+            if !rest.is_empty() {
+                let delta = choice.delta.get_or_insert_with(Default::default);
+                append_delta_text(delta, field, &rest);
+            }
+            held.swap_logprobs(&mut choice.extra, field, true);
+        }
+
+        let keys: Vec<(u32, u32)> = self
+            .tool_args
+            .keys()
+            .filter(|(c, _)| *c == index)
+            .copied()
+            .collect();
+        let mut rest: Vec<ToolCall> = keys
+            .into_iter()
+            .filter_map(|key| Some((key.1, self.tool_args.remove(&key)?)))
+            .filter(|(_, redactor)| !redactor.is_empty())
+            .map(|(index, mut redactor)| ToolCall {
+                index: Some(index),
+                function: Some(FunctionCall {
+                    name: None,
+                    arguments: Some(redactor.finish().0),
+                }),
+                ..Default::default()
+            })
+            .collect();
+        rest.sort_by_key(|call| call.index);
+        if !rest.is_empty() {
+            let delta = choice.delta.get_or_insert_with(Default::default);
+            delta.tool_calls.get_or_insert_with(Vec::new).extend(rest);
         }
+    }
+}
+
+/// One text field of one streamed choice. The logprobs of `content` and
+/// `refusal` tokens are held back with their text, and masked once it is
+/// released.
+#[derive(Default)]
+struct HeldText {
+    text: TextRedactor,
+    logprobs: VecDeque<serde_json::Value>,
+    // Offset in the field's text of the first held logprobs entry
+    offset: usize,
+}
 
-        if stream_done {
+impl HeldText {
+    /// Takes the choice's logprobs entries for `field` and puts back those
+    /// whose text has been released, or all of them when `flush`ing.
+    fn swap_logprobs(
+        &mut self,
+        extra: &mut serde_json::Map<String, serde_json::Value>,
+        field: &str,
+        flush: bool,
+    ) {
+        if !matches!(field, "content" | "refusal") {
             return;
         }
+        let incoming = match extra.get_mut("logprobs").and_then(|l| l.get_mut(field)) {
+            Some(serde_json::Value::Array(entries)) => Some(std::mem::take(entries)),
+            _ => None,
+        };
+        let listed = incoming.is_some();
+        self.logprobs.extend(incoming.into_iter().flatten());
 
-        while let Some(item) = upstream.next().await {
-            match item {
-                Ok(line) => {
-                    if let Some((data, should_break)) = process_stream_line(&line) {
-                        yield Ok(axum::response::sse::Event::default().data(data));
-                        if should_break {
-                            break;
-                        }
-                    }
-                }
-                Err(e) => {
-                    let err = format!(r#"{{"error":"stream error: {}"}}"#, e);
-                    yield Ok(axum::response::sse::Event::default().data(err));
-                    break;
-                }
+        let mut end = self.offset;
+        let mut released = Vec::new();
+        while let Some(entry) = self.logprobs.front() {
+            let len = token_len(entry);
+            if !flush && end + len > self.text.released() {
+                break;
             }
+            end += len;
+            released.extend(self.logprobs.pop_front());
         }
-    };
+        self.offset = mask_logprobs(&mut released, self.offset, self.text.spans());
 
-    let sse = Sse::new(stream)
-        .keep_alive(axum::response::sse::KeepAlive::new().interval(Duration::from_secs(10)));
-    track_http_metrics("/v1/chat/completions", &model, &request_id);
-    sse.into_response()
+        if released.is_empty() && !listed {
+            return;
+        }
+        let logprobs = extra.entry("logprobs").or_insert_with(|| json!({}));
+        if !logprobs.is_object() {
+            *logprobs = json!({});
+        }
+        logprobs[field] = serde_json::Value::Array(released);
+    }
 }
 
-fn process_stream_line(line: &str) -> Option<(String, bool)> {
-    if line.trim() == "data: [DONE]" {
-        return Some(("data: [DONE]".to_string(), true));
+/// The text a delta carries in `field`, one of `STREAM_TEXT_FIELDS`.
+fn delta_text<'a>(delta: &'a mut OpenAIDelta, field: &str) -> Option<&'a mut String> {
+    let value = match field {
+        "content" => return delta.content.as_mut(),
+        "refusal" => return delta.refusal.as_mut(),
+        "audio" => delta.extra.get_mut("audio")?.get_mut("transcript")?,
+        field => delta.extra.get_mut(field)?,
+    };
+    match value {
+        serde_json::Value::String(text) => Some(text),
+        _ => None,
     }
+}
 
-    if let Some(json_part) = line.strip_prefix("data: ") {
-        if let Ok(mut chunk) = serde_json::from_str::<OpenAIStreamChunk>(json_part) {
-            if let Some(choice) = chunk.choices.get_mut(0) {
-                if let Some(delta) = choice.delta.as_mut() {
-                    if let Some(content) = delta.content.as_mut() {
-                        let (red, _) = redact_text(content);
-                        *content = red;
-                    }
+fn append_delta_text(delta: &mut OpenAIDelta, field: &str, text: &str) {
+    if delta_text(delta, field).is_none() {
+        match field {
+            "content" => delta.content = Some(String::new()),
+            "refusal" => delta.refusal = Some(String::new()),
+            "audio" => {
+                let audio = delta.extra.entry("audio").or_insert_with(|| json!({}));
+                if !audio.is_object() {
+                    *audio = json!({});
                 }
+                audio["transcript"] = json!("");
             }
-            if let Ok(s) = serde_json::to_string(&chunk) {
-                return Some((format!("data: {}", s), false));
+            field => {
+                delta.extra.insert(field.to_string(), json!(""));
             }
         }
-        return Some((format!("data: {}", json_part), false));
     }
+    if let Some(existing) = delta_text(delta, field) {
+        existing.push_str(text);
+    }
+}
+
+/// The text among a message's or delta's unmodelled fields: refusals,
+/// reasoning and audio transcripts.
+fn extra_texts(extra: &mut serde_json::Map<String, serde_json::Value>) -> Vec<&mut String> {
+    let mut texts = Vec::new();
+    for (field, value) in extra.iter_mut() {
+        let value = match field.as_str() {
+            "audio" => value.get_mut("transcript"),
+            field if EXTRA_TEXT_FIELDS.contains(&field) => Some(value),
+            _ => None,
+        };
+        if let Some(serde_json::Value::String(text)) = value {
+            texts.push(text);
+        }
+    }
+    texts
+}
+
+fn chunk_data(chunk: &OpenAIStreamChunk) -> String {
+    serde_json::to_string(chunk).unwrap_or_default()
+}
 
-    None
+/// Redacts a message's text (every text part of array content, refusals,
+/// reasoning and audio transcripts) and the arguments of any tool calls it
+/// makes. Tool results are plain `content` on `tool` messages.
+fn redact_message(message: &mut ChatMessage) -> RedactionStats {
+    let mut stats = RedactionStats::default();
+    let mut redact = |text: &mut String| {
+        let (redacted, s) = redact_text(text);
+        *text = redacted;
+        stats += s;
+    };
+    match message.content.as_mut() {
+        Some(MessageContent::Text(text)) => redact(text),
+        Some(MessageContent::Parts(parts)) => parts
+            .iter_mut()
+            .filter_map(|p| p.text.as_mut())
+            .for_each(&mut redact),
+        None => {}
+    }
+    for call in message.tool_calls.iter_mut().flatten() {
+        if let Some(args) = call.function.as_mut().and_then(|f| f.arguments.as_mut()) {
+            redact(args);
+        }
+    }
+    extra_texts(&mut message.extra)
+        .into_iter()
+        .for_each(&mut redact);
+    stats
 }
 
 fn redact_completion(resp: &mut OpenAIChatCompletionResponse) {
     for choice in &mut resp.choices {
         if let Some(message) = choice.message.as_mut() {
-            let (redacted, _) = redact_text(&message.content);
-            message.content = redacted;
+            if let Some(logprobs) = choice.extra.get_mut("logprobs") {
+                let refusal = message.extra.get("refusal").and_then(|r| r.as_str());
+                let texts = [
+                    ("content", message.text()),
+                    ("refusal", refusal.unwrap_or_default().into()),
+                ];
+                for (field, text) in texts {
+                    if let Some(serde_json::Value::Array(entries)) = logprobs.get_mut(field) {
+                        mask_logprobs(entries, 0, &redaction_spans(&text));
+                    }
+                }
+            }
+            redact_message(message);
         }
     }
 }
 
+/// Masks the logprobs `entries` whose tokens overlap `spans`, byte ranges of
+/// the text the tokens spell out starting at `offset`, alternatives in
+/// `top_logprobs` included. Tokens are too short for `redact_text` to
+/// recognise anything in them on their own. Returns the offset just past the
+/// last entry.
+fn mask_logprobs(
+    entries: &mut [serde_json::Value],
+    mut offset: usize,
+    spans: &[Range<usize>],
+) -> usize {
+    for entry in entries {
+        let len = token_len(entry);
+        let token = offset..offset + len;
+        offset += len;
+        if !spans
+            .iter()
+            .any(|s| s.start < token.end && token.start < s.end)
+        {
+            continue;
+        }
+        mask_token(entry);
+        if let Some(serde_json::Value::Array(top)) = entry.get_mut("top_logprobs") {
+            top.iter_mut().for_each(mask_token);
+        }
+    }
+    offset
+}
+
+/// Bytes of text a logprobs entry stands for. `bytes` is exact where a token
+/// splits a multi-byte character.
+fn token_len(entry: &serde_json::Value) -> usize {
+    entry["bytes"]
+        .as_array()
+        .map(Vec::len)
+        .or_else(|| entry["token"].as_str().map(str::len))
+        .unwrap_or_default()
+}
+
+fn mask_token(entry: &mut serde_json::Value) {
+    let Some(token) = entry["token"].as_str() else {
+        return;
+    };
+    let masked = "*".repeat(token.chars().count());
+    if entry.get("bytes").is_some_and(|b| !b.is_null()) {
+        entry["bytes"] = json!(masked.as_bytes());
+    }
+    entry["token"] = json!(masked);
+}
+
 fn handle_quota_error(err: QuotaError) -> Response {
     match err {
         QuotaError::Exceeded { limit, .. } => {
@@ -334,6 +970,14 @@ fn handle_quota_error(err: QuotaError) -> Response {
             )
                 .into_response()
         }
+        QuotaError::ImagesExceeded { limit, .. } => {
+            metrics::counter!("quota_block_total", "reason" => "images_exceeded").increment(1);
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("image quota exceeded (limit={limit})"),
+            )
+                .into_response()
+        }
         QuotaError::Backend(e) => {
             tracing::error!(error = %e, "quota backend failure");
             (StatusCode::INTERNAL_SERVER_ERROR, "quota backend failure").into_response()
@@ -355,3 +999,247 @@ async fn handle_layer_error(err: BoxError) -> impl IntoResponse {
     tracing::error!(error = %err, "unhandled middleware error");
     (StatusCode::INTERNAL_SERVER_ERROR, "internal error")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::sse_data;
+
+    /// Redacts a recorded SSE stream the way `chat_completions` does.
+    fn redact_recording(recording: &str) -> Vec<OpenAIStreamChunk> {
+        let mut redaction = StreamRedaction::default();
+        let mut out: Vec<OpenAIStreamChunk> = recording
+            .lines()
+            .filter_map(sse_data)
+            .take_while(|data| *data != "[DONE]")
+            .map(|data| redaction.process(serde_json::from_str(data).unwrap()))
+            .collect();
+        out.extend(redaction.finish());
+        out
+    }
+
+    /// Concatenates one field of every delta of choice `index`.
+    fn choice_text(
+        chunks: &[OpenAIStreamChunk],
+        index: u32,
+        field: impl Fn(&OpenAIDelta) -> Option<String>,
+    ) -> String {
+        chunks
+            .iter()
+            .flat_map(|chunk| &chunk.choices)
+            .filter(|choice| choice.index == Some(index))
+            .filter_map(|choice| choice.delta.as_ref().and_then(&field))
+            .collect()
+    }
+
+    fn tool_args(delta: &OpenAIDelta) -> Option<String> {
+        let calls = delta.tool_calls.as_ref()?;
+        Some(
+            calls
+                .iter()
+                .filter_map(|call| call.function.as_ref()?.arguments.clone())
+                .collect(),
+        )
+    }
+
+    // `n: 2` with a refusal in the second choice, recorded from OpenAI
+    const REFUSAL_RECORDING: &str = r#"data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[{"index":0,"delta":{"role":"assistant","content":"","refusal":null},"logprobs":null,"finish_reason":null},{"index":1,"delta":{"role":"assistant","content":"","refusal":null},"logprobs":null,"finish_reason":null}]}
+
+data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[{"index":0,"delta":{"content":"Write to jane.doe@example.com"},"logprobs":null,"finish_reason":null}]}
+
+data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[{"index":1,"delta":{"refusal":"I can't email jane.doe@example.com for you."},"logprobs":null,"finish_reason":null}]}
+
+data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[{"index":1,"delta":{"reasoning_content":"They want 555-123-4567 called"},"logprobs":null,"finish_reason":null}]}
+
+data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[{"index":0,"delta":{},"logprobs":null,"finish_reason":"stop"}]}
+
+data: {"id":"chatcmpl-1","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[{"index":1,"delta":{},"logprobs":null,"finish_reason":"stop"}]}
+
+data: [DONE]
+"#;
+
+    // `n: 2` with both choices calling the same tool, arguments interleaved,
+    // cut off before the second call's arguments were complete
+    const TOOL_CALL_RECORDING: &str = r#"data: {"id":"chatcmpl-2","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[{"index":0,"delta":{"role":"assistant","content":null,"tool_calls":[{"index":0,"id":"call_a","type":"function","function":{"name":"send_email","arguments":""}}]},"finish_reason":null},{"index":1,"delta":{"role":"assistant","content":null,"tool_calls":[{"index":0,"id":"call_b","type":"function","function":{"name":"send_email","arguments":""}}]},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-2","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"to\":\"jane"}}]},"finish_reason":null},{"index":1,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"to\":\"john"}}]},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-2","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[{"index":1,"delta":{"tool_calls":[{"index":0,"function":{"arguments":".roe@example.com"}}]},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-2","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":".doe@example.com\"}"}}]},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-2","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[{"index":0,"delta":{},"finish_reason":"tool_calls"}]}
+
+data: [DONE]
+"#;
+
+    #[test]
+    fn every_choice_and_text_field_is_redacted() {
+        let chunks = redact_recording(REFUSAL_RECORDING);
+        let raw: String = chunks.iter().map(chunk_data).collect();
+        assert!(!raw.contains("jane.doe@"), "{raw}");
+        assert!(!raw.contains("555-123-4567"), "{raw}");
+
+        let content = choice_text(&chunks, 0, |d| d.content.clone());
+        assert_eq!(content, redact_text("Write to jane.doe@example.com").0);
+        let refusal = choice_text(&chunks, 1, |d| d.refusal.clone());
+        assert!(refusal.starts_with("I can't email "), "{refusal}");
+    }
+
+    #[test]
+    fn completions_redact_the_same_fields_as_streams() {
+        let fields = json!({
+            "refusal": "I won't email jane.doe@example.com",
+            "reasoning_content": "jane.doe@example.com is an email",
+            "audio": { "id": "audio_1", "data": "AAAA", "transcript": "jane.doe@example.com" },
+        });
+        let mut message = fields.clone();
+        message["role"] = json!("assistant");
+        message["content"] = json!(null);
+        let mut response: OpenAIChatCompletionResponse = serde_json::from_value(json!({
+            "id": "chatcmpl-3",
+            "choices": [{ "index": 0, "message": message, "finish_reason": "stop" }],
+        }))
+        .unwrap();
+        redact_completion(&mut response);
+        let message = serde_json::to_value(&response.choices[0].message).unwrap();
+        assert!(!message.to_string().contains("jane.doe@"), "{message}");
+        assert_eq!(message["audio"]["id"], "audio_1");
+
+        let chunk = serde_json::from_value(json!({
+            "id": "chatcmpl-3",
+            "choices": [{ "index": 0, "delta": fields, "finish_reason": "stop" }],
+        }))
+        .unwrap();
+        let chunk = StreamRedaction::default().process(chunk);
+        let delta = serde_json::to_value(&chunk.choices[0].delta).unwrap();
+        for field in ["refusal", "reasoning_content"] {
+            assert_eq!(delta[field], message[field]);
+        }
+        assert_eq!(delta["audio"], message["audio"]);
+    }
+
+    fn logprob(token: &str) -> serde_json::Value {
+        json!({
+            "token": token,
+            "logprob": -0.1,
+            "bytes": token.as_bytes(),
+            "top_logprobs": [{ "token": token, "logprob": -0.1, "bytes": token.as_bytes() }],
+        })
+    }
+
+    #[test]
+    fn logprobs_tokens_of_redacted_values_are_masked() {
+        let tokens = ["Mail", " jane", ".doe", "@example", ".com", " now"];
+        let mut response: OpenAIChatCompletionResponse = serde_json::from_value(json!({
+            "id": "chatcmpl-4",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": tokens.concat() },
+                "logprobs": { "content": tokens.map(logprob), "refusal": null },
+                "finish_reason": "stop",
+            }],
+        }))
+        .unwrap();
+        redact_completion(&mut response);
+        let logprobs = &response.choices[0].extra["logprobs"]["content"];
+        let masked: Vec<&str> = (0..tokens.len())
+            .map(|i| logprobs[i]["token"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            masked,
+            ["Mail", "*****", "****", "********", "****", " now"]
+        );
+        assert!(!logprobs.to_string().contains("jane"), "{logprobs}");
+        assert_eq!(logprobs[1]["bytes"], json!(b"*****"));
+        assert_eq!(logprobs[1]["top_logprobs"][0]["token"], "*****");
+    }
+
+    // `n: 2` with `logprobs: true, top_logprobs: 1`, one token per chunk
+    const LOGPROBS_RECORDING: &str = r#"data: {"id":"chatcmpl-5","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","system_fingerprint":"fp_1","choices":[{"index":0,"delta":{"role":"assistant","content":"","refusal":null},"logprobs":{"content":[],"refusal":null},"finish_reason":null},{"index":1,"delta":{"role":"assistant","content":"","refusal":null},"logprobs":{"content":[],"refusal":null},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-5","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","system_fingerprint":"fp_1","choices":[{"index":0,"delta":{"content":"Mail"},"logprobs":{"content":[{"token":"Mail","logprob":-0.01,"bytes":[77,97,105,108],"top_logprobs":[{"token":"Send","logprob":-4.2,"bytes":[83,101,110,100]}]}],"refusal":null},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-5","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","system_fingerprint":"fp_1","choices":[{"index":1,"delta":{"content":"Call"},"logprobs":{"content":[{"token":"Call","logprob":-0.01,"bytes":[67,97,108,108],"top_logprobs":[{"token":"Phone","logprob":-4.2,"bytes":[80,104,111,110,101]}]}],"refusal":null},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-5","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","system_fingerprint":"fp_1","choices":[{"index":0,"delta":{"content":" jane"},"logprobs":{"content":[{"token":" jane","logprob":-0.01,"bytes":[32,106,97,110,101],"top_logprobs":[{"token":" john","logprob":-4.2,"bytes":[32,106,111,104,110]}]}],"refusal":null},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-5","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","system_fingerprint":"fp_1","choices":[{"index":1,"delta":{"content":" 555"},"logprobs":{"content":[{"token":" 555","logprob":-0.01,"bytes":[32,53,53,53],"top_logprobs":[{"token":" 556","logprob":-4.2,"bytes":[32,53,53,54]}]}],"refusal":null},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-5","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","system_fingerprint":"fp_1","choices":[{"index":0,"delta":{"content":".doe"},"logprobs":{"content":[{"token":".doe","logprob":-0.01,"bytes":[46,100,111,101],"top_logprobs":[{"token":".d","logprob":-4.2,"bytes":[46,100]}]}],"refusal":null},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-5","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","system_fingerprint":"fp_1","choices":[{"index":1,"delta":{"content":"-123"},"logprobs":{"content":[{"token":"-123","logprob":-0.01,"bytes":[45,49,50,51],"top_logprobs":[{"token":"-124","logprob":-4.2,"bytes":[45,49,50,52]}]}],"refusal":null},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-5","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","system_fingerprint":"fp_1","choices":[{"index":0,"delta":{"content":"@example"},"logprobs":{"content":[{"token":"@example","logprob":-0.01,"bytes":[64,101,120,97,109,112,108,101],"top_logprobs":[{"token":"@gmail","logprob":-4.2,"bytes":[64,103,109,97,105,108]}]}],"refusal":null},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-5","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","system_fingerprint":"fp_1","choices":[{"index":1,"delta":{"content":"-4567"},"logprobs":{"content":[{"token":"-4567","logprob":-0.01,"bytes":[45,52,53,54,55],"top_logprobs":[{"token":"-4568","logprob":-4.2,"bytes":[45,52,53,54,56]}]}],"refusal":null},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-5","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","system_fingerprint":"fp_1","choices":[{"index":0,"delta":{"content":".com"},"logprobs":{"content":[{"token":".com","logprob":-0.01,"bytes":[46,99,111,109],"top_logprobs":[{"token":".org","logprob":-4.2,"bytes":[46,111,114,103]}]}],"refusal":null},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-5","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","system_fingerprint":"fp_1","choices":[{"index":1,"delta":{"content":"."},"logprobs":{"content":[{"token":".","logprob":-0.01,"bytes":[46],"top_logprobs":[{"token":"!","logprob":-4.2,"bytes":[33]}]}],"refusal":null},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-5","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","system_fingerprint":"fp_1","choices":[{"index":0,"delta":{"content":" today"},"logprobs":{"content":[{"token":" today","logprob":-0.01,"bytes":[32,116,111,100,97,121],"top_logprobs":[{"token":" now","logprob":-4.2,"bytes":[32,110,111,119]}]}],"refusal":null},"finish_reason":null}]}
+
+data: {"id":"chatcmpl-5","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","system_fingerprint":"fp_1","choices":[{"index":0,"delta":{},"logprobs":null,"finish_reason":"stop"}]}
+
+data: {"id":"chatcmpl-5","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","system_fingerprint":"fp_1","choices":[{"index":1,"delta":{},"logprobs":null,"finish_reason":"stop"}]}
+
+data: [DONE]
+"#;
+
+    #[test]
+    fn values_split_across_deltas_are_redacted_with_their_logprobs() {
+        let chunks = redact_recording(LOGPROBS_RECORDING);
+        let raw: String = chunks.iter().map(chunk_data).collect();
+        assert!(!raw.contains("jane"), "{raw}");
+        assert!(!raw.contains("4567"), "{raw}");
+
+        let texts = ["Mail jane.doe@example.com today", "Call 555-123-4567."];
+        for (index, text) in (0..).zip(texts) {
+            let content = choice_text(&chunks, index, |d| d.content.clone());
+            assert_eq!(content, redact_text(text).0);
+
+            // Every token comes out once, in order, masked where it was redacted
+            let tokens: Vec<String> = chunks
+                .iter()
+                .flat_map(|chunk| &chunk.choices)
+                .filter(|choice| choice.index == Some(index))
+                .filter_map(|choice| choice.extra.get("logprobs")?.get("content")?.as_array())
+                .flatten()
+                .map(|entry| entry["token"].as_str().unwrap().to_string())
+                .collect();
+            let masked = tokens
+                .iter()
+                .filter(|t| t.chars().all(|c| c == '*'))
+                .count();
+            assert_eq!(masked, if index == 0 { 4 } else { 3 }, "{tokens:?}");
+            assert_eq!(
+                tokens.first().unwrap(),
+                texts[index as usize].split(' ').next().unwrap()
+            );
+        }
+        // The number could have gone on; it goes out with the chunk that
+        // finishes its choice
+        let last = &chunks.last().unwrap().choices[0];
+        assert_eq!(last.finish_reason.as_deref(), Some("stop"));
+        let content = last.delta.as_ref().and_then(|d| d.content.clone());
+        assert_eq!(content.unwrap(), redact_text("555-123-4567.").0);
+    }
+
+    #[test]
+    fn tool_arguments_are_buffered_per_choice() {
+        let chunks = redact_recording(TOOL_CALL_RECORDING);
+        let first = choice_text(&chunks, 0, tool_args);
+        assert_eq!(first, redact_text(r#"{"to":"jane.doe@example.com"}"#).0);
+        assert!(!first.contains("jane.doe@"));
+
+        // Choice 1 never finished; its held-back arguments come out in the final chunk
+        let last = chunks.last().unwrap();
+        assert_eq!(last.id.as_deref(), Some("chatcmpl-2"));
+        assert_eq!(last.choices.len(), 1);
+        assert_eq!(last.choices[0].index, Some(1));
+        let second = choice_text(&chunks, 1, tool_args);
+        assert_eq!(second, redact_text(r#"{"to":"john.roe@example.com"#).0);
+        assert!(!second.contains("john.roe@"));
+    }
+}
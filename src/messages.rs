@@ -0,0 +1,667 @@
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
+use async_stream::stream;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{sse::Event, IntoResponse, Response, Sse},
+    Json,
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::fallback::Served;
+use crate::provider::{
+    ChatCompletionRequest, ChatMessage, ContentPart, FunctionCall, ImageUrl, MessageContent,
+    OpenAIChatCompletionResponse, OpenAIStreamChunk, OpenAIUsage, ToolCall,
+};
+use crate::telemetry::track_http_metrics;
+use crate::{
+    admit_chat, complete_chat, open_chat_stream, record_upstream, redact_completion, tenant,
+    upstream_error_status, with_upstream_header, AppState, StreamRedaction,
+};
+
+const ROUTE: &str = "/v1/messages";
+
+/// An Anthropic Messages API request. Fields without an OpenAI equivalent
+/// (`top_k`, `thinking`, ...) are dropped.
+#[derive(Debug, Deserialize)]
+pub struct MessagesRequest {
+    model: String,
+    #[serde(default)]
+    max_tokens: Option<u32>,
+    messages: Vec<InboundMessage>,
+    #[serde(default)]
+    system: Option<Body>,
+    #[serde(default)]
+    temperature: Option<f32>,
+    #[serde(default)]
+    top_p: Option<f32>,
+    #[serde(default)]
+    stream: Option<bool>,
+    #[serde(default)]
+    stop_sequences: Option<Vec<String>>,
+    #[serde(default)]
+    tools: Option<Vec<Value>>,
+    #[serde(default)]
+    tool_choice: Option<Value>,
+    #[serde(default)]
+    metadata: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InboundMessage {
+    role: String,
+    content: Body,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Body {
+    Text(String),
+    Blocks(Vec<Block>),
+}
+
+impl Body {
+    fn text(self) -> String {
+        match self {
+            Self::Text(text) => text,
+            Self::Blocks(blocks) => blocks
+                .into_iter()
+                .filter_map(|b| match b {
+                    Block::Text { text } => Some(text),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Block {
+    Text {
+        text: String,
+    },
+    Image {
+        source: ImageSource,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        #[serde(default)]
+        input: Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        #[serde(default)]
+        content: Option<Body>,
+    },
+    /// Documents, thinking and other blocks no OpenAI-shaped upstream takes.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+impl MessagesRequest {
+    fn into_chat(self) -> ChatCompletionRequest {
+        let mut messages = Vec::new();
+        if let Some(system) = self.system {
+            messages.push(ChatMessage::new("system", system.text()));
+        }
+        for m in self.messages {
+            push_message(&mut messages, m);
+        }
+
+        let mut extra = Map::new();
+        let tools: Vec<Value> = self
+            .tools
+            .into_iter()
+            .flatten()
+            .filter_map(translate_tool)
+            .collect();
+        if !tools.is_empty() {
+            extra.insert("tools".to_string(), Value::Array(tools));
+        }
+        if let Some(choice) = self.tool_choice.as_ref().and_then(translate_tool_choice) {
+            extra.insert("tool_choice".to_string(), choice);
+        }
+        if let Some(stop) = self.stop_sequences {
+            extra.insert("stop".to_string(), json!(stop));
+        }
+        if let Some(user) = self.metadata.as_ref().and_then(|m| m.get("user_id")) {
+            extra.insert("user".to_string(), user.clone());
+        }
+        // OpenAI only reports usage for streams when asked to
+        if self.stream == Some(true) {
+            extra.insert(
+                "stream_options".to_string(),
+                json!({ "include_usage": true }),
+            );
+        }
+
+        ChatCompletionRequest {
+            model: self.model,
+            messages,
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_tokens,
+            // Anthropic only streams when asked to
+            stream: Some(self.stream.unwrap_or(false)),
+            extra,
+        }
+    }
+}
+
+/// Appends one Anthropic message as OpenAI messages: tool results become
+/// `tool` messages ahead of whatever else the turn says, and tool uses
+/// become `tool_calls`.
+fn push_message(messages: &mut Vec<ChatMessage>, message: InboundMessage) {
+    let blocks = match message.content {
+        Body::Text(text) => return messages.push(ChatMessage::new(&message.role, text)),
+        Body::Blocks(blocks) => blocks,
+    };
+    let mut parts = Vec::new();
+    let mut tool_calls = Vec::new();
+    for block in blocks {
+        match block {
+            Block::Text { text } => parts.push(ContentPart {
+                kind: "text".to_string(),
+                text: Some(text),
+                ..Default::default()
+            }),
+            Block::Image { source } => parts.push(ContentPart {
+                kind: "image_url".to_string(),
+                image_url: Some(ImageUrl {
+                    url: match source {
+                        ImageSource::Base64 { media_type, data } => {
+                            format!("data:{media_type};base64,{data}")
+                        }
+                        ImageSource::Url { url } => url,
+                    },
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            Block::ToolUse { id, name, input } => tool_calls.push(ToolCall {
+                id: Some(id),
+                kind: Some("function".to_string()),
+                function: Some(FunctionCall {
+                    name: Some(name),
+                    arguments: Some(input.to_string()),
+                }),
+                ..Default::default()
+            }),
+            Block::ToolResult {
+                tool_use_id,
+                content,
+            } => messages.push(ChatMessage {
+                tool_call_id: Some(tool_use_id),
+                ..ChatMessage::new("tool", content.map(Body::text).unwrap_or_default())
+            }),
+            Block::Other => {}
+        }
+    }
+    if parts.is_empty() && tool_calls.is_empty() {
+        return;
+    }
+    // Text-only turns stay plain strings, which every upstream takes
+    let content = if parts.is_empty() {
+        None
+    } else if parts.iter().all(|p| p.text.is_some()) {
+        Some(MessageContent::Text(
+            MessageContent::Parts(parts).into_text(),
+        ))
+    } else {
+        Some(MessageContent::Parts(parts))
+    };
+    messages.push(ChatMessage {
+        role: message.role,
+        content,
+        tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        ..Default::default()
+    });
+}
+
+/// Client tools carry an `input_schema`; server tools (web search, ...) are
+/// Anthropic-only and dropped.
+fn translate_tool(tool: Value) -> Option<Value> {
+    let mut function = json!({
+        "name": tool.get("name")?,
+        "parameters": tool.get("input_schema")?,
+    });
+    if let Some(description) = tool.get("description") {
+        function["description"] = description.clone();
+    }
+    Some(json!({ "type": "function", "function": function }))
+}
+
+fn translate_tool_choice(choice: &Value) -> Option<Value> {
+    Some(match choice.get("type")?.as_str()? {
+        "auto" => json!("auto"),
+        "any" => json!("required"),
+        "none" => json!("none"),
+        "tool" => json!({ "type": "function", "function": { "name": choice.get("name")? } }),
+        _ => return None,
+    })
+}
+
+fn stop_reason(finish_reason: Option<&str>) -> &'static str {
+    match finish_reason {
+        Some("length") => "max_tokens",
+        Some("tool_calls" | "function_call") => "tool_use",
+        Some("content_filter") => "refusal",
+        _ => "end_turn",
+    }
+}
+
+fn usage(usage: Option<OpenAIUsage>) -> Value {
+    let usage = usage.unwrap_or_default();
+    json!({
+        "input_tokens": usage.prompt_tokens.unwrap_or(0),
+        "output_tokens": usage.completion_tokens.unwrap_or(0),
+    })
+}
+
+/// Tool arguments as the JSON object Anthropic clients expect.
+fn tool_input(arguments: Option<&str>) -> Value {
+    arguments
+        .and_then(|a| serde_json::from_str(a).ok())
+        .unwrap_or_else(|| json!({}))
+}
+
+/// Rebuilds a chat completion as an Anthropic message from its first choice.
+fn into_message(response: OpenAIChatCompletionResponse, model: &str) -> Value {
+    let choice = response.choices.into_iter().next().unwrap_or_default();
+    let message = choice.message.unwrap_or_default();
+    let mut content = Vec::new();
+    let text = message.text();
+    if !text.is_empty() {
+        content.push(json!({ "type": "text", "text": text }));
+    }
+    for call in message.tool_calls.iter().flatten() {
+        let function = call.function.clone().unwrap_or_default();
+        content.push(json!({
+            "type": "tool_use",
+            "id": call.id,
+            "name": function.name,
+            "input": tool_input(function.arguments.as_deref()),
+        }));
+    }
+    json!({
+        "id": response.id.unwrap_or_else(message_id),
+        "type": "message",
+        "role": "assistant",
+        "model": response.model.as_deref().unwrap_or(model),
+        "content": content,
+        "stop_reason": stop_reason(choice.finish_reason.as_deref()),
+        "stop_sequence": null,
+        "usage": usage(response.usage),
+    })
+}
+
+fn message_id() -> String {
+    format!("msg_{}", Uuid::new_v4().simple())
+}
+
+#[derive(Debug, PartialEq)]
+enum OpenBlock {
+    Text,
+    /// A tool use, by the OpenAI tool call index it comes from.
+    Tool(u32),
+}
+
+/// Turns a stream of chat chunks into Anthropic's typed events, opening a
+/// content block whenever the delta switches between text and a tool call.
+/// `message_delta` waits for the end of the stream, since OpenAI reports
+/// usage in a chunk of its own after the `finish_reason`.
+#[derive(Default)]
+struct EventTranslator {
+    started: bool,
+    stopped: bool,
+    next_index: u32,
+    open: Option<OpenBlock>,
+    usage: Option<OpenAIUsage>,
+    stop_reason: Option<&'static str>,
+}
+
+impl EventTranslator {
+    fn translate(&mut self, chunk: &OpenAIStreamChunk, model: &str) -> Vec<Value> {
+        let mut events = Vec::new();
+        if !self.started {
+            events.push(self.start(chunk.id.clone(), chunk.model.as_deref().unwrap_or(model)));
+        }
+        if chunk.usage.is_some() {
+            self.usage = chunk.usage;
+        }
+        let Some(choice) = chunk.choices.first() else {
+            return events;
+        };
+        if let Some(delta) = &choice.delta {
+            if let Some(text) = delta.content.as_deref().filter(|t| !t.is_empty()) {
+                self.open_block(
+                    OpenBlock::Text,
+                    &mut events,
+                    || json!({ "type": "text", "text": "" }),
+                );
+                events.push(self.delta(json!({ "type": "text_delta", "text": text })));
+            }
+            for call in delta.tool_calls.iter().flatten() {
+                let function = call.function.clone().unwrap_or_default();
+                self.open_block(OpenBlock::Tool(call.index.unwrap_or(0)), &mut events, || {
+                    json!({ "type": "tool_use", "id": call.id, "name": function.name, "input": {} })
+                });
+                if let Some(args) = function.arguments.filter(|a| !a.is_empty()) {
+                    events.push(
+                        self.delta(json!({ "type": "input_json_delta", "partial_json": args })),
+                    );
+                }
+            }
+        }
+        if let Some(reason) = &choice.finish_reason {
+            events.extend(self.close_block());
+            self.stop_reason = Some(stop_reason(Some(reason)));
+        }
+        events
+    }
+
+    /// Ends the message once the stream is over, with `end_turn` if it
+    /// ended without a `finish_reason`.
+    fn finish(&mut self, model: &str) -> Vec<Value> {
+        let mut events = Vec::new();
+        if !self.started {
+            events.push(self.start(None, model));
+        }
+        events.extend(self.stop(self.stop_reason.unwrap_or("end_turn")));
+        events
+    }
+
+    fn start(&mut self, id: Option<String>, model: &str) -> Value {
+        self.started = true;
+        json!({
+            "type": "message_start",
+            "message": {
+                "id": id.unwrap_or_else(message_id),
+                "type": "message",
+                "role": "assistant",
+                "model": model,
+                "content": [],
+                "stop_reason": null,
+                "stop_sequence": null,
+                "usage": usage(None),
+            },
+        })
+    }
+
+    fn open_block(
+        &mut self,
+        block: OpenBlock,
+        events: &mut Vec<Value>,
+        content_block: impl FnOnce() -> Value,
+    ) {
+        if self.open.as_ref() == Some(&block) {
+            return;
+        }
+        events.extend(self.close_block());
+        self.open = Some(block);
+        events.push(json!({
+            "type": "content_block_start",
+            "index": self.next_index,
+            "content_block": content_block(),
+        }));
+    }
+
+    fn close_block(&mut self) -> Option<Value> {
+        self.open.take()?;
+        let index = self.next_index;
+        self.next_index += 1;
+        Some(json!({ "type": "content_block_stop", "index": index }))
+    }
+
+    fn delta(&self, delta: Value) -> Value {
+        json!({ "type": "content_block_delta", "index": self.next_index, "delta": delta })
+    }
+
+    fn stop(&mut self, reason: &str) -> Vec<Value> {
+        if std::mem::replace(&mut self.stopped, true) {
+            return Vec::new();
+        }
+        let mut events: Vec<Value> = self.close_block().into_iter().collect();
+        events.push(json!({
+            "type": "message_delta",
+            "delta": { "stop_reason": reason, "stop_sequence": null },
+            "usage": usage(self.usage),
+        }));
+        events.push(json!({ "type": "message_stop" }));
+        events
+    }
+}
+
+fn sse_event(event: Value) -> Event {
+    let kind = event["type"].as_str().unwrap_or("message").to_string();
+    Event::default()
+        .event(kind)
+        .data(serde_json::to_string(&event).unwrap_or_default())
+}
+
+/// Anthropic's error envelope.
+fn error_body(status: StatusCode, message: &str) -> Value {
+    let kind = if status.is_client_error() {
+        "invalid_request_error"
+    } else {
+        "api_error"
+    };
+    json!({ "type": "error", "error": { "type": kind, "message": message } })
+}
+
+/// Accepts Anthropic Messages API requests and serves them from whichever
+/// upstream the model routes to, translating the reply (or its SSE events)
+/// back. Admission, redaction and metrics are those of chat completions.
+#[instrument(skip(state, headers, req), fields(tenant = %tenant(&headers),
+                                               model = %req.model,
+                                               route = tracing::field::Empty,
+                                               provider = tracing::field::Empty,
+                                               upstream_model = tracing::field::Empty))]
+pub async fn messages_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<MessagesRequest>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+    metrics::counter!("requests_total", "route" => ROUTE).increment(1);
+
+    let mut chat_req = req.into_chat();
+    if let Err(refused) = admit_chat(&state, &headers, ROUTE, &mut chat_req).await {
+        return refused;
+    }
+
+    let route = state.router.resolve(&chat_req.model);
+    tracing::Span::current().record("route", route.route.as_str());
+    let model = std::mem::replace(&mut chat_req.model, route.primary().model.clone());
+
+    if chat_req.stream == Some(false) {
+        let Served {
+            value: mut response,
+            upstream,
+        } = match complete_chat(&state, &route, &chat_req).await {
+            Ok(served) => served,
+            Err(e) => {
+                track_http_metrics(ROUTE, &model, &route.route, route.primary(), &request_id);
+                let status = upstream_error_status(&e);
+                return (status, Json(error_body(status, &e.to_string()))).into_response();
+            }
+        };
+
+        record_upstream(&upstream);
+        redact_completion(&mut response);
+        track_http_metrics(ROUTE, &model, &route.route, &upstream, &request_id);
+        return with_upstream_header(
+            Json(into_message(response, &model)).into_response(),
+            &upstream,
+        );
+    }
+
+    let keep_alive = axum::response::sse::KeepAlive::new().interval(Duration::from_secs(10));
+    let Served {
+        value: (first_chunk, mut upstream_stream),
+        upstream,
+    } = match open_chat_stream(&state, &route, &chat_req).await {
+        Ok(served) => served,
+        Err(e) => {
+            track_http_metrics(ROUTE, &model, &route.route, route.primary(), &request_id);
+            let status = upstream_error_status(&e);
+            return (status, Json(error_body(status, &e.to_string()))).into_response();
+        }
+    };
+    record_upstream(&upstream);
+
+    let stream_model = model.clone();
+    let stream = stream! {
+        let mut redaction = StreamRedaction::default();
+        let mut translator = EventTranslator::default();
+        let mut next = first_chunk.map(Ok::<_, anyhow::Error>);
+        loop {
+            let chunk = match next {
+                Some(Ok(chunk)) => chunk,
+                Some(Err(e)) => {
+                    let message = format!("stream error: {e}");
+                    yield Ok::<_, Infallible>(sse_event(error_body(StatusCode::BAD_GATEWAY, &message)));
+                    return;
+                }
+                None => break,
+            };
+            for event in translator.translate(&redaction.process(chunk), &stream_model) {
+                yield Ok(sse_event(event));
+            }
+            next = upstream_stream.next().await;
+        }
+        if let Some(rest) = redaction.finish() {
+            for event in translator.translate(&rest, &stream_model) {
+                yield Ok(sse_event(event));
+            }
+        }
+        for event in translator.finish(&stream_model) {
+            yield Ok(sse_event(event));
+        }
+    };
+
+    track_http_metrics(ROUTE, &model, &route.route, &upstream, &request_id);
+    with_upstream_header(
+        Sse::new(stream).keep_alive(keep_alive).into_response(),
+        &upstream,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_requests_to_chat() {
+        let req: MessagesRequest = serde_json::from_value(json!({
+            "model": "claude-3-5-haiku",
+            "max_tokens": 256,
+            "system": [{ "type": "text", "text": "be brief" }],
+            "messages": [
+                { "role": "user", "content": "weather in Quito?" },
+                { "role": "assistant", "content": [
+                    { "type": "text", "text": "checking" },
+                    { "type": "tool_use", "id": "tu_1", "name": "weather", "input": { "city": "Quito" } },
+                ] },
+                { "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "tu_1", "content": [{ "type": "text", "text": "18C" }] },
+                    { "type": "text", "text": "and this?" },
+                    { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "iVBO" } },
+                ] },
+            ],
+            "tools": [{ "name": "weather", "input_schema": { "type": "object" } }],
+            "tool_choice": { "type": "any" },
+            "stop_sequences": ["END"],
+        }))
+        .unwrap();
+        let chat = serde_json::to_value(req.into_chat()).unwrap();
+        assert_eq!(
+            chat["messages"],
+            json!([
+                { "role": "system", "content": "be brief" },
+                { "role": "user", "content": "weather in Quito?" },
+                { "role": "assistant", "content": "checking", "tool_calls": [
+                    { "id": "tu_1", "type": "function", "function": { "name": "weather", "arguments": "{\"city\":\"Quito\"}" } },
+                ] },
+                { "role": "tool", "content": "18C", "tool_call_id": "tu_1" },
+                { "role": "user", "content": [
+                    { "type": "text", "text": "and this?" },
+                    { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBO" } },
+                ] },
+            ])
+        );
+        assert_eq!(
+            chat["tools"][0]["function"]["parameters"],
+            json!({ "type": "object" })
+        );
+        assert_eq!(chat["tool_choice"], "required");
+        assert_eq!(chat["stop"], json!(["END"]));
+        assert_eq!(chat["stream"], false);
+    }
+
+    #[test]
+    fn translates_chunks_to_events() {
+        let chunks = [
+            json!({ "id": "c1", "choices": [{ "index": 0, "delta": { "role": "assistant", "content": "Let me check" } }] }),
+            json!({ "id": "c1", "choices": [{ "index": 0, "delta": { "tool_calls": [{ "index": 0, "id": "call_1", "type": "function", "function": { "name": "weather", "arguments": "" } }] } }] }),
+            json!({ "id": "c1", "choices": [{ "index": 0, "delta": { "tool_calls": [{ "index": 0, "function": { "arguments": "{\"city\":" } }] } }] }),
+            json!({ "id": "c1", "choices": [{ "index": 0, "delta": {}, "finish_reason": "tool_calls" }] }),
+            json!({ "id": "c1", "choices": [], "usage": { "prompt_tokens": 12, "completion_tokens": 7, "total_tokens": 19 } }),
+        ];
+        let mut redaction = StreamRedaction::default();
+        let mut translator = EventTranslator::default();
+        let mut events = Vec::new();
+        for chunk in chunks {
+            let chunk: OpenAIStreamChunk = serde_json::from_value(chunk).unwrap();
+            events.extend(translator.translate(&redaction.process(chunk), "claude"));
+        }
+        assert!(redaction.finish().is_none());
+        events.extend(translator.finish("claude"));
+        let kinds: Vec<&str> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+        assert_eq!(
+            kinds,
+            [
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        // "check" is held back until the tool call starts, but still lands in
+        // the text block ahead of it
+        let text: String = events[2..4]
+            .iter()
+            .map(|e| e["delta"]["text"].as_str().unwrap())
+            .collect();
+        assert_eq!(text, "Let me check");
+        assert_eq!(events[5]["index"], 1);
+        assert_eq!(events[5]["content_block"]["name"], "weather");
+        assert_eq!(events[6]["delta"]["partial_json"], "{\"city\":");
+        assert_eq!(events[8]["delta"]["stop_reason"], "tool_use");
+        assert_eq!(
+            events[8]["usage"],
+            json!({ "input_tokens": 12, "output_tokens": 7 })
+        );
+    }
+}
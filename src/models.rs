@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Value};
+
+use crate::{tenant, AppState};
+
+/// Lists the models the caller may use, synthesized from `MODEL_ROUTES`
+/// (see [`crate::routing::ModelRouter::listed_models`]). `owned_by` is the
+/// upstream that serves the model first.
+pub async fn list_models_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Json<Value> {
+    metrics::counter!("requests_total", "route" => "/v1/models").increment(1);
+    let tenant = tenant(&headers);
+    let data: Vec<Value> = state
+        .router
+        .listed_models()
+        .into_iter()
+        .filter(|m| state.permissions.allows(tenant, &m.id))
+        .map(|m| model_object(&m.id, &m.upstream))
+        .collect();
+    Json(json!({ "object": "list", "data": data }))
+}
+
+/// Describes one model, which need not be listed as long as a route other
+/// than the default one matches it.
+pub async fn retrieve_model_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(model): Path<String>,
+) -> Response {
+    metrics::counter!("requests_total", "route" => "/v1/models/{model}").increment(1);
+    let route = state.router.resolve(&model);
+    if route.route == "default" || !state.permissions.allows(tenant(&headers), &model) {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("model {model} not found") })),
+        )
+            .into_response();
+    }
+    Json(model_object(&model, &route.primary().provider)).into_response()
+}
+
+fn model_object(id: &str, upstream: &str) -> Value {
+    json!({
+        "id": id,
+        "object": "model",
+        // Routes have no creation date; the field is required by OpenAI clients
+        "created": 0,
+        "owned_by": upstream,
+    })
+}
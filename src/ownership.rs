@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use redis::aio::ConnectionManager;
+use serde_json::Value;
+use tokio::sync::Mutex;
+
+use crate::config::AppConfig;
+
+/// Which tenant uploaded each file and created each batch, remembered for
+/// `BATCH_OWNER_TTL_SECS` after the id was last seen. Kept in Redis when
+/// `REDIS_URL` is set, so every instance agrees and restarts forget nothing;
+/// otherwise in memory, per instance. Ids without an owner belong to nobody.
+#[derive(Clone)]
+pub struct Owners {
+    store: Store,
+    ttl: Duration,
+}
+
+#[derive(Clone)]
+enum Store {
+    Redis(Arc<Mutex<ConnectionManager>>),
+    Memory(Arc<std::sync::Mutex<HashMap<String, (String, Instant)>>>),
+}
+
+impl Owners {
+    pub async fn new(cfg: &AppConfig) -> anyhow::Result<Self> {
+        let store = match cfg.redis_url.as_ref() {
+            Some(url) => {
+                let client = redis::Client::open(url.clone())
+                    .with_context(|| format!("failed to create redis client for {url}"))?;
+                let conn = ConnectionManager::new(client)
+                    .await
+                    .context("failed to connect to redis")?;
+                Store::Redis(Arc::new(Mutex::new(conn)))
+            }
+            None => Store::Memory(Default::default()),
+        };
+        Ok(Self {
+            store,
+            ttl: Duration::from_secs(cfg.batch_owner_ttl_secs),
+        })
+    }
+
+    pub async fn owns(&self, tenant: &str, id: &str) -> anyhow::Result<bool> {
+        let owners = self.owners_of(&[id]).await?;
+        Ok(owners[0].as_deref() == Some(tenant))
+    }
+
+    /// Records a file or batch object the upstream returned to `tenant`,
+    /// along with a batch's output and error files.
+    pub async fn record(&self, tenant: &str, object: &Value) -> anyhow::Result<()> {
+        let ids: Vec<&str> = ["id", "output_file_id", "error_file_id"]
+            .into_iter()
+            .filter_map(|key| object[key].as_str())
+            .collect();
+        if ids.is_empty() {
+            return Ok(());
+        }
+        match &self.store {
+            Store::Redis(conn) => {
+                let mut pipe = redis::pipe();
+                for id in ids {
+                    pipe.cmd("SET")
+                        .arg(key(id))
+                        .arg(tenant)
+                        .arg("EX")
+                        .arg(self.ttl.as_secs())
+                        .ignore();
+                }
+                let mut conn = conn.lock().await;
+                let _: () = pipe.query_async(&mut *conn).await?;
+            }
+            Store::Memory(owners) => {
+                let mut owners = owners.lock().expect("owners lock poisoned");
+                owners.retain(|_, (_, seen)| seen.elapsed() < self.ttl);
+                for id in ids {
+                    owners.insert(id.to_string(), (tenant.to_string(), Instant::now()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn forget(&self, id: &str) -> anyhow::Result<()> {
+        match &self.store {
+            Store::Redis(conn) => {
+                let mut conn = conn.lock().await;
+                let _: () = redis::cmd("DEL")
+                    .arg(key(id))
+                    .query_async(&mut *conn)
+                    .await?;
+            }
+            Store::Memory(owners) => {
+                owners.lock().expect("owners lock poisoned").remove(id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the entries of a list reply that `tenant` does not own, and
+    /// records the output files of the batches it does.
+    pub async fn retain_owned(&self, tenant: &str, list: &mut Value) -> anyhow::Result<()> {
+        let Some(Value::Array(items)) = list.get_mut("data") else {
+            return Ok(());
+        };
+        let ids: Vec<&str> = items
+            .iter()
+            .map(|item| item["id"].as_str().unwrap_or_default())
+            .collect();
+        let owners = self.owners_of(&ids).await?;
+        let mut owners = owners.into_iter();
+        items.retain(|_| owners.next().flatten().as_deref() == Some(tenant));
+        for item in items.iter() {
+            self.record(tenant, item).await?;
+        }
+        Ok(())
+    }
+
+    async fn owners_of(&self, ids: &[&str]) -> anyhow::Result<Vec<Option<String>>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        match &self.store {
+            Store::Redis(conn) => {
+                let keys: Vec<String> = ids.iter().map(|id| key(id)).collect();
+                let mut conn = conn.lock().await;
+                Ok(redis::cmd("MGET").arg(keys).query_async(&mut *conn).await?)
+            }
+            Store::Memory(owners) => {
+                let owners = owners.lock().expect("owners lock poisoned");
+                Ok(ids
+                    .iter()
+                    .map(|id| {
+                        let (tenant, seen) = owners.get(*id)?;
+                        (seen.elapsed() < self.ttl).then(|| tenant.clone())
+                    })
+                    .collect())
+            }
+        }
+    }
+}
+
+fn key(id: &str) -> String {
+    format!("owner:{id}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn in_memory(ttl: Duration) -> Owners {
+        Owners {
+            store: Store::Memory(Default::default()),
+            ttl,
+        }
+    }
+
+    #[tokio::test]
+    async fn files_and_batches_are_scoped_to_their_owner() {
+        let owners = in_memory(Duration::from_secs(60));
+        owners
+            .record("tenant-a", &json!({ "id": "file-a" }))
+            .await
+            .unwrap();
+        owners
+            .record("tenant-b", &json!({ "id": "file-b" }))
+            .await
+            .unwrap();
+        assert!(owners.owns("tenant-a", "file-a").await.unwrap());
+        assert!(!owners.owns("tenant-b", "file-a").await.unwrap());
+        assert!(!owners.owns("tenant-a", "file-unknown").await.unwrap());
+
+        let batch =
+            json!({ "id": "batch_a", "input_file_id": "file-a", "output_file_id": "file-out" });
+        owners.record("tenant-a", &batch).await.unwrap();
+        assert!(owners.owns("tenant-a", "file-out").await.unwrap());
+
+        let mut list = json!({
+            "object": "list",
+            "data": [{ "id": "file-a" }, { "id": "file-b" }, { "id": "file-out" }],
+        });
+        owners.retain_owned("tenant-b", &mut list).await.unwrap();
+        assert_eq!(list["data"], json!([{ "id": "file-b" }]));
+
+        owners.forget("file-b").await.unwrap();
+        assert!(!owners.owns("tenant-b", "file-b").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn owners_expire() {
+        let owners = in_memory(Duration::ZERO);
+        owners
+            .record("tenant-a", &json!({ "id": "file-a" }))
+            .await
+            .unwrap();
+        assert!(!owners.owns("tenant-a", "file-a").await.unwrap());
+    }
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+
+use crate::config::AppConfig;
+use crate::routing::glob_matches;
+
+/// Which model names each tenant may request, as globs over the name the
+/// client sends (before routing rewrites it).
+#[derive(Debug, Clone)]
+pub struct ModelPermissions {
+    tenants: HashMap<String, Vec<String>>,
+    default: Vec<String>,
+}
+
+impl ModelPermissions {
+    pub fn new(cfg: &AppConfig) -> Self {
+        Self {
+            tenants: cfg.tenant_models.clone(),
+            default: cfg.default_models.clone(),
+        }
+    }
+
+    pub fn allows(&self, tenant: &str, model: &str) -> bool {
+        self.tenants
+            .get(tenant)
+            .unwrap_or(&self.default)
+            .iter()
+            .any(|pattern| glob_matches(pattern, model))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tenants_fall_back_to_default_globs() {
+        let globs = |list: &[&str]| list.iter().map(|g| g.to_string()).collect::<Vec<_>>();
+        let p = ModelPermissions {
+            tenants: HashMap::from([
+                ("team-a".to_string(), globs(&["gpt-4o-mini", "claude-*"])),
+                ("locked".to_string(), Vec::new()),
+            ]),
+            default: globs(&["gpt-4o-mini"]),
+        };
+        assert!(p.allows("team-a", "claude-3-5-haiku"));
+        assert!(!p.allows("team-a", "gpt-4o"));
+        assert!(!p.allows("locked", "gpt-4o-mini"));
+        assert!(p.allows("anonymous", "gpt-4o-mini"));
+        assert!(!p.allows("anonymous", "claude-3-5-haiku"));
+    }
+}
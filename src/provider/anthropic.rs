@@ -0,0 +1,559 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use async_stream::try_stream;
+use async_trait::async_trait;
+use futures::StreamExt;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    body_lines, check_status, keys::KeyPool, probe_request, sse_data, unexpected_payload, unix_now,
+    Capabilities, ChatCompletionRequest, ChatMessage, ChatStream, DataUrl, MessageContent,
+    OpenAIChatCompletionChoice, OpenAIChatCompletionResponse, OpenAIChoice, OpenAIDelta,
+    OpenAIStreamChunk, OpenAIUsage, Provider, Unsupported,
+};
+
+const DEFAULT_VERSION: &str = "2023-06-01";
+// The Messages API requires max_tokens while the OpenAI shape leaves it optional.
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+#[derive(Clone)]
+pub struct AnthropicProvider {
+    keys: Arc<KeyPool>,
+    version: String,
+    client: Client,
+    base_url: Url,
+}
+
+impl AnthropicProvider {
+    pub fn new(
+        keys: Arc<KeyPool>,
+        base_url: Option<String>,
+        version: Option<String>,
+    ) -> anyhow::Result<Self> {
+        let base = match base_url {
+            Some(u) if !u.is_empty() => Url::parse(&u)?,
+            _ => Url::parse("https://api.anthropic.com")?,
+        };
+        let client = Client::builder().build()?;
+        Ok(Self {
+            keys,
+            version: version
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| DEFAULT_VERSION.to_string()),
+            client,
+            base_url: base,
+        })
+    }
+
+    async fn send(
+        &self,
+        body: &MessagesRequest,
+        accept: &str,
+    ) -> anyhow::Result<reqwest::Response> {
+        let url = self.base_url.join("/v1/messages")?;
+        let key = self.keys.pick();
+        let res = self
+            .client
+            .post(url)
+            .header("x-api-key", &*key)
+            .header("anthropic-version", &self.version)
+            .header("Accept", accept)
+            .json(body)
+            .send()
+            .await
+            .context("anthropic send failed")?;
+
+        let res = check_status(self.name(), res).await;
+        self.keys.observe(&key, &res);
+        res
+    }
+}
+
+#[async_trait]
+impl Provider for AnthropicProvider {
+    fn name(&self) -> &'static str {
+        "anthropic"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { streaming: true }
+    }
+
+    async fn chat_completion(
+        &self,
+        payload: ChatCompletionRequest,
+    ) -> anyhow::Result<OpenAIChatCompletionResponse> {
+        let body = MessagesRequest::from_chat(payload, false)?;
+        let res = self.send(&body, "application/json").await?;
+        let message = res
+            .json::<MessagesResponse>()
+            .await
+            .context("failed to parse anthropic response")?;
+        Ok(message.into_completion())
+    }
+
+    async fn chat_stream(&self, payload: ChatCompletionRequest) -> anyhow::Result<ChatStream> {
+        let body = MessagesRequest::from_chat(payload, true)?;
+        let res = self.send(&body, "text/event-stream").await?;
+        let mut lines = Box::pin(body_lines(res));
+
+        let stream = try_stream! {
+            let mut translator = StreamTranslator::default();
+            while let Some(line) = lines.next().await {
+                let line = line?;
+                let Some(data) = sse_data(&line) else {
+                    continue;
+                };
+                if data.trim().is_empty() {
+                    continue;
+                }
+                let event: StreamEvent = serde_json::from_str(data).map_err(|e| {
+                    unexpected_payload("anthropic stream payload", data.as_bytes(), &e)
+                })?;
+                match translator.translate(event)? {
+                    Step::Emit(chunk) => yield chunk,
+                    Step::Skip => {}
+                    Step::Done => break,
+                }
+            }
+        };
+        Ok(Box::pin(stream))
+    }
+
+    async fn probe(&self, path: Option<&str>) -> anyhow::Result<()> {
+        let url = self.base_url.join(path.unwrap_or("/v1/models"))?;
+        let req = self
+            .client
+            .get(url)
+            .header("x-api-key", &*self.keys.pick())
+            .header("anthropic-version", &self.version);
+        probe_request(self.name(), req).await
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct MessagesRequest {
+    model: String,
+    max_tokens: u32,
+    messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    stream: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Message {
+    role: String,
+    content: MessageBody,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum MessageBody {
+    Text(String),
+    Blocks(Vec<RequestBlock>),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RequestBlock {
+    Text { text: String },
+    Image { source: ImageSource },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+impl MessageBody {
+    fn from_content(content: Option<MessageContent>) -> Self {
+        let parts = match content {
+            Some(MessageContent::Parts(parts)) => parts,
+            other => return Self::Text(other.map(MessageContent::into_text).unwrap_or_default()),
+        };
+        let blocks = parts
+            .into_iter()
+            .filter_map(|part| {
+                if let Some(text) = part.text {
+                    return Some(RequestBlock::Text { text });
+                }
+                let url = part.image_url?.url;
+                let source = match DataUrl::parse(&url) {
+                    Some(data) => ImageSource::Base64 {
+                        media_type: data.media_type.to_string(),
+                        data: data.data.to_string(),
+                    },
+                    None => ImageSource::Url { url },
+                };
+                Some(RequestBlock::Image { source })
+            })
+            .collect();
+        Self::Blocks(blocks)
+    }
+}
+
+impl MessagesRequest {
+    fn from_chat(payload: ChatCompletionRequest, stream: bool) -> anyhow::Result<Self> {
+        if payload.uses_tools() {
+            return Err(Unsupported {
+                provider: "anthropic",
+                operation: "tool calling",
+            }
+            .into());
+        }
+        // Anthropic takes system prompts as a top-level field, not as messages
+        let mut system = Vec::new();
+        let mut messages = Vec::new();
+        for m in payload.messages {
+            match m.role.as_str() {
+                "system" | "developer" => system.push(m.text().into_owned()),
+                "assistant" => messages.push(Message {
+                    role: "assistant".to_string(),
+                    content: MessageBody::from_content(m.content),
+                }),
+                _ => messages.push(Message {
+                    role: "user".to_string(),
+                    content: MessageBody::from_content(m.content),
+                }),
+            }
+        }
+        Ok(Self {
+            model: payload.model,
+            max_tokens: payload.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            messages,
+            system: (!system.is_empty()).then(|| system.join("\n\n")),
+            // Anthropic accepts 0..=1 where OpenAI accepts 0..=2
+            temperature: payload.temperature.map(|t| t.clamp(0.0, 1.0)),
+            top_p: payload.top_p,
+            stream,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MessagesResponse {
+    id: Option<String>,
+    model: Option<String>,
+    #[serde(default)]
+    content: Vec<ContentBlock>,
+    stop_reason: Option<String>,
+    usage: Option<Usage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+struct Usage {
+    #[serde(default)]
+    input_tokens: Option<u32>,
+    #[serde(default)]
+    output_tokens: Option<u32>,
+}
+
+impl Usage {
+    fn into_openai(self) -> OpenAIUsage {
+        OpenAIUsage {
+            prompt_tokens: self.input_tokens,
+            completion_tokens: self.output_tokens,
+            total_tokens: match (self.input_tokens, self.output_tokens) {
+                (Some(i), Some(o)) => Some(i + o),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl MessagesResponse {
+    fn into_completion(self) -> OpenAIChatCompletionResponse {
+        let text: String = self
+            .content
+            .iter()
+            .filter(|b| b.kind == "text")
+            .filter_map(|b| b.text.as_deref())
+            .collect();
+        OpenAIChatCompletionResponse {
+            id: self.id,
+            object: Some("chat.completion".to_string()),
+            created: Some(unix_now()),
+            model: self.model,
+            choices: vec![OpenAIChatCompletionChoice {
+                index: Some(0),
+                message: Some(ChatMessage::new("assistant", text)),
+                finish_reason: self.stop_reason.as_deref().map(map_stop_reason),
+                extra: Default::default(),
+            }],
+            usage: self.usage.map(Usage::into_openai),
+            extra: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    MessageStart {
+        message: MessageStart,
+    },
+    ContentBlockStart {},
+    ContentBlockDelta {
+        delta: BlockDelta,
+    },
+    ContentBlockStop {},
+    MessageDelta {
+        delta: MessageDeltaBody,
+        #[serde(default)]
+        usage: Option<Usage>,
+    },
+    MessageStop,
+    Ping,
+    Error {
+        error: serde_json::Value,
+    },
+    /// Event types added after this was written; skipped.
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageStart {
+    id: Option<String>,
+    model: Option<String>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockDelta {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageDeltaBody {
+    #[serde(default)]
+    stop_reason: Option<String>,
+}
+
+enum Step {
+    Emit(OpenAIStreamChunk),
+    Skip,
+    Done,
+}
+
+/// Carries message-level metadata from `message_start` onto every chunk.
+#[derive(Default)]
+struct StreamTranslator {
+    id: Option<String>,
+    model: Option<String>,
+    created: i64,
+    input_tokens: Option<u32>,
+}
+
+impl StreamTranslator {
+    fn translate(&mut self, event: StreamEvent) -> anyhow::Result<Step> {
+        let step = match event {
+            StreamEvent::MessageStart { message } => {
+                self.id = message.id;
+                self.model = message.model;
+                self.created = unix_now();
+                self.input_tokens = message.usage.and_then(|u| u.input_tokens);
+                Step::Emit(self.chunk(
+                    OpenAIDelta {
+                        role: Some("assistant".to_string()),
+                        content: Some(String::new()),
+                        ..Default::default()
+                    },
+                    None,
+                ))
+            }
+            StreamEvent::ContentBlockDelta { delta } if delta.kind == "text_delta" => {
+                Step::Emit(self.chunk(
+                    OpenAIDelta {
+                        role: None,
+                        content: delta.text,
+                        ..Default::default()
+                    },
+                    None,
+                ))
+            }
+            StreamEvent::MessageDelta { delta, usage } => {
+                let mut chunk = self.chunk(
+                    OpenAIDelta::default(),
+                    delta.stop_reason.as_deref().map(map_stop_reason),
+                );
+                chunk.usage = usage.map(|u| {
+                    Usage {
+                        input_tokens: u.input_tokens.or(self.input_tokens),
+                        output_tokens: u.output_tokens,
+                    }
+                    .into_openai()
+                });
+                Step::Emit(chunk)
+            }
+            StreamEvent::MessageStop => Step::Done,
+            StreamEvent::Error { error } => {
+                return Err(anyhow::anyhow!("anthropic stream error: {error}"));
+            }
+            StreamEvent::ContentBlockDelta { .. }
+            | StreamEvent::ContentBlockStart {}
+            | StreamEvent::ContentBlockStop {}
+            | StreamEvent::Ping
+            | StreamEvent::Unknown => Step::Skip,
+        };
+        Ok(step)
+    }
+
+    fn chunk(&self, delta: OpenAIDelta, finish_reason: Option<String>) -> OpenAIStreamChunk {
+        OpenAIStreamChunk {
+            id: self.id.clone(),
+            object: Some("chat.completion.chunk".to_string()),
+            created: Some(self.created),
+            model: self.model.clone(),
+            choices: vec![OpenAIChoice {
+                index: Some(0),
+                delta: Some(delta),
+                finish_reason,
+                extra: Default::default(),
+            }],
+            usage: None,
+            extra: Default::default(),
+        }
+    }
+}
+
+fn map_stop_reason(reason: &str) -> String {
+    match reason {
+        "max_tokens" => "length",
+        "tool_use" => "tool_calls",
+        "refusal" => "content_filter",
+        _ => "stop",
+    }
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(role: &str, content: &str) -> ChatMessage {
+        ChatMessage::new(role, content)
+    }
+
+    #[test]
+    fn system_messages_are_lifted() {
+        let req = ChatCompletionRequest {
+            model: "claude-3-5-sonnet-latest".to_string(),
+            messages: vec![
+                msg("system", "be brief"),
+                msg("user", "hi"),
+                msg("assistant", "hello"),
+            ],
+            temperature: Some(1.5),
+            top_p: None,
+            max_tokens: None,
+            stream: None,
+            extra: Default::default(),
+        };
+        let body = MessagesRequest::from_chat(req, true).unwrap();
+        assert_eq!(body.system.as_deref(), Some("be brief"));
+        assert_eq!(body.messages.len(), 2);
+        assert_eq!(body.max_tokens, DEFAULT_MAX_TOKENS);
+        assert_eq!(body.temperature, Some(1.0));
+    }
+
+    #[test]
+    fn tool_requests_are_refused() {
+        let mut req = ChatCompletionRequest {
+            model: "claude-3-5-sonnet-latest".to_string(),
+            messages: vec![msg("user", "weather in Quito?")],
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            stream: None,
+            extra: Default::default(),
+        };
+        req.extra.insert(
+            "tools".to_string(),
+            serde_json::json!([{ "type": "function", "function": { "name": "weather" } }]),
+        );
+        let err = MessagesRequest::from_chat(req, false).unwrap_err();
+        assert!(err.downcast_ref::<Unsupported>().is_some());
+    }
+
+    #[test]
+    fn image_parts_become_blocks() {
+        let content: MessageContent = serde_json::from_value(serde_json::json!([
+            { "type": "text", "text": "what is this?" },
+            { "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBORw0K" } },
+            { "type": "image_url", "image_url": { "url": "https://example.com/a.png", "detail": "low" } },
+        ]))
+        .unwrap();
+        let body = serde_json::to_value(MessageBody::from_content(Some(content))).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!([
+                { "type": "text", "text": "what is this?" },
+                { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "iVBORw0K" } },
+                { "type": "image", "source": { "type": "url", "url": "https://example.com/a.png" } },
+            ])
+        );
+    }
+
+    #[test]
+    fn translates_stream_events() {
+        let events = [
+            r#"{"type":"message_start","message":{"id":"msg_1","model":"claude","usage":{"input_tokens":7}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hello"}}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"end_turn"},"usage":{"output_tokens":3}}"#,
+            r#"{"type":"message_stop"}"#,
+        ];
+        let mut translator = StreamTranslator::default();
+        let steps: Vec<Step> = events
+            .iter()
+            .map(|e| {
+                translator
+                    .translate(serde_json::from_str(e).unwrap())
+                    .unwrap()
+            })
+            .collect();
+
+        let Step::Emit(delta) = &steps[2] else {
+            panic!("expected text delta");
+        };
+        let choice = &delta.choices[0];
+        assert_eq!(delta.id.as_deref(), Some("msg_1"));
+        assert_eq!(
+            choice.delta.as_ref().unwrap().content.as_deref(),
+            Some("Hello")
+        );
+
+        let Step::Emit(last) = &steps[3] else {
+            panic!("expected message delta");
+        };
+        assert_eq!(last.choices[0].finish_reason.as_deref(), Some("stop"));
+        assert_eq!(last.usage.unwrap().total_tokens, Some(10));
+        assert!(matches!(steps[4], Step::Done));
+
+        let unknown = r#"{"type":"content_block_checkpoint","index":0,"note":"new"}"#;
+        let step = translator.translate(serde_json::from_str(unknown).unwrap());
+        assert!(matches!(step.unwrap(), Step::Skip));
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+
+use crate::config::BreakerConfig;
+
+use super::{
+    is_transient, AudioStream, Capabilities, ChatCompletionRequest, ChatStream, EmbeddingsRequest,
+    EmbeddingsResponse, FilesRequest, ImageGenerationRequest, ImagesResponse,
+    OpenAIChatCompletionResponse, Provider, RealtimeSocket, ResponsesRequest, ResponsesStream,
+    SpeechRequest, Transcript, TranscriptionRequest,
+};
+
+/// Returned without contacting the upstream while its breaker is open.
+#[derive(Debug, thiserror::Error)]
+#[error("circuit open for upstream {0}")]
+pub struct CircuitOpen(pub String);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Closed,
+    Open {
+        until: Instant,
+    },
+    /// A single probe request is in flight; its outcome decides the next state.
+    HalfOpen,
+}
+
+impl State {
+    fn gauge(&self) -> f64 {
+        match self {
+            State::Closed => 0.0,
+            State::Open { .. } => 1.0,
+            State::HalfOpen => 2.0,
+        }
+    }
+}
+
+/// Per-second success/failure counts over the last `window_secs` seconds.
+struct Window {
+    started: Instant,
+    // (second since `started`, successes, failures)
+    buckets: Vec<(u64, u32, u32)>,
+}
+
+impl Window {
+    fn new(secs: u64, now: Instant) -> Self {
+        Self {
+            started: now,
+            buckets: vec![(u64::MAX, 0, 0); secs as usize],
+        }
+    }
+
+    fn record(&mut self, ok: bool, now: Instant) {
+        let sec = now.duration_since(self.started).as_secs();
+        let len = self.buckets.len() as u64;
+        let bucket = &mut self.buckets[(sec % len) as usize];
+        if bucket.0 != sec {
+            *bucket = (sec, 0, 0);
+        }
+        if ok {
+            bucket.1 += 1;
+        } else {
+            bucket.2 += 1;
+        }
+    }
+
+    /// Total requests and failures still inside the window.
+    fn totals(&self, now: Instant) -> (u32, u32) {
+        let sec = now.duration_since(self.started).as_secs();
+        let len = self.buckets.len() as u64;
+        self.buckets
+            .iter()
+            .filter(|(s, ..)| *s != u64::MAX && sec.saturating_sub(*s) < len)
+            .fold((0, 0), |(total, failed), (_, ok, fail)| {
+                (total + ok + fail, failed + fail)
+            })
+    }
+
+    fn reset(&mut self, now: Instant) {
+        *self = Self::new(self.buckets.len() as u64, now);
+    }
+}
+
+struct Inner {
+    state: State,
+    window: Window,
+}
+
+/// Closed/open/half-open breaker for one upstream.
+pub struct Breaker {
+    upstream: String,
+    cfg: BreakerConfig,
+    inner: Mutex<Inner>,
+}
+
+impl Breaker {
+    pub fn new(upstream: &str, cfg: BreakerConfig) -> Self {
+        let breaker = Self {
+            upstream: upstream.to_string(),
+            cfg,
+            inner: Mutex::new(Inner {
+                state: State::Closed,
+                window: Window::new(cfg.window_secs.max(1), Instant::now()),
+            }),
+        };
+        breaker.export(State::Closed);
+        breaker
+    }
+
+    /// Whether a request sent now would be let through.
+    pub fn is_available(&self, now: Instant) -> bool {
+        match self.inner.lock().expect("breaker lock poisoned").state {
+            State::Closed => true,
+            State::Open { until } => now >= until,
+            State::HalfOpen => false,
+        }
+    }
+
+    /// Admits a request, returning `Some(is_probe)`, or `None` if it must be rejected.
+    fn acquire(&self, now: Instant) -> Option<bool> {
+        let mut inner = self.inner.lock().expect("breaker lock poisoned");
+        match inner.state {
+            State::Closed => Some(false),
+            State::Open { until } if now >= until => {
+                self.transition(&mut inner, State::HalfOpen, "half_open");
+                Some(true)
+            }
+            State::Open { .. } | State::HalfOpen => {
+                metrics::counter!("cb_events_total", "event" => "rejected", "upstream" => self.upstream.clone())
+                    .increment(1);
+                None
+            }
+        }
+    }
+
+    fn record(&self, ok: bool, probe: bool, now: Instant) {
+        let mut inner = self.inner.lock().expect("breaker lock poisoned");
+        if probe {
+            if ok {
+                inner.window.reset(now);
+                self.transition(&mut inner, State::Closed, "closed");
+            } else {
+                self.open(&mut inner, now);
+            }
+            return;
+        }
+        // A late result from before the breaker opened says nothing new
+        if inner.state != State::Closed {
+            return;
+        }
+        inner.window.record(ok, now);
+        let (total, failed) = inner.window.totals(now);
+        if !ok
+            && total >= self.cfg.min_requests.max(1)
+            && failed as f64 >= self.cfg.failure_rate * total as f64
+        {
+            self.open(&mut inner, now);
+        }
+    }
+
+    fn open(&self, inner: &mut Inner, now: Instant) {
+        let until = now + Duration::from_secs(self.cfg.open_secs);
+        self.transition(inner, State::Open { until }, "opened");
+        tracing::warn!(upstream = %self.upstream, "circuit opened");
+    }
+
+    fn transition(&self, inner: &mut Inner, to: State, event: &'static str) {
+        inner.state = to;
+        metrics::counter!("cb_events_total", "event" => event, "upstream" => self.upstream.clone())
+            .increment(1);
+        self.export(to);
+    }
+
+    fn export(&self, state: State) {
+        metrics::gauge!("cb_state", "upstream" => self.upstream.clone()).set(state.gauge());
+    }
+}
+
+/// An admitted request. Dropping it without an outcome (the caller gave up)
+/// only matters for probes, which then count as failed.
+struct Permit<'a> {
+    breaker: &'a Breaker,
+    probe: bool,
+    done: bool,
+}
+
+impl Permit<'_> {
+    fn finish<T>(mut self, result: &anyhow::Result<T>) {
+        self.done = true;
+        // Client errors say nothing about the upstream's health
+        let ok = match result {
+            Ok(_) => true,
+            Err(e) => !is_transient(e),
+        };
+        self.breaker.record(ok, self.probe, Instant::now());
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if !self.done && self.probe {
+            self.breaker.record(false, true, Instant::now());
+        }
+    }
+}
+
+/// Wraps a single upstream with its own [`Breaker`].
+pub struct BreakerProvider {
+    inner: Arc<dyn Provider>,
+    breaker: Breaker,
+}
+
+impl BreakerProvider {
+    pub fn new(upstream: &str, inner: Arc<dyn Provider>, cfg: BreakerConfig) -> Self {
+        Self {
+            inner,
+            breaker: Breaker::new(upstream, cfg),
+        }
+    }
+
+    fn permit(&self) -> anyhow::Result<Permit<'_>> {
+        match self.breaker.acquire(Instant::now()) {
+            Some(probe) => Ok(Permit {
+                breaker: &self.breaker,
+                probe,
+                done: false,
+            }),
+            None => Err(CircuitOpen(self.breaker.upstream.clone()).into()),
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for BreakerProvider {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn is_available(&self) -> bool {
+        self.breaker.is_available(Instant::now())
+    }
+
+    async fn chat_completion(
+        &self,
+        payload: ChatCompletionRequest,
+    ) -> anyhow::Result<OpenAIChatCompletionResponse> {
+        let permit = self.permit()?;
+        let result = self.inner.chat_completion(payload).await;
+        permit.finish(&result);
+        result
+    }
+
+    async fn chat_stream(&self, payload: ChatCompletionRequest) -> anyhow::Result<ChatStream> {
+        let permit = self.permit()?;
+        let result = self.inner.chat_stream(payload).await;
+        permit.finish(&result);
+        result
+    }
+
+    async fn embeddings(&self, payload: EmbeddingsRequest) -> anyhow::Result<EmbeddingsResponse> {
+        let permit = self.permit()?;
+        let result = self.inner.embeddings(payload).await;
+        permit.finish(&result);
+        result
+    }
+
+    async fn responses(&self, payload: ResponsesRequest) -> anyhow::Result<serde_json::Value> {
+        let permit = self.permit()?;
+        let result = self.inner.responses(payload).await;
+        permit.finish(&result);
+        result
+    }
+
+    async fn responses_stream(&self, payload: ResponsesRequest) -> anyhow::Result<ResponsesStream> {
+        let permit = self.permit()?;
+        let result = self.inner.responses_stream(payload).await;
+        permit.finish(&result);
+        result
+    }
+
+    async fn transcription(&self, request: TranscriptionRequest) -> anyhow::Result<Transcript> {
+        let permit = self.permit()?;
+        let result = self.inner.transcription(request).await;
+        permit.finish(&result);
+        result
+    }
+
+    async fn speech(&self, payload: SpeechRequest) -> anyhow::Result<AudioStream> {
+        let permit = self.permit()?;
+        let result = self.inner.speech(payload).await;
+        permit.finish(&result);
+        result
+    }
+
+    async fn image_generation(
+        &self,
+        payload: ImageGenerationRequest,
+    ) -> anyhow::Result<ImagesResponse> {
+        let permit = self.permit()?;
+        let result = self.inner.image_generation(payload).await;
+        permit.finish(&result);
+        result
+    }
+
+    async fn realtime(&self, model: &str) -> anyhow::Result<RealtimeSocket> {
+        let permit = self.permit()?;
+        let result = self.inner.realtime(model).await;
+        permit.finish(&result);
+        result
+    }
+
+    async fn files(&self, request: FilesRequest) -> anyhow::Result<reqwest::Response> {
+        let permit = self.permit()?;
+        let result = self.inner.files(request).await;
+        permit.finish(&result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> Breaker {
+        Breaker::new(
+            "test",
+            BreakerConfig {
+                failure_rate: 0.5,
+                min_requests: 4,
+                window_secs: 10,
+                open_secs: 5,
+            },
+        )
+    }
+
+    #[test]
+    fn opens_on_failure_rate_and_recovers_through_probe() {
+        let b = breaker();
+        let t0 = Instant::now();
+        b.record(true, false, t0);
+        b.record(true, false, t0);
+        b.record(false, false, t0);
+        assert!(b.is_available(t0));
+        b.record(false, false, t0);
+        assert!(!b.is_available(t0));
+        assert_eq!(b.acquire(t0), None);
+
+        // After the open period one probe goes through, others wait for it
+        let t1 = t0 + Duration::from_secs(5);
+        assert_eq!(b.acquire(t1), Some(true));
+        assert_eq!(b.acquire(t1), None);
+        b.record(true, true, t1);
+        assert_eq!(b.acquire(t1), Some(false));
+    }
+
+    #[test]
+    fn failed_probe_reopens_and_old_failures_expire() {
+        let b = breaker();
+        let t0 = Instant::now();
+        for _ in 0..4 {
+            b.record(false, false, t0);
+        }
+        let t1 = t0 + Duration::from_secs(5);
+        assert_eq!(b.acquire(t1), Some(true));
+        b.record(false, true, t1);
+        assert!(!b.is_available(t1 + Duration::from_secs(4)));
+
+        let b = breaker();
+        for _ in 0..3 {
+            b.record(false, false, t0);
+        }
+        // Outside the window, so one more failure is not enough to open
+        b.record(false, false, t0 + Duration::from_secs(11));
+        assert!(b.is_available(t0 + Duration::from_secs(11)));
+    }
+}
@@ -0,0 +1,549 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use async_stream::try_stream;
+use async_trait::async_trait;
+use futures::StreamExt;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    check_status, keys::KeyPool, probe_request, unexpected_payload, unix_now, Capabilities,
+    ChatCompletionRequest, ChatMessage, ChatStream, DataUrl, EmbeddingsRequest, EmbeddingsResponse,
+    MessageContent, OpenAIChatCompletionChoice, OpenAIChatCompletionResponse, OpenAIChoice,
+    OpenAIDelta, OpenAIStreamChunk, OpenAIUsage, Provider, Unsupported,
+};
+
+#[derive(Clone)]
+pub struct GeminiProvider {
+    keys: Arc<KeyPool>,
+    client: Client,
+    base_url: Url,
+}
+
+impl GeminiProvider {
+    pub fn new(keys: Arc<KeyPool>, base_url: Option<String>) -> anyhow::Result<Self> {
+        let base = match base_url {
+            Some(u) if !u.is_empty() => Url::parse(&u)?,
+            _ => Url::parse("https://generativelanguage.googleapis.com")?,
+        };
+        let client = Client::builder().build()?;
+        Ok(Self {
+            keys,
+            client,
+            base_url: base,
+        })
+    }
+
+    async fn send(
+        &self,
+        model: &str,
+        method: &str,
+        body: &impl Serialize,
+    ) -> anyhow::Result<reqwest::Response> {
+        let model = model.strip_prefix("models/").unwrap_or(model);
+        let url = self
+            .base_url
+            .join(&format!("/v1beta/models/{model}:{method}"))?;
+        let key = self.keys.pick();
+        let res = self
+            .client
+            .post(url)
+            .header("x-goog-api-key", &*key)
+            .json(body)
+            .send()
+            .await
+            .context("gemini send failed")?;
+
+        let res = check_status(self.name(), res).await;
+        self.keys.observe(&key, &res);
+        res
+    }
+}
+
+#[async_trait]
+impl Provider for GeminiProvider {
+    fn name(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { streaming: true }
+    }
+
+    async fn chat_completion(
+        &self,
+        payload: ChatCompletionRequest,
+    ) -> anyhow::Result<OpenAIChatCompletionResponse> {
+        let model = payload.model.clone();
+        let body = GenerateContentRequest::from_chat(payload)?;
+        let res = self.send(&model, "generateContent", &body).await?;
+        let response = res
+            .json::<GenerateContentResponse>()
+            .await
+            .context("failed to parse gemini response")?;
+        Ok(response.into_completion(model))
+    }
+
+    async fn chat_stream(&self, payload: ChatCompletionRequest) -> anyhow::Result<ChatStream> {
+        let model = payload.model.clone();
+        let body = GenerateContentRequest::from_chat(payload)?;
+        let res = self.send(&model, "streamGenerateContent", &body).await?;
+        let mut bytes = res.bytes_stream();
+
+        // Without `alt=sse` the endpoint streams one JSON array whose elements
+        // arrive incrementally, so elements are cut out as soon as they close.
+        let stream = try_stream! {
+            let mut splitter = JsonArraySplitter::default();
+            let mut translator = StreamTranslator::new(model);
+            while let Some(chunk) = bytes.next().await {
+                let chunk = chunk.context("gemini stream read failed")?;
+                for element in splitter.push(&chunk) {
+                    let response: GenerateContentResponse = serde_json::from_slice(&element)
+                        .map_err(|e| unexpected_payload("gemini stream payload", &element, &e))?;
+                    yield translator.translate(response)?;
+                }
+            }
+        };
+        Ok(Box::pin(stream))
+    }
+
+    async fn embeddings(&self, payload: EmbeddingsRequest) -> anyhow::Result<EmbeddingsResponse> {
+        let Some(input) = payload.input.texts() else {
+            return Err(Unsupported {
+                provider: self.name(),
+                operation: "token id embedding input",
+            }
+            .into());
+        };
+        let model = payload
+            .model
+            .strip_prefix("models/")
+            .unwrap_or(&payload.model);
+        let body = BatchEmbedRequest {
+            requests: input
+                .into_iter()
+                .map(|text| EmbedContentRequest {
+                    model: format!("models/{model}"),
+                    content: Content {
+                        role: None,
+                        parts: vec![Part::text(text.to_string())],
+                    },
+                    output_dimensionality: payload.dimensions,
+                })
+                .collect(),
+        };
+        let res = self.send(model, "batchEmbedContents", &body).await?;
+        let response = res
+            .json::<BatchEmbedResponse>()
+            .await
+            .context("failed to parse gemini embeddings response")?;
+        // Gemini doesn't report token counts for embeddings
+        Ok(EmbeddingsResponse::from_vectors(
+            payload.model.clone(),
+            response.embeddings.into_iter().map(|e| e.values).collect(),
+            None,
+        ))
+    }
+
+    async fn probe(&self, path: Option<&str>) -> anyhow::Result<()> {
+        let url = self.base_url.join(path.unwrap_or("/v1beta/models"))?;
+        let req = self
+            .client
+            .get(url)
+            .header("x-goog-api-key", &*self.keys.pick());
+        probe_request(self.name(), req).await
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentRequest {
+    contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<Content>,
+    generation_config: GenerationConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct Content {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct Part {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    inline_data: Option<Blob>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Blob {
+    mime_type: String,
+    data: String,
+}
+
+impl Part {
+    fn text(text: String) -> Self {
+        Self {
+            text: Some(text),
+            ..Default::default()
+        }
+    }
+
+    /// Gemini only takes images inline (or from its own file store), so
+    /// remote image URLs cannot be translated.
+    fn from_content(content: Option<MessageContent>) -> anyhow::Result<Vec<Self>> {
+        let parts = match content {
+            Some(MessageContent::Parts(parts)) => parts,
+            other => {
+                return Ok(vec![Self::text(
+                    other.map(MessageContent::into_text).unwrap_or_default(),
+                )])
+            }
+        };
+        let mut out = Vec::new();
+        for part in parts {
+            if let Some(text) = part.text {
+                out.push(Self::text(text));
+            } else if let Some(image) = part.image_url {
+                let data = DataUrl::parse(&image.url).ok_or(Unsupported {
+                    provider: "gemini",
+                    operation: "remote image urls",
+                })?;
+                out.push(Self {
+                    inline_data: Some(Blob {
+                        mime_type: data.media_type.to_string(),
+                        data: data.data.to_string(),
+                    }),
+                    ..Default::default()
+                });
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Debug, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct GenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_output_tokens: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchEmbedRequest {
+    requests: Vec<EmbedContentRequest>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EmbedContentRequest {
+    model: String,
+    content: Content,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output_dimensionality: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchEmbedResponse {
+    #[serde(default)]
+    embeddings: Vec<ContentEmbedding>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContentEmbedding {
+    values: Vec<f64>,
+}
+
+impl GenerateContentRequest {
+    fn from_chat(payload: ChatCompletionRequest) -> anyhow::Result<Self> {
+        if payload.uses_tools() {
+            return Err(Unsupported {
+                provider: "gemini",
+                operation: "tool calling",
+            }
+            .into());
+        }
+        let mut system = Vec::new();
+        let mut contents = Vec::new();
+        for m in payload.messages {
+            let role = match m.role.as_str() {
+                "system" | "developer" => {
+                    system.push(Part::text(m.text().into_owned()));
+                    continue;
+                }
+                "assistant" => "model",
+                _ => "user",
+            };
+            contents.push(Content {
+                role: Some(role.to_string()),
+                parts: Part::from_content(m.content)?,
+            });
+        }
+        Ok(Self {
+            contents,
+            system_instruction: (!system.is_empty()).then_some(Content {
+                role: None,
+                parts: system,
+            }),
+            generation_config: GenerationConfig {
+                temperature: payload.temperature,
+                top_p: payload.top_p,
+                max_output_tokens: payload.max_tokens,
+            },
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentResponse {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    #[serde(default)]
+    usage_metadata: Option<UsageMetadata>,
+    #[serde(default)]
+    response_id: Option<String>,
+    #[serde(default)]
+    model_version: Option<String>,
+    #[serde(default)]
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Candidate {
+    #[serde(default)]
+    index: Option<u32>,
+    #[serde(default)]
+    content: Option<Content>,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+impl Candidate {
+    fn text(&self) -> String {
+        self.content
+            .iter()
+            .flat_map(|c| c.parts.iter())
+            .filter_map(|p| p.text.as_deref())
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: Option<u32>,
+    #[serde(default)]
+    candidates_token_count: Option<u32>,
+    #[serde(default)]
+    total_token_count: Option<u32>,
+}
+
+impl From<UsageMetadata> for OpenAIUsage {
+    fn from(u: UsageMetadata) -> Self {
+        OpenAIUsage {
+            prompt_tokens: u.prompt_token_count,
+            completion_tokens: u.candidates_token_count,
+            total_tokens: u.total_token_count,
+        }
+    }
+}
+
+impl GenerateContentResponse {
+    fn into_completion(self, model: String) -> OpenAIChatCompletionResponse {
+        OpenAIChatCompletionResponse {
+            id: self.response_id,
+            object: Some("chat.completion".to_string()),
+            created: Some(unix_now()),
+            model: Some(self.model_version.unwrap_or(model)),
+            choices: self
+                .candidates
+                .iter()
+                .enumerate()
+                .map(|(i, c)| OpenAIChatCompletionChoice {
+                    index: Some(c.index.unwrap_or(i as u32)),
+                    message: Some(ChatMessage::new("assistant", c.text())),
+                    finish_reason: c.finish_reason.as_deref().map(map_finish_reason),
+                    extra: Default::default(),
+                })
+                .collect(),
+            usage: self.usage_metadata.map(Into::into),
+            extra: Default::default(),
+        }
+    }
+}
+
+struct StreamTranslator {
+    model: String,
+    created: i64,
+    first: bool,
+}
+
+impl StreamTranslator {
+    fn new(model: String) -> Self {
+        Self {
+            model,
+            created: unix_now(),
+            first: true,
+        }
+    }
+
+    fn translate(
+        &mut self,
+        response: GenerateContentResponse,
+    ) -> anyhow::Result<OpenAIStreamChunk> {
+        if let Some(error) = response.error {
+            return Err(anyhow::anyhow!("gemini stream error: {error}"));
+        }
+        let role = std::mem::take(&mut self.first).then(|| "assistant".to_string());
+        let finished = response
+            .candidates
+            .iter()
+            .any(|c| c.finish_reason.is_some());
+        let choices = response
+            .candidates
+            .iter()
+            .enumerate()
+            .map(|(i, c)| OpenAIChoice {
+                index: Some(c.index.unwrap_or(i as u32)),
+                delta: Some(OpenAIDelta {
+                    role: role.clone(),
+                    content: Some(c.text()),
+                    ..Default::default()
+                }),
+                finish_reason: c.finish_reason.as_deref().map(map_finish_reason),
+                extra: Default::default(),
+            })
+            .collect();
+        Ok(OpenAIStreamChunk {
+            id: response.response_id,
+            object: Some("chat.completion.chunk".to_string()),
+            created: Some(self.created),
+            model: Some(response.model_version.unwrap_or_else(|| self.model.clone())),
+            choices,
+            // usageMetadata is cumulative; only the final element carries the totals we report
+            usage: finished
+                .then_some(response.usage_metadata)
+                .flatten()
+                .map(Into::into),
+            extra: Default::default(),
+        })
+    }
+}
+
+fn map_finish_reason(reason: &str) -> String {
+    match reason {
+        "MAX_TOKENS" => "length",
+        "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" => "content_filter",
+        _ => "stop",
+    }
+    .to_string()
+}
+
+/// Incrementally extracts the top-level objects of a streamed JSON array.
+#[derive(Default)]
+struct JsonArraySplitter {
+    current: Vec<u8>,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl JsonArraySplitter {
+    fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut complete = Vec::new();
+        for &b in bytes {
+            if self.depth == 0 {
+                // Between elements: skip `[`, `,`, `]` and whitespace
+                if b == b'{' {
+                    self.depth = 1;
+                    self.current.push(b);
+                }
+                continue;
+            }
+            self.current.push(b);
+            if self.in_string {
+                match b {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match b {
+                b'"' => self.in_string = true,
+                b'{' | b'[' => self.depth += 1,
+                b'}' | b']' => {
+                    self.depth -= 1;
+                    if self.depth == 0 {
+                        complete.push(std::mem::take(&mut self.current));
+                    }
+                }
+                _ => {}
+            }
+        }
+        complete
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_streamed_array_across_reads() {
+        let body = br#"[{"candidates":[{"content":{"parts":[{"text":"a } \" ["}]}}]}
+,
+{"candidates":[],"usageMetadata":{"totalTokenCount":3}}]"#;
+        let mut splitter = JsonArraySplitter::default();
+        let mut elements = Vec::new();
+        for piece in body.chunks(7) {
+            elements.extend(splitter.push(piece));
+        }
+        assert_eq!(elements.len(), 2);
+        let first: GenerateContentResponse = serde_json::from_slice(&elements[0]).unwrap();
+        assert_eq!(first.candidates[0].text(), "a } \" [");
+    }
+
+    #[test]
+    fn maps_roles_and_usage() {
+        let req = ChatCompletionRequest {
+            model: "gemini-1.5-flash".to_string(),
+            messages: vec![
+                ChatMessage::new("system", "be brief"),
+                ChatMessage::new("assistant", "hello"),
+            ],
+            temperature: None,
+            top_p: None,
+            max_tokens: Some(64),
+            stream: None,
+            extra: Default::default(),
+        };
+        let body = GenerateContentRequest::from_chat(req).unwrap();
+        assert!(body.system_instruction.is_some());
+        assert_eq!(body.contents[0].role.as_deref(), Some("model"));
+
+        let mut translator = StreamTranslator::new("gemini-1.5-flash".to_string());
+        let last: GenerateContentResponse = serde_json::from_str(
+            r#"{"candidates":[{"content":{"parts":[{"text":"!"}]},"finishReason":"MAX_TOKENS"}],
+                "usageMetadata":{"promptTokenCount":4,"candidatesTokenCount":6,"totalTokenCount":10}}"#,
+        )
+        .unwrap();
+        let chunk = translator.translate(last).unwrap();
+        assert_eq!(chunk.choices[0].finish_reason.as_deref(), Some("length"));
+        assert_eq!(chunk.usage.unwrap().total_tokens, Some(10));
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+
+use crate::config::HealthProbe;
+
+use super::{
+    AudioStream, Capabilities, ChatCompletionRequest, ChatMessage, ChatStream, EmbeddingsRequest,
+    EmbeddingsResponse, FilesRequest, ImageGenerationRequest, ImagesResponse,
+    OpenAIChatCompletionResponse, Provider, RealtimeSocket, ResponsesRequest, ResponsesStream,
+    SpeechRequest, Transcript, TranscriptionRequest,
+};
+
+/// Consecutive probe results needed to flip an upstream's status.
+const THRESHOLD: u32 = 2;
+/// Probes slower than this count as failed.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Last known health of one upstream, as seen by its background probe.
+/// Upstreams start out healthy so a slow first probe doesn't block traffic.
+pub struct Health {
+    upstream: String,
+    healthy: AtomicBool,
+    // Probes in a row that disagree with `healthy`
+    streak: AtomicU32,
+}
+
+impl Health {
+    fn new(upstream: &str) -> Self {
+        let health = Self {
+            upstream: upstream.to_string(),
+            healthy: AtomicBool::new(true),
+            streak: AtomicU32::new(0),
+        };
+        health.export(true);
+        health
+    }
+
+    pub fn upstream(&self) -> &str {
+        &self.upstream
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    fn record(&self, ok: bool) {
+        if ok == self.is_healthy() {
+            self.streak.store(0, Ordering::Relaxed);
+            return;
+        }
+        if self.streak.fetch_add(1, Ordering::Relaxed) + 1 < THRESHOLD {
+            return;
+        }
+        self.streak.store(0, Ordering::Relaxed);
+        self.healthy.store(ok, Ordering::Relaxed);
+        self.export(ok);
+        if ok {
+            tracing::info!(upstream = %self.upstream, "upstream healthy again");
+        } else {
+            tracing::warn!(upstream = %self.upstream, "upstream marked unhealthy");
+        }
+    }
+
+    fn export(&self, healthy: bool) {
+        metrics::gauge!("upstream_healthy", "upstream" => self.upstream.clone()).set(if healthy {
+            1.0
+        } else {
+            0.0
+        });
+    }
+}
+
+/// Starts probing `provider` every `every` and returns the status it keeps up to date.
+pub fn spawn(
+    upstream: &str,
+    provider: Arc<dyn Provider>,
+    probe: Option<HealthProbe>,
+    every: Duration,
+) -> Arc<Health> {
+    let health = Arc::new(Health::new(upstream));
+    let status = health.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            let result = tokio::time::timeout(PROBE_TIMEOUT, run_probe(&*provider, probe.as_ref()))
+                .await
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("health probe timed out")));
+            if let Err(e) = &result {
+                tracing::debug!(upstream = %status.upstream, error = %e, "health probe failed");
+            }
+            status.record(result.is_ok());
+        }
+    });
+    health
+}
+
+async fn run_probe(provider: &dyn Provider, probe: Option<&HealthProbe>) -> anyhow::Result<()> {
+    match probe {
+        None => provider.probe(None).await,
+        Some(HealthProbe::Path(path)) => provider.probe(Some(path)).await,
+        Some(HealthProbe::Chat(model)) => provider
+            .chat_completion(ChatCompletionRequest {
+                model: model.clone(),
+                messages: vec![ChatMessage::new("user", "ping")],
+                temperature: None,
+                top_p: None,
+                max_tokens: Some(1),
+                stream: Some(false),
+                extra: Default::default(),
+            })
+            .await
+            .map(|_| ()),
+    }
+}
+
+/// Reports an upstream as unavailable while its probe says it is unhealthy.
+pub struct HealthGate {
+    inner: Arc<dyn Provider>,
+    health: Arc<Health>,
+}
+
+impl HealthGate {
+    pub fn new(inner: Arc<dyn Provider>, health: Arc<Health>) -> Self {
+        Self { inner, health }
+    }
+}
+
+#[async_trait]
+impl Provider for HealthGate {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn is_available(&self) -> bool {
+        self.health.is_healthy() && self.inner.is_available()
+    }
+
+    async fn chat_completion(
+        &self,
+        payload: ChatCompletionRequest,
+    ) -> anyhow::Result<OpenAIChatCompletionResponse> {
+        self.inner.chat_completion(payload).await
+    }
+
+    async fn chat_stream(&self, payload: ChatCompletionRequest) -> anyhow::Result<ChatStream> {
+        self.inner.chat_stream(payload).await
+    }
+
+    async fn embeddings(&self, payload: EmbeddingsRequest) -> anyhow::Result<EmbeddingsResponse> {
+        self.inner.embeddings(payload).await
+    }
+
+    async fn responses(&self, payload: ResponsesRequest) -> anyhow::Result<serde_json::Value> {
+        self.inner.responses(payload).await
+    }
+
+    async fn responses_stream(&self, payload: ResponsesRequest) -> anyhow::Result<ResponsesStream> {
+        self.inner.responses_stream(payload).await
+    }
+
+    async fn transcription(&self, request: TranscriptionRequest) -> anyhow::Result<Transcript> {
+        self.inner.transcription(request).await
+    }
+
+    async fn speech(&self, payload: SpeechRequest) -> anyhow::Result<AudioStream> {
+        self.inner.speech(payload).await
+    }
+
+    async fn image_generation(
+        &self,
+        payload: ImageGenerationRequest,
+    ) -> anyhow::Result<ImagesResponse> {
+        self.inner.image_generation(payload).await
+    }
+
+    async fn realtime(&self, model: &str) -> anyhow::Result<RealtimeSocket> {
+        self.inner.realtime(model).await
+    }
+
+    async fn files(&self, request: FilesRequest) -> anyhow::Result<reqwest::Response> {
+        self.inner.files(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_flips_after_consecutive_results() {
+        let h = Health::new("test");
+        h.record(false);
+        assert!(h.is_healthy());
+        h.record(true);
+        h.record(false);
+        assert!(h.is_healthy());
+        h.record(false);
+        assert!(!h.is_healthy());
+        h.record(true);
+        h.record(true);
+        assert!(h.is_healthy());
+    }
+}
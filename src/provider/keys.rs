@@ -0,0 +1,231 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock, Weak,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Context;
+
+use crate::config::KeySource;
+
+use super::UpstreamError;
+
+/// Upstream API keys used in rotation. Keys that come back with 401 or 429
+/// are benched for a while; if every key is benched the one that comes back
+/// soonest is used rather than failing the request outright.
+pub struct KeyPool {
+    upstream: String,
+    quarantine_for: Duration,
+    inline: Vec<Arc<str>>,
+    keys: RwLock<Vec<Arc<str>>>,
+    quarantined: Mutex<HashMap<Arc<str>, Instant>>,
+    next: AtomicUsize,
+}
+
+impl KeyPool {
+    pub fn single(upstream: &str, key: String) -> Arc<Self> {
+        Arc::new(Self::new(upstream, vec![key.into()], Duration::ZERO))
+    }
+
+    /// Builds the pool for `source`, watching its key file (if any) every `poll`.
+    pub fn from_source(
+        upstream: &str,
+        source: &KeySource,
+        quarantine_for: Duration,
+        poll: Duration,
+    ) -> anyhow::Result<Arc<Self>> {
+        let mut pool = Self::new(
+            upstream,
+            source.keys.iter().map(|k| Arc::from(k.as_str())).collect(),
+            quarantine_for,
+        );
+        pool.inline = pool.keys.get_mut().expect("key lock poisoned").clone();
+        let Some(file) = source.file.as_ref() else {
+            if pool.inline.is_empty() {
+                anyhow::bail!("no api keys configured for {upstream}");
+            }
+            return Ok(Arc::new(pool));
+        };
+
+        let path = PathBuf::from(file);
+        let pool = Arc::new(pool);
+        pool.load(&path)?;
+        tokio::spawn(watch(Arc::downgrade(&pool), path, poll));
+        Ok(pool)
+    }
+
+    fn new(upstream: &str, keys: Vec<Arc<str>>, quarantine_for: Duration) -> Self {
+        Self {
+            upstream: upstream.to_string(),
+            quarantine_for,
+            inline: Vec::new(),
+            keys: RwLock::new(keys),
+            quarantined: Mutex::new(HashMap::new()),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Next usable key in round-robin order.
+    pub fn pick(&self) -> Arc<str> {
+        let keys = self.keys.read().expect("key lock poisoned");
+        let quarantined = self.quarantined.lock().expect("key lock poisoned");
+        let now = Instant::now();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..keys.len())
+            .map(|i| &keys[(start + i) % keys.len()])
+            .find(|k| quarantined.get(*k).is_none_or(|until| *until <= now))
+            .or_else(|| keys.iter().min_by_key(|k| quarantined.get(*k)))
+            .cloned()
+            .expect("key pools are never empty")
+    }
+
+    /// Benches `key` if the upstream rejected it as unauthorized or rate limited.
+    pub fn observe<T>(&self, key: &Arc<str>, result: &anyhow::Result<T>) {
+        let Err(err) = result else {
+            return;
+        };
+        let Some(e) = err.chain().find_map(|c| c.downcast_ref::<UpstreamError>()) else {
+            return;
+        };
+        let (reason, duration) = match e.status.as_u16() {
+            401 => ("unauthorized", self.quarantine_for * 10),
+            429 => ("rate_limited", e.retry_after.unwrap_or(self.quarantine_for)),
+            _ => return,
+        };
+        if duration.is_zero() {
+            return;
+        }
+        self.quarantined
+            .lock()
+            .expect("key lock poisoned")
+            .insert(key.clone(), Instant::now() + duration);
+        metrics::counter!("api_key_quarantined_total", "upstream" => self.upstream.clone(), "reason" => reason)
+            .increment(1);
+        tracing::warn!(upstream = %self.upstream, key = %hint(key), reason, ?duration, "api key quarantined");
+    }
+
+    /// Replaces the file-backed keys, keeping the quarantine of keys that stay.
+    fn load(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read key file {}", path.display()))?;
+        let mut keys = self.inline.clone();
+        keys.extend(
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(Arc::from),
+        );
+        if keys.is_empty() {
+            anyhow::bail!("key file {} has no keys", path.display());
+        }
+        self.quarantined
+            .lock()
+            .expect("key lock poisoned")
+            .retain(|k, _| keys.contains(k));
+        metrics::gauge!("api_keys_loaded", "upstream" => self.upstream.clone())
+            .set(keys.len() as f64);
+        *self.keys.write().expect("key lock poisoned") = keys;
+        Ok(())
+    }
+}
+
+/// Reloads the key file whenever its modification time changes, until the
+/// pool is dropped. A bad file is logged and the current keys are kept.
+async fn watch(pool: Weak<KeyPool>, path: PathBuf, every: Duration) {
+    let modified =
+        |path: &Path| -> Option<SystemTime> { std::fs::metadata(path).ok()?.modified().ok() };
+    let mut last = modified(&path);
+    let mut interval = tokio::time::interval(every);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let Some(pool) = pool.upgrade() else {
+            return;
+        };
+        let current = modified(&path);
+        if current == last {
+            continue;
+        }
+        last = current;
+        match pool.load(&path) {
+            Ok(()) => tracing::info!(upstream = %pool.upstream, "api keys reloaded"),
+            Err(e) => {
+                tracing::warn!(upstream = %pool.upstream, error = %e, "api key reload failed")
+            }
+        }
+    }
+}
+
+/// Enough of a key to tell keys apart in logs.
+fn hint(key: &str) -> String {
+    let tail = key
+        .char_indices()
+        .rev()
+        .nth(3)
+        .map_or(key, |(i, _)| &key[i..]);
+    format!("...{tail}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(keys: &[&str]) -> KeyPool {
+        KeyPool::new(
+            "test",
+            keys.iter().map(|k| Arc::from(*k)).collect(),
+            Duration::from_secs(60),
+        )
+    }
+
+    fn rejected(code: u16) -> anyhow::Result<()> {
+        Err(UpstreamError {
+            provider: "openai",
+            status: reqwest::StatusCode::from_u16(code).unwrap(),
+            body: String::new(),
+            retry_after: None,
+        }
+        .into())
+    }
+
+    #[test]
+    fn rotates_and_skips_quarantined_keys() {
+        let p = pool(&["a", "b", "c"]);
+        let picks: Vec<_> = (0..3).map(|_| p.pick().to_string()).collect();
+        assert_eq!(picks, ["a", "b", "c"]);
+
+        p.observe(&Arc::from("b"), &rejected(429));
+        p.observe(&Arc::from("c"), &rejected(500));
+        let picks: Vec<_> = (0..4).map(|_| p.pick().to_string()).collect();
+        assert_eq!(picks, ["a", "c", "c", "a"]);
+
+        // With everything benched, the key that comes back first is used
+        p.observe(&Arc::from("a"), &rejected(401));
+        p.observe(&Arc::from("c"), &rejected(401));
+        assert_eq!(&*p.pick(), "b");
+    }
+
+    #[test]
+    fn reload_keeps_quarantine_of_remaining_keys() {
+        let path = std::env::temp_dir().join(format!("keys-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "a\n# comment\nb\n").unwrap();
+        let p = pool(&[]);
+        p.load(&path).unwrap();
+        p.observe(&Arc::from("a"), &rejected(429));
+
+        std::fs::write(&path, "a\nc\n").unwrap();
+        p.load(&path).unwrap();
+        let picks: Vec<_> = (0..2).map(|_| p.pick().to_string()).collect();
+        assert_eq!(picks, ["c", "c"]);
+
+        std::fs::write(&path, "\n").unwrap();
+        assert!(p.load(&path).is_err());
+        assert_eq!(&*p.pick(), "c");
+        std::fs::remove_file(path).unwrap();
+    }
+}
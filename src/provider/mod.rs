@@ -1 +1,1211 @@
+pub mod anthropic;
+pub mod breaker;
+pub mod gemini;
+pub mod health;
+pub mod keys;
+pub mod llamacpp;
+pub mod ollama;
 pub mod openai;
+pub mod pool;
+pub mod retry;
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use futures::{Stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncBufReadExt;
+use tokio_stream::wrappers::LinesStream;
+use tokio_util::io::StreamReader;
+
+use crate::config::AppConfig;
+
+/// Stream of normalized chunks produced by a provider. The stream ends when the
+/// upstream signals completion; callers are responsible for emitting `[DONE]`.
+pub type BoxStream<T> = Pin<Box<dyn Stream<Item = anyhow::Result<T>> + Send>>;
+pub type ChatStream = BoxStream<OpenAIStreamChunk>;
+/// Typed Responses API events (`{"type": "response.output_text.delta", ...}`).
+pub type ResponsesStream = BoxStream<serde_json::Value>;
+/// An open OpenAI Realtime API session.
+pub type RealtimeSocket =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+/// Encoded audio from a speech request, in the requested `response_format`.
+pub type AudioStream = BoxStream<bytes::Bytes>;
+
+/// What a backend can do, so handlers can adapt instead of failing upstream.
+#[derive(Debug, Clone, Copy)]
+pub struct Capabilities {
+    pub streaming: bool,
+}
+
+/// An upstream LLM backend. Requests and responses use the OpenAI chat shape as
+/// the internal model; implementations translate to and from their own wire format.
+#[async_trait]
+pub trait Provider: Send + Sync {
+    /// Short identifier used in logs and metrics (e.g. `openai`).
+    fn name(&self) -> &'static str;
+
+    fn capabilities(&self) -> Capabilities;
+
+    /// Whether a request sent now would be attempted at all; `false` while a
+    /// circuit breaker is open so pools can steer around the upstream.
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    async fn chat_completion(
+        &self,
+        payload: ChatCompletionRequest,
+    ) -> anyhow::Result<OpenAIChatCompletionResponse>;
+
+    async fn chat_stream(&self, payload: ChatCompletionRequest) -> anyhow::Result<ChatStream>;
+
+    /// Embeds `payload.input`. Backends without an embeddings API keep the
+    /// default, which fails with [`Unsupported`].
+    async fn embeddings(&self, payload: EmbeddingsRequest) -> anyhow::Result<EmbeddingsResponse> {
+        let _ = payload;
+        Err(Unsupported {
+            provider: self.name(),
+            operation: "embeddings",
+        }
+        .into())
+    }
+
+    /// OpenAI Responses API call, answered with the upstream's response object.
+    async fn responses(&self, payload: ResponsesRequest) -> anyhow::Result<serde_json::Value> {
+        let _ = payload;
+        Err(Unsupported {
+            provider: self.name(),
+            operation: "the responses api",
+        }
+        .into())
+    }
+
+    async fn responses_stream(&self, payload: ResponsesRequest) -> anyhow::Result<ResponsesStream> {
+        let _ = payload;
+        Err(Unsupported {
+            provider: self.name(),
+            operation: "the responses api",
+        }
+        .into())
+    }
+
+    /// Speech-to-text. The transcript comes back in the requested
+    /// `response_format`.
+    async fn transcription(&self, request: TranscriptionRequest) -> anyhow::Result<Transcript> {
+        let _ = request;
+        Err(Unsupported {
+            provider: self.name(),
+            operation: "audio transcription",
+        }
+        .into())
+    }
+
+    /// Text-to-speech, streamed as the upstream produces it.
+    async fn speech(&self, payload: SpeechRequest) -> anyhow::Result<AudioStream> {
+        let _ = payload;
+        Err(Unsupported {
+            provider: self.name(),
+            operation: "speech",
+        }
+        .into())
+    }
+
+    async fn image_generation(
+        &self,
+        payload: ImageGenerationRequest,
+    ) -> anyhow::Result<ImagesResponse> {
+        let _ = payload;
+        Err(Unsupported {
+            provider: self.name(),
+            operation: "image generation",
+        }
+        .into())
+    }
+
+    /// Opens a Realtime API WebSocket session with `model`.
+    async fn realtime(&self, model: &str) -> anyhow::Result<RealtimeSocket> {
+        let _ = model;
+        Err(Unsupported {
+            provider: self.name(),
+            operation: "the realtime api",
+        }
+        .into())
+    }
+
+    /// OpenAI Files or Batches API call, answered with the raw upstream
+    /// response so file contents can be read as they are.
+    async fn files(&self, request: FilesRequest) -> anyhow::Result<reqwest::Response> {
+        let _ = request;
+        Err(Unsupported {
+            provider: self.name(),
+            operation: "the files and batches api",
+        }
+        .into())
+    }
+
+    /// Cheap liveness check for background health probing: `GET path`, or the
+    /// provider's own default (usually its models list) when `None`.
+    async fn probe(&self, path: Option<&str>) -> anyhow::Result<()> {
+        let _ = path;
+        Ok(())
+    }
+}
+
+/// Backends configured for this gateway instance, keyed by their configured
+/// name: [`Provider::name`] for the built-in providers, or the name given in
+/// `OPENAI_UPSTREAMS` / `UPSTREAM_POOLS`.
+#[derive(Clone)]
+pub struct ProviderRegistry {
+    providers: HashMap<String, Arc<dyn Provider>>,
+    health: Vec<Arc<health::Health>>,
+    default: String,
+}
+
+impl ProviderRegistry {
+    pub fn from_config(cfg: &AppConfig) -> anyhow::Result<Self> {
+        let mut providers: HashMap<String, Arc<dyn Provider>> = HashMap::new();
+        fn register(
+            providers: &mut HashMap<String, Arc<dyn Provider>>,
+            name: &str,
+            p: Arc<dyn Provider>,
+        ) -> anyhow::Result<()> {
+            if providers.insert(name.to_string(), p).is_some() {
+                anyhow::bail!("upstream name {name} is configured twice");
+            }
+            Ok(())
+        }
+
+        let quarantine = Duration::from_secs(cfg.key_quarantine_secs);
+        let poll = Duration::from_secs(cfg.key_file_poll_secs);
+        let key_pool = |name: &str, source: &crate::config::KeySource| {
+            keys::KeyPool::from_source(name, source, quarantine, poll)
+        };
+
+        let openai_keys = cfg
+            .openai_api_keys
+            .is_configured()
+            .then(|| key_pool("openai", &cfg.openai_api_keys))
+            .transpose()?;
+        if let Some(keys) = openai_keys.as_ref() {
+            register(
+                &mut providers,
+                "openai",
+                Arc::new(openai::OpenAIProvider::new(
+                    keys.clone(),
+                    cfg.openai_base_url.clone(),
+                )?),
+            )?;
+        }
+        for upstream in &cfg.openai_upstreams {
+            let keys = match upstream.api_key.clone() {
+                Some(key) => keys::KeyPool::single(&upstream.name, key),
+                None => openai_keys
+                    .clone()
+                    .ok_or_else(|| anyhow::anyhow!("no api key for upstream {}", upstream.name))?,
+            };
+            register(
+                &mut providers,
+                &upstream.name,
+                Arc::new(openai::OpenAIProvider::new(
+                    keys,
+                    Some(upstream.base_url.clone()),
+                )?),
+            )?;
+        }
+        if let Some(endpoint) = cfg.azure_openai_endpoint.as_ref() {
+            if cfg.azure_openai_api_keys.is_configured() {
+                register(
+                    &mut providers,
+                    "azure",
+                    Arc::new(openai::OpenAIProvider::new_azure(
+                        key_pool("azure", &cfg.azure_openai_api_keys)?,
+                        endpoint,
+                        cfg.azure_openai_api_version.clone(),
+                        cfg.azure_openai_deployments.clone(),
+                    )?),
+                )?;
+            }
+        }
+        if cfg.anthropic_api_keys.is_configured() {
+            register(
+                &mut providers,
+                "anthropic",
+                Arc::new(anthropic::AnthropicProvider::new(
+                    key_pool("anthropic", &cfg.anthropic_api_keys)?,
+                    cfg.anthropic_base_url.clone(),
+                    cfg.anthropic_version.clone(),
+                )?),
+            )?;
+        }
+        if cfg.gemini_api_keys.is_configured() {
+            register(
+                &mut providers,
+                "gemini",
+                Arc::new(gemini::GeminiProvider::new(
+                    key_pool("gemini", &cfg.gemini_api_keys)?,
+                    cfg.gemini_base_url.clone(),
+                )?),
+            )?;
+        }
+        if let Some(url) = cfg.ollama_base_url.as_ref() {
+            register(
+                &mut providers,
+                "ollama",
+                Arc::new(ollama::OllamaProvider::new(Some(url.clone()))?),
+            )?;
+        }
+        if let Some(url) = cfg.llamacpp_base_url.as_ref() {
+            register(
+                &mut providers,
+                "llamacpp",
+                Arc::new(llamacpp::LlamaCppProvider::new(Some(url.clone()))?),
+            )?;
+        }
+
+        if let Some(name) = cfg
+            .health_probes
+            .keys()
+            .find(|name| !providers.contains_key(*name))
+        {
+            anyhow::bail!("HEALTH_PROBES references unknown upstream {name}");
+        }
+
+        // Every plain upstream gets its own breaker, retries and health probe;
+        // pools see the wrapped members. Retries sit outside the breaker so each
+        // attempt counts, and probes talk to the upstream directly.
+        let mut health = Vec::new();
+        for (name, p) in providers.iter_mut() {
+            let guarded = Arc::new(breaker::BreakerProvider::new(
+                name,
+                p.clone(),
+                cfg.circuit_breaker,
+            ));
+            let retried: Arc<dyn Provider> =
+                Arc::new(retry::RetryProvider::new(name, guarded, cfg.retry));
+            *p = match cfg.health_check_secs {
+                Some(secs) => {
+                    let status = health::spawn(
+                        name,
+                        p.clone(),
+                        cfg.health_probes.get(name).cloned(),
+                        Duration::from_secs(secs),
+                    );
+                    health.push(status.clone());
+                    Arc::new(health::HealthGate::new(retried, status))
+                }
+                None => retried,
+            };
+        }
+
+        // Pools are built last and may only contain plain upstreams
+        let mut pools = Vec::new();
+        for pool in &cfg.upstream_pools {
+            let members = pool
+                .members
+                .iter()
+                .map(|m| {
+                    let provider = providers.get(&m.upstream).cloned().ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "pool {} references unknown upstream {}",
+                            pool.name,
+                            m.upstream
+                        )
+                    })?;
+                    Ok(pool::Member::new(&m.upstream, provider, m.weight))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let mut provider = pool::PoolProvider::new(&pool.name, pool.strategy, members)?;
+            if let Some(ms) = cfg.pool_hedge_ms.get(&pool.name) {
+                provider = provider.with_hedging(Duration::from_millis(*ms));
+            }
+            pools.push((pool.name.clone(), provider));
+        }
+        for (name, pool) in pools {
+            register(&mut providers, &name, Arc::new(pool))?;
+        }
+
+        let default = match cfg.default_provider.as_ref() {
+            Some(name) if !providers.contains_key(name) => {
+                anyhow::bail!("DEFAULT_PROVIDER={name} is not configured")
+            }
+            Some(name) => name.clone(),
+            None => [
+                "openai",
+                "azure",
+                "anthropic",
+                "gemini",
+                "ollama",
+                "llamacpp",
+            ]
+            .into_iter()
+            .find(|name| providers.contains_key(*name))
+            .ok_or_else(|| anyhow::anyhow!("no upstream provider configured"))?
+            .to_string(),
+        };
+
+        Ok(Self {
+            providers,
+            health,
+            default,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Provider>> {
+        self.providers.get(name).cloned()
+    }
+
+    pub fn default_name(&self) -> &str {
+        &self.default
+    }
+
+    /// Probed upstreams; empty when health checks are disabled.
+    pub fn health(&self) -> &[Arc<health::Health>] {
+        &self.health
+    }
+}
+
+/// A non-success HTTP response from an upstream, kept typed so callers can
+/// decide whether another upstream is worth trying.
+#[derive(Debug, thiserror::Error)]
+#[error("{provider} error: {status} - {body}")]
+pub struct UpstreamError {
+    pub provider: &'static str,
+    pub status: reqwest::StatusCode,
+    pub body: String,
+    /// How long the upstream asked us to wait, from `Retry-After`.
+    pub retry_after: Option<Duration>,
+}
+
+/// The provider has no equivalent of the requested operation.
+#[derive(Debug, thiserror::Error)]
+#[error("{provider} does not support {operation}")]
+pub struct Unsupported {
+    pub provider: &'static str,
+    pub operation: &'static str,
+}
+
+/// Passes successful responses through and turns the rest into [`UpstreamError`].
+pub(crate) async fn check_status(
+    provider: &'static str,
+    res: reqwest::Response,
+) -> anyhow::Result<reqwest::Response> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+    let retry_after = res
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_retry_after);
+    let body = res.text().await.unwrap_or_default();
+    Err(UpstreamError {
+        provider,
+        status,
+        body,
+        retry_after,
+    }
+    .into())
+}
+
+/// `Retry-After` is either delay-seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at =
+        time::OffsetDateTime::parse(value, &time::format_description::well_known::Rfc2822).ok()?;
+    let delay = at - time::OffsetDateTime::now_utc();
+    Some(delay.try_into().unwrap_or(Duration::ZERO))
+}
+
+/// Failures that say something about the upstream rather than the request:
+/// connection errors, timeouts, 5xx and 429.
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<UpstreamError>() {
+            return e.status.is_server_error()
+                || e.status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_connect() || e.is_timeout();
+        }
+        false
+    })
+}
+
+/// Sends a health probe request and checks that it succeeded.
+pub(crate) async fn probe_request(
+    provider: &'static str,
+    req: reqwest::RequestBuilder,
+) -> anyhow::Result<()> {
+    let res = req.send().await.context("probe send failed")?;
+    check_status(provider, res).await.map(|_| ())
+}
+
+/// Waits for the first chunk so errors up to that point surface from the call
+/// itself, then hands back the complete stream.
+pub(crate) async fn peek_first<T: Send + 'static>(
+    mut stream: BoxStream<T>,
+) -> anyhow::Result<BoxStream<T>> {
+    Ok(match stream.next().await.transpose()? {
+        Some(first) => Box::pin(futures::stream::once(async { Ok(first) }).chain(stream)),
+        None => stream,
+    })
+}
+
+/// Splits a response body into text lines, the common framing for SSE and NDJSON.
+pub(crate) fn body_lines(
+    res: reqwest::Response,
+) -> impl Stream<Item = anyhow::Result<String>> + Send {
+    let stream = res.bytes_stream().map_err(std::io::Error::other);
+    let reader = tokio::io::BufReader::new(StreamReader::new(stream));
+    LinesStream::new(reader.lines()).map(|l| l.map_err(|e| e.into()))
+}
+
+pub(crate) fn unix_now() -> i64 {
+    time::OffsetDateTime::now_utc().unix_timestamp()
+}
+
+/// Id for responses synthesized by the gateway when the upstream has none.
+pub(crate) fn completion_id() -> String {
+    format!("chatcmpl-{}", uuid::Uuid::new_v4().simple())
+}
+
+/// Returns the payload of an SSE `data:` line, or `None` for any other line.
+pub(crate) fn sse_data(line: &str) -> Option<&str> {
+    let data = line.strip_prefix("data:")?;
+    Some(data.strip_prefix(' ').unwrap_or(data))
+}
+
+/// Error for a streamed payload that doesn't parse as `what`. Payloads are
+/// model output that hasn't been redacted yet, and serde's messages can
+/// quote them, so the error only says where parsing stopped. The payload's
+/// length and a hash of it are logged at debug level to match it up.
+pub(crate) fn unexpected_payload(
+    what: &str,
+    payload: &[u8],
+    err: &serde_json::Error,
+) -> anyhow::Error {
+    let mut hasher = DefaultHasher::new();
+    payload.hash(&mut hasher);
+    tracing::debug!(
+        bytes = payload.len(),
+        hash = %format!("{:08x}", hasher.finish() >> 32),
+        "unexpected {what}"
+    );
+    anyhow::anyhow!(
+        "unexpected {what} (line {}, column {})",
+        err.line(),
+        err.column()
+    )
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    /// Fields the gateway does not model, passed through untouched.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Request fields that declare tools or steer the model towards them.
+const TOOL_FIELDS: [&str; 4] = ["tools", "tool_choice", "functions", "function_call"];
+
+impl ChatCompletionRequest {
+    /// Whether the request offers tools or replays a tool-calling exchange,
+    /// which backends that only translate text can't carry.
+    pub fn uses_tools(&self) -> bool {
+        TOOL_FIELDS
+            .iter()
+            .any(|field| self.extra.contains_key(*field))
+            || self.messages.iter().any(|m| {
+                m.tool_calls.is_some()
+                    || m.extra.contains_key("function_call")
+                    || matches!(m.role.as_str(), "tool" | "function")
+            })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ChatMessage {
+    pub role: String,
+    /// Null on assistant turns that only call tools.
+    #[serde(default)]
+    pub content: Option<MessageContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// The call a `tool` message answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// Fields the gateway does not model (`refusal`, `audio`,
+    /// `reasoning_content`, ...), passed through after redaction.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ChatMessage {
+    pub fn new(role: &str, content: impl Into<String>) -> Self {
+        Self {
+            role: role.to_string(),
+            content: Some(MessageContent::Text(content.into())),
+            ..Default::default()
+        }
+    }
+
+    /// The text content, empty for tool-call-only turns. Used by backends
+    /// that only translate text.
+    pub fn text(&self) -> Cow<'_, str> {
+        self.content
+            .as_ref()
+            .map(MessageContent::text)
+            .unwrap_or_default()
+    }
+}
+
+/// Message content: a plain string, or an array of typed parts mixing text
+/// and images.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl MessageContent {
+    /// The text parts joined by newlines.
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            Self::Text(text) => Cow::Borrowed(text),
+            Self::Parts(parts) => Cow::Owned(
+                parts
+                    .iter()
+                    .filter_map(|p| p.text.as_deref())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+        }
+    }
+
+    pub fn into_text(self) -> String {
+        match self {
+            Self::Text(text) => text,
+            parts => parts.text().into_owned(),
+        }
+    }
+
+    /// Image URLs (remote or `data:`) in part order.
+    pub fn images(&self) -> impl Iterator<Item = &str> {
+        let parts = match self {
+            Self::Text(_) => &[][..],
+            Self::Parts(parts) => parts,
+        };
+        parts
+            .iter()
+            .filter_map(|p| p.image_url.as_ref().map(|i| i.url.as_str()))
+    }
+}
+
+/// One entry of array content. Part types other than `text` and `image_url`
+/// (`input_audio`, `file`, ...) pass through in `extra`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ContentPart {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<ImageUrl>,
+    /// Fields the gateway does not model, passed through untouched.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ImageUrl {
+    pub url: String,
+    /// Fields the gateway does not model (`detail`), passed through untouched.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A base64 `data:` URL split into its media type and payload.
+#[derive(Debug, PartialEq)]
+pub struct DataUrl<'a> {
+    pub media_type: &'a str,
+    pub data: &'a str,
+}
+
+impl<'a> DataUrl<'a> {
+    /// Returns `None` for remote URLs and for data URLs that are not base64.
+    pub fn parse(url: &'a str) -> Option<Self> {
+        let (meta, data) = url.strip_prefix("data:")?.split_once(',')?;
+        let media_type = meta.strip_suffix(";base64")?;
+        Some(Self { media_type, data })
+    }
+
+    /// Size of the decoded payload, without decoding it.
+    pub fn decoded_len(&self) -> usize {
+        base64_decoded_len(self.data)
+    }
+}
+
+/// Number of bytes `data` decodes to, without decoding it.
+fn base64_decoded_len(data: &str) -> usize {
+    data.trim_end_matches('=').len() * 3 / 4
+}
+
+/// A tool call on an assistant message, or a piece of one in a stream delta,
+/// where `index` says which call it continues and `arguments` arrives in
+/// fragments.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ToolCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<FunctionCall>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FunctionCall {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// JSON-encoded arguments, as produced by the model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct OpenAIStreamChunk {
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub choices: Vec<OpenAIChoice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<OpenAIUsage>,
+    /// Fields the gateway does not model, passed through untouched.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct OpenAIChoice {
+    pub index: Option<u32>,
+    pub delta: Option<OpenAIDelta>,
+    pub finish_reason: Option<String>,
+    /// Fields the gateway does not model, passed through untouched.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct OpenAIDelta {
+    pub role: Option<String>,
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    /// Fields the gateway does not model (`reasoning_content`, `audio`, ...),
+    /// passed through after redaction.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct OpenAIChatCompletionResponse {
+    pub id: Option<String>,
+    pub object: Option<String>,
+    pub created: Option<i64>,
+    pub model: Option<String>,
+    pub choices: Vec<OpenAIChatCompletionChoice>,
+    pub usage: Option<OpenAIUsage>,
+    /// Fields the gateway does not model, passed through untouched.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct OpenAIChatCompletionChoice {
+    pub index: Option<u32>,
+    pub message: Option<ChatMessage>,
+    pub finish_reason: Option<String>,
+    /// Fields the gateway does not model, passed through untouched.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct OpenAIUsage {
+    pub prompt_tokens: Option<u32>,
+    pub completion_tokens: Option<u32>,
+    pub total_tokens: Option<u32>,
+}
+
+impl OpenAIChatCompletionResponse {
+    /// Re-frames a complete response as a single stream chunk, for backends that
+    /// cannot stream.
+    pub fn into_stream_chunk(self) -> OpenAIStreamChunk {
+        OpenAIStreamChunk {
+            id: self.id,
+            object: Some("chat.completion.chunk".to_string()),
+            created: self.created,
+            model: self.model,
+            choices: self
+                .choices
+                .into_iter()
+                .map(|c| OpenAIChoice {
+                    index: c.index,
+                    delta: c.message.map(|m| OpenAIDelta {
+                        role: Some(m.role),
+                        content: m.content.map(MessageContent::into_text),
+                        // Stream deltas identify each call by its position
+                        tool_calls: m.tool_calls.map(|calls| {
+                            calls
+                                .into_iter()
+                                .enumerate()
+                                .map(|(i, call)| ToolCall {
+                                    index: Some(i as u32),
+                                    ..call
+                                })
+                                .collect()
+                        }),
+                        extra: m.extra,
+                        ..Default::default()
+                    }),
+                    finish_reason: c.finish_reason,
+                    extra: c.extra,
+                })
+                .collect(),
+            usage: self.usage,
+            extra: self.extra,
+        }
+    }
+}
+
+/// A Files or Batches API call, with `path` relative to `/v1/` (`files`,
+/// `files/{id}/content`, `batches/{id}/cancel`, ...).
+#[derive(Debug, Clone)]
+pub struct FilesRequest {
+    pub method: reqwest::Method,
+    pub path: String,
+    pub query: Option<String>,
+    pub body: FilesBody,
+}
+
+#[derive(Debug, Clone)]
+pub enum FilesBody {
+    Empty,
+    Json(serde_json::Value),
+    /// A `multipart/form-data` file upload.
+    Upload {
+        purpose: String,
+        filename: String,
+        content: bytes::Bytes,
+    },
+}
+
+/// A transcription upload. The audio is spooled to a temporary file (it is
+/// capped by `AUDIO_MAX_BYTES`) so retries and fallback tiers can stream it
+/// again without holding it in memory.
+#[derive(Debug, Clone)]
+pub struct TranscriptionRequest {
+    pub model: String,
+    pub file: AudioUpload,
+    pub filename: String,
+    pub content_type: Option<String>,
+    /// The other form fields (`language`, `prompt`, `response_format`, ...), as sent.
+    pub fields: Vec<(String, String)>,
+}
+
+/// An uploaded recording spooled to disk. The file is removed once the last
+/// clone is dropped.
+#[derive(Debug, Clone)]
+pub struct AudioUpload {
+    file: Arc<SpooledFile>,
+    len: u64,
+}
+
+#[derive(Debug)]
+pub struct SpooledFile(std::path::PathBuf);
+
+impl SpooledFile {
+    /// Creates an empty file under the temporary directory.
+    pub async fn create() -> std::io::Result<(Self, tokio::fs::File)> {
+        let path = std::env::temp_dir().join(format!("gateway-audio-{}", uuid::Uuid::new_v4()));
+        let file = tokio::fs::File::create(&path).await?;
+        Ok((Self(path), file))
+    }
+}
+
+impl Drop for SpooledFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            tracing::warn!(path = %self.0.display(), error = %e, "failed to remove spooled upload");
+        }
+    }
+}
+
+impl AudioUpload {
+    /// An upload of `len` bytes written to `file`.
+    pub fn new(file: SpooledFile, len: u64) -> Self {
+        Self {
+            file: Arc::new(file),
+            len,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// A multipart part streaming the upload from disk.
+    pub async fn part(&self) -> anyhow::Result<reqwest::multipart::Part> {
+        let file = tokio::fs::File::open(&self.file.0)
+            .await
+            .context("failed to open spooled upload")?;
+        let body = reqwest::Body::wrap_stream(tokio_util::io::ReaderStream::new(file));
+        Ok(reqwest::multipart::Part::stream_with_length(body, self.len))
+    }
+}
+
+/// A transcript: JSON for the `json` and `verbose_json` formats, plain
+/// text for `text`, `srt` and `vtt`.
+#[derive(Debug, Clone)]
+pub enum Transcript {
+    Json(serde_json::Value),
+    Text(String),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SpeechRequest {
+    pub model: String,
+    pub input: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<String>,
+    /// `voice`, `speed`, `instructions`, ...
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImageGenerationRequest {
+    /// OpenAI falls back to `dall-e-2` when no model is given.
+    #[serde(default = "default_image_model")]
+    pub model: String,
+    pub prompt: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+fn default_image_model() -> String {
+    "dall-e-2".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImagesResponse {
+    #[serde(default)]
+    pub data: Vec<GeneratedImage>,
+    /// `created`, `usage`, ...
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GeneratedImage {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub b64_json: Option<Base64Image>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revised_prompt: Option<String>,
+    /// `url`, when the image is hosted rather than inlined.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Inline image data. Its `Debug` output only gives the size, so generated
+/// images never end up in logs.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(transparent)]
+pub struct Base64Image(pub String);
+
+impl Base64Image {
+    pub fn decoded_len(&self) -> usize {
+        base64_decoded_len(&self.0)
+    }
+}
+
+impl std::fmt::Debug for Base64Image {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<{} bytes of base64>", self.0.len())
+    }
+}
+
+/// Body of a Responses API call. Only the fields the gateway acts on are
+/// typed; everything else is forwarded untouched.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResponsesRequest {
+    pub model: String,
+    /// A string or an array of input items.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EmbeddingsRequest {
+    pub model: String,
+    pub input: EmbeddingInput,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding_format: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+/// The forms OpenAI accepts for `input`: text or pre-tokenized, one or many.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    Text(String),
+    Texts(Vec<String>),
+    Tokens(Vec<u32>),
+    TokenBatches(Vec<Vec<u32>>),
+}
+
+impl EmbeddingInput {
+    /// The inputs as text, or `None` for token ids.
+    pub fn texts(&self) -> Option<Vec<&str>> {
+        match self {
+            EmbeddingInput::Text(t) => Some(vec![t.as_str()]),
+            EmbeddingInput::Texts(ts) => Some(ts.iter().map(String::as_str).collect()),
+            EmbeddingInput::Tokens(_) | EmbeddingInput::TokenBatches(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmbeddingsResponse {
+    #[serde(default = "list_object")]
+    pub object: String,
+    pub data: Vec<Embedding>,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<EmbeddingsUsage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Embedding {
+    #[serde(default = "embedding_object")]
+    pub object: String,
+    pub index: u32,
+    /// Floats, or a base64 string when `encoding_format` is `base64`.
+    pub embedding: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct EmbeddingsUsage {
+    pub prompt_tokens: u32,
+    pub total_tokens: u32,
+}
+
+impl EmbeddingsResponse {
+    /// Builds a response from one float vector per input, in input order.
+    pub(crate) fn from_vectors(
+        model: String,
+        vectors: Vec<Vec<f64>>,
+        usage: Option<EmbeddingsUsage>,
+    ) -> Self {
+        Self {
+            object: list_object(),
+            data: vectors
+                .into_iter()
+                .enumerate()
+                .map(|(index, v)| Embedding {
+                    object: embedding_object(),
+                    index: index as u32,
+                    embedding: serde_json::json!(v),
+                })
+                .collect(),
+            model,
+            usage,
+        }
+    }
+}
+
+fn list_object() -> String {
+    "list".to_string()
+}
+
+fn embedding_object() -> String {
+    "embedding".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_chat_fields_round_trip() {
+        let req = serde_json::json!({
+            "model": "gpt-4o-mini",
+            "messages": [
+                { "role": "user", "content": "hi" },
+                { "role": "assistant", "content": null, "refusal": "no", "audio": { "id": "audio_1" } },
+            ],
+            "seed": 7,
+            "response_format": { "type": "json_object" },
+        });
+        let parsed: ChatCompletionRequest = serde_json::from_value(req.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), req);
+
+        let res = serde_json::json!({
+            "id": "x", "object": "chat.completion", "created": 1, "model": "m",
+            "system_fingerprint": "fp",
+            "choices": [{ "index": 0, "message": { "role": "assistant", "content": "a", "refusal": null, "reasoning_content": "b" }, "finish_reason": "stop", "logprobs": null }],
+            "usage": null,
+        });
+        let parsed: OpenAIChatCompletionResponse = serde_json::from_value(res.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), res);
+    }
+
+    #[test]
+    fn unparsable_payloads_stay_out_of_errors() {
+        let payload = r#"{"choices": "mail jane.doe@example.com"}"#;
+        let err = serde_json::from_str::<OpenAIStreamChunk>(payload).unwrap_err();
+        assert!(
+            err.to_string().contains("jane.doe@"),
+            "serde quotes the payload"
+        );
+        let err = unexpected_payload("openai stream payload", payload.as_bytes(), &err);
+        let message = format!("{err:#} {err:?}");
+        assert!(!message.contains("jane.doe@"), "{message}");
+        assert!(message.contains("unexpected openai stream payload (line 1"));
+    }
+
+    #[test]
+    fn tool_calling_messages_round_trip() {
+        let req = serde_json::json!({
+            "model": "gpt-4o-mini",
+            "messages": [
+                { "role": "user", "content": "weather in Quito?" },
+                { "role": "assistant", "content": null, "tool_calls": [{ "id": "c1", "type": "function", "function": { "name": "weather", "arguments": "{\"city\":\"Quito\"}" } }] },
+                { "role": "tool", "tool_call_id": "c1", "content": "18C" },
+            ],
+        });
+        let parsed: ChatCompletionRequest = serde_json::from_value(req.clone()).unwrap();
+        assert_eq!(parsed.messages[1].text(), "");
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), req);
+
+        let chunk = OpenAIChatCompletionResponse {
+            choices: vec![OpenAIChatCompletionChoice {
+                message: Some(parsed.messages[1].clone()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+        .into_stream_chunk();
+        let calls = chunk.choices[0]
+            .delta
+            .as_ref()
+            .unwrap()
+            .tool_calls
+            .as_ref()
+            .unwrap();
+        assert_eq!(calls[0].index, Some(0));
+        assert_eq!(calls[0].id.as_deref(), Some("c1"));
+        assert!(parsed.uses_tools());
+    }
+
+    #[test]
+    fn tool_use_is_detected() {
+        let plain: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o-mini",
+            "messages": [{ "role": "user", "content": "hi" }],
+        }))
+        .unwrap();
+        assert!(!plain.uses_tools());
+
+        let mut offered = plain.clone();
+        offered
+            .extra
+            .insert("tools".to_string(), serde_json::json!([]));
+        assert!(offered.uses_tools());
+
+        let mut answered = plain;
+        answered.messages.push(ChatMessage::new("tool", "18C"));
+        assert!(answered.uses_tools());
+    }
+
+    #[test]
+    fn content_parts_round_trip() {
+        let message = serde_json::json!({
+            "role": "user",
+            "content": [
+                { "type": "text", "text": "describe" },
+                { "type": "image_url", "image_url": { "url": "data:image/jpeg;base64,/9j/4AAQ", "detail": "high" } },
+                { "type": "input_audio", "input_audio": { "data": "UklG", "format": "wav" } },
+                { "type": "text", "text": "briefly" },
+            ],
+        });
+        let parsed: ChatMessage = serde_json::from_value(message.clone()).unwrap();
+        assert_eq!(parsed.text(), "describe\nbriefly");
+        let content = parsed.content.as_ref().unwrap();
+        let image = DataUrl::parse(content.images().next().unwrap()).unwrap();
+        assert_eq!(image.media_type, "image/jpeg");
+        assert_eq!(image.decoded_len(), 6);
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), message);
+    }
+
+    #[test]
+    fn embedding_input_accepts_every_openai_form() {
+        let input = |json: &str| serde_json::from_str::<EmbeddingInput>(json).unwrap();
+        assert_eq!(input(r#""a""#).texts(), Some(vec!["a"]));
+        assert_eq!(input(r#"["a","b"]"#).texts(), Some(vec!["a", "b"]));
+        assert!(matches!(input("[1,2]"), EmbeddingInput::Tokens(_)));
+        assert!(matches!(
+            input("[[1],[2,3]]"),
+            EmbeddingInput::TokenBatches(_)
+        ));
+        assert_eq!(input("[[1]]").texts(), None);
+    }
+}
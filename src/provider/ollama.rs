@@ -0,0 +1,401 @@
+use anyhow::Context;
+use async_stream::try_stream;
+use async_trait::async_trait;
+use futures::StreamExt;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    body_lines, check_status, completion_id, probe_request, unexpected_payload, unix_now,
+    Capabilities, ChatCompletionRequest, ChatMessage, ChatStream, DataUrl, EmbeddingsRequest,
+    EmbeddingsResponse, EmbeddingsUsage, MessageContent, OpenAIChatCompletionChoice,
+    OpenAIChatCompletionResponse, OpenAIChoice, OpenAIDelta, OpenAIStreamChunk, OpenAIUsage,
+    Provider, Unsupported,
+};
+
+/// Talks to Ollama's native `/api/chat`, which streams newline-delimited JSON.
+#[derive(Clone)]
+pub struct OllamaProvider {
+    client: Client,
+    base_url: Url,
+}
+
+impl OllamaProvider {
+    pub fn new(base_url: Option<String>) -> anyhow::Result<Self> {
+        let base = match base_url {
+            Some(u) if !u.is_empty() => Url::parse(&u)?,
+            _ => Url::parse("http://localhost:11434")?,
+        };
+        let client = Client::builder().build()?;
+        Ok(Self {
+            client,
+            base_url: base,
+        })
+    }
+
+    async fn send(&self, path: &str, body: &impl Serialize) -> anyhow::Result<reqwest::Response> {
+        let url = self.base_url.join(path)?;
+        let res = self
+            .client
+            .post(url)
+            .json(body)
+            .send()
+            .await
+            .context("ollama send failed")?;
+
+        check_status(self.name(), res).await
+    }
+}
+
+#[async_trait]
+impl Provider for OllamaProvider {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { streaming: true }
+    }
+
+    async fn chat_completion(
+        &self,
+        payload: ChatCompletionRequest,
+    ) -> anyhow::Result<OpenAIChatCompletionResponse> {
+        let body = ChatRequest::from_chat(payload, false)?;
+        let res = self.send("/api/chat", &body).await?;
+        let line = res
+            .json::<ChatLine>()
+            .await
+            .context("failed to parse ollama response")?;
+        Ok(OpenAIChatCompletionResponse {
+            id: Some(completion_id()),
+            object: Some("chat.completion".to_string()),
+            created: Some(unix_now()),
+            usage: line.usage(),
+            model: line.model,
+            choices: vec![OpenAIChatCompletionChoice {
+                index: Some(0),
+                message: Some(ChatMessage::new(
+                    "assistant",
+                    line.message.map(|m| m.content).unwrap_or_default(),
+                )),
+                finish_reason: line.done_reason.as_deref().map(map_done_reason),
+                extra: Default::default(),
+            }],
+            extra: Default::default(),
+        })
+    }
+
+    async fn chat_stream(&self, payload: ChatCompletionRequest) -> anyhow::Result<ChatStream> {
+        let body = ChatRequest::from_chat(payload, true)?;
+        let res = self.send("/api/chat", &body).await?;
+        let mut lines = Box::pin(body_lines(res));
+
+        let stream = try_stream! {
+            let mut translator = StreamTranslator::default();
+            while let Some(line) = lines.next().await {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let line: ChatLine = serde_json::from_str(&line)
+                    .map_err(|e| unexpected_payload("ollama stream payload", line.as_bytes(), &e))?;
+                let done = line.done;
+                yield translator.translate(line)?;
+                if done {
+                    break;
+                }
+            }
+        };
+        Ok(Box::pin(stream))
+    }
+
+    async fn embeddings(&self, payload: EmbeddingsRequest) -> anyhow::Result<EmbeddingsResponse> {
+        let Some(input) = payload.input.texts() else {
+            return Err(Unsupported {
+                provider: self.name(),
+                operation: "token id embedding input",
+            }
+            .into());
+        };
+        let body = EmbedRequest {
+            model: &payload.model,
+            input,
+            dimensions: payload.dimensions,
+        };
+        let res = self.send("/api/embed", &body).await?;
+        let body = res
+            .json::<EmbedResponse>()
+            .await
+            .context("failed to parse ollama embed response")?;
+        let usage = body.prompt_eval_count.map(|n| EmbeddingsUsage {
+            prompt_tokens: n,
+            total_tokens: n,
+        });
+        Ok(EmbeddingsResponse::from_vectors(
+            body.model.unwrap_or(payload.model),
+            body.embeddings,
+            usage,
+        ))
+    }
+
+    async fn probe(&self, path: Option<&str>) -> anyhow::Result<()> {
+        let url = self.base_url.join(path.unwrap_or("/api/tags"))?;
+        probe_request(self.name(), self.client.get(url)).await
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<Message>,
+    stream: bool,
+    options: Options,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Message {
+    role: String,
+    content: String,
+    /// Base64 images, without the `data:` prefix.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Default)]
+struct Options {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u32>,
+}
+
+impl ChatRequest {
+    fn from_chat(payload: ChatCompletionRequest, stream: bool) -> anyhow::Result<Self> {
+        if payload.uses_tools() {
+            return Err(Unsupported {
+                provider: "ollama",
+                operation: "tool calling",
+            }
+            .into());
+        }
+        let mut messages = Vec::with_capacity(payload.messages.len());
+        for m in payload.messages {
+            // Ollama has no way to fetch remote images itself
+            let images = m
+                .content
+                .iter()
+                .flat_map(MessageContent::images)
+                .map(|url| {
+                    DataUrl::parse(url)
+                        .map(|data| data.data.to_string())
+                        .ok_or(Unsupported {
+                            provider: "ollama",
+                            operation: "remote image urls",
+                        })
+                })
+                .collect::<Result<_, _>>()?;
+            messages.push(Message {
+                content: m.text().into_owned(),
+                role: m.role,
+                images,
+            });
+        }
+        Ok(Self {
+            model: payload.model,
+            messages,
+            stream,
+            options: Options {
+                temperature: payload.temperature,
+                top_p: payload.top_p,
+                num_predict: payload.max_tokens,
+            },
+        })
+    }
+}
+
+/// One NDJSON line of a streamed reply, or the whole non-streamed reply.
+#[derive(Debug, Deserialize)]
+struct ChatLine {
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    message: Option<Message>,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    done_reason: Option<String>,
+    #[serde(default)]
+    prompt_eval_count: Option<u32>,
+    #[serde(default)]
+    eval_count: Option<u32>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+impl ChatLine {
+    fn usage(&self) -> Option<OpenAIUsage> {
+        if !self.done {
+            return None;
+        }
+        Some(OpenAIUsage {
+            prompt_tokens: self.prompt_eval_count,
+            completion_tokens: self.eval_count,
+            total_tokens: Some(self.prompt_eval_count.unwrap_or(0) + self.eval_count.unwrap_or(0)),
+        })
+    }
+}
+
+/// Turns the NDJSON lines of one streamed reply into chat chunks.
+struct StreamTranslator {
+    id: String,
+    created: i64,
+    first: bool,
+}
+
+impl Default for StreamTranslator {
+    fn default() -> Self {
+        Self {
+            id: completion_id(),
+            created: unix_now(),
+            first: true,
+        }
+    }
+}
+
+impl StreamTranslator {
+    fn translate(&mut self, line: ChatLine) -> anyhow::Result<OpenAIStreamChunk> {
+        if let Some(error) = line.error.as_ref() {
+            anyhow::bail!("ollama stream error: {error}");
+        }
+        let role = std::mem::take(&mut self.first).then(|| "assistant".to_string());
+        Ok(OpenAIStreamChunk {
+            id: Some(self.id.clone()),
+            object: Some("chat.completion.chunk".to_string()),
+            created: Some(self.created),
+            usage: line.usage(),
+            model: line.model,
+            choices: vec![OpenAIChoice {
+                index: Some(0),
+                delta: Some(OpenAIDelta {
+                    role,
+                    content: line.message.map(|m| m.content),
+                    ..Default::default()
+                }),
+                finish_reason: line
+                    .done
+                    .then(|| map_done_reason(line.done_reason.as_deref().unwrap_or("stop"))),
+                extra: Default::default(),
+            }],
+            extra: Default::default(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct EmbedRequest<'a> {
+    model: &'a str,
+    input: Vec<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dimensions: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbedResponse {
+    #[serde(default)]
+    model: Option<String>,
+    embeddings: Vec<Vec<f64>>,
+    #[serde(default)]
+    prompt_eval_count: Option<u32>,
+}
+
+fn map_done_reason(reason: &str) -> String {
+    match reason {
+        "length" => "length",
+        _ => "stop",
+    }
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn maps_chat_requests() {
+        let image = serde_json::json!({
+            "type": "image_url",
+            "image_url": { "url": "data:image/png;base64,iVBORw0KGgo=" },
+        });
+        let req: ChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "llama3.2",
+            "messages": [
+                { "role": "system", "content": "be brief" },
+                { "role": "user", "content": [{ "type": "text", "text": "what is this?" }, image] },
+            ],
+            "temperature": 0.2,
+            "max_tokens": 64,
+        }))
+        .unwrap();
+        let body =
+            serde_json::to_value(ChatRequest::from_chat(req.clone(), true).unwrap()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "model": "llama3.2",
+                "messages": [
+                    { "role": "system", "content": "be brief" },
+                    { "role": "user", "content": "what is this?", "images": ["iVBORw0KGgo="] },
+                ],
+                "stream": true,
+                "options": { "temperature": 0.2f32, "num_predict": 64 },
+            })
+        );
+
+        let mut remote = req;
+        remote.messages[1] = serde_json::from_value(serde_json::json!({
+            "role": "user",
+            "content": [{ "type": "image_url", "image_url": { "url": "https://example.com/cat.png" } }],
+        }))
+        .unwrap();
+        let err = ChatRequest::from_chat(remote, false).unwrap_err();
+        assert!(err.downcast_ref::<Unsupported>().is_some());
+    }
+
+    #[test]
+    fn translates_ndjson_stream() {
+        let lines = [
+            r#"{"model":"llama3.2","created_at":"2024-01-01T00:00:00Z","message":{"role":"assistant","content":"Hel"},"done":false}"#,
+            r#"{"model":"llama3.2","created_at":"2024-01-01T00:00:00Z","message":{"role":"assistant","content":"lo"},"done":false}"#,
+            r#"{"model":"llama3.2","created_at":"2024-01-01T00:00:00Z","message":{"role":"assistant","content":""},"done":true,"done_reason":"length","prompt_eval_count":7,"eval_count":3}"#,
+        ];
+        let mut translator = StreamTranslator::default();
+        let chunks: Vec<OpenAIStreamChunk> = lines
+            .iter()
+            .map(|line| {
+                translator
+                    .translate(serde_json::from_str(line).unwrap())
+                    .unwrap()
+            })
+            .collect();
+
+        let deltas: Vec<_> = chunks
+            .iter()
+            .map(|c| c.choices[0].delta.as_ref().unwrap())
+            .collect();
+        assert_eq!(deltas[0].role.as_deref(), Some("assistant"));
+        assert_eq!(deltas[1].role, None);
+        let text: String = deltas.iter().filter_map(|d| d.content.as_deref()).collect();
+        assert_eq!(text, "Hello");
+        assert!(chunks.iter().all(|c| c.id == chunks[0].id));
+
+        let last = chunks.last().unwrap();
+        assert_eq!(last.choices[0].finish_reason.as_deref(), Some("length"));
+        assert_eq!(last.usage.as_ref().unwrap().total_tokens, Some(10));
+        assert_eq!(chunks[0].choices[0].finish_reason, None);
+
+        let error = serde_json::from_str(r#"{"error":"model not found"}"#).unwrap();
+        assert!(translator.translate(error).is_err());
+    }
+}
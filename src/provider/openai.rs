@@ -1,93 +1,233 @@
+use std::{collections::HashMap, sync::Arc};
+
 use anyhow::Context;
-use futures::{Stream, StreamExt, TryStreamExt};
-use reqwest::{Client, Url};
-use serde::{Deserialize, Serialize};
-use tokio::io::AsyncBufReadExt;
-use tokio_stream::wrappers::LinesStream;
-use tokio_util::io::StreamReader;
+use async_trait::async_trait;
+use futures::{future, TryStreamExt};
+use reqwest::{Client, RequestBuilder, Url};
+use tokio_tungstenite::tungstenite::{
+    client::IntoClientRequest, handshake::client::Request as WsRequest, Error as WsError,
+};
+
+use crate::config::AzureDeployment;
+
+use super::{
+    body_lines, check_status, keys::KeyPool, probe_request, sse_data, unexpected_payload,
+    AudioStream, Capabilities, ChatCompletionRequest, ChatStream, EmbeddingsRequest,
+    EmbeddingsResponse, FilesBody, FilesRequest, ImageGenerationRequest, ImagesResponse,
+    OpenAIChatCompletionResponse, OpenAIStreamChunk, Provider, RealtimeSocket, ResponsesRequest,
+    ResponsesStream, SpeechRequest, Transcript, TranscriptionRequest, Unsupported, UpstreamError,
+};
+
+const DEFAULT_AZURE_API_VERSION: &str = "2024-06-01";
 
 #[derive(Clone)]
 pub struct OpenAIProvider {
-    api_key: String,
+    keys: Arc<KeyPool>,
     client: Client,
     base_url: Url,
+    mode: Mode,
+}
+
+/// How requests are addressed and authenticated. Azure OpenAI serves the same
+/// wire format from per-deployment URLs and expects an `api-key` header.
+#[derive(Clone)]
+enum Mode {
+    OpenAI,
+    Azure {
+        api_version: String,
+        deployments: HashMap<String, AzureDeployment>,
+    },
 }
 
 impl OpenAIProvider {
-    pub fn new(api_key: String, base_url: Option<String>) -> anyhow::Result<Self> {
+    pub fn new(keys: Arc<KeyPool>, base_url: Option<String>) -> anyhow::Result<Self> {
         let base = match base_url {
             Some(u) if !u.is_empty() => Url::parse(&u)?,
             _ => Url::parse("https://api.openai.com")?,
         };
+        Self::with_mode(keys, base, Mode::OpenAI)
+    }
+
+    /// Azure OpenAI resource at `endpoint`. Models missing from `deployments`
+    /// are sent to a deployment of the same name.
+    pub fn new_azure(
+        keys: Arc<KeyPool>,
+        endpoint: &str,
+        api_version: Option<String>,
+        deployments: HashMap<String, AzureDeployment>,
+    ) -> anyhow::Result<Self> {
+        let mode = Mode::Azure {
+            api_version: api_version
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| DEFAULT_AZURE_API_VERSION.to_string()),
+            deployments,
+        };
+        Self::with_mode(keys, Url::parse(endpoint)?, mode)
+    }
+
+    fn with_mode(keys: Arc<KeyPool>, base: Url, mode: Mode) -> anyhow::Result<Self> {
         let mut builder = Client::builder();
         if base.scheme() == "https" {
             builder = builder.http2_prior_knowledge();
         }
         let client = builder.build()?;
         Ok(Self {
-            api_key,
+            keys,
             client,
             base_url: base,
+            mode,
         })
     }
 
-    pub async fn chat_stream(
+    /// A POST to `operation` (e.g. `chat/completions`) for `model`, and the
+    /// key it is authenticated with.
+    fn request(&self, model: &str, operation: &str) -> anyhow::Result<(RequestBuilder, Arc<str>)> {
+        let key = self.keys.pick();
+        let req = match &self.mode {
+            Mode::OpenAI => {
+                let url = self.base_url.join(&format!("/v1/{operation}"))?;
+                self.client.post(url).bearer_auth(&key)
+            }
+            Mode::Azure {
+                api_version,
+                deployments,
+            } => {
+                let (deployment, version) = azure_deployment(deployments, api_version, model);
+                let mut url = self
+                    .base_url
+                    .join(&format!("/openai/deployments/{deployment}/{operation}"))?;
+                url.query_pairs_mut().append_pair("api-version", version);
+                self.client.post(url).header("api-key", &*key)
+            }
+        };
+        Ok((req, key))
+    }
+
+    /// The Realtime API endpoint for `model` with its authentication headers.
+    fn realtime_request(&self, model: &str, key: &str) -> anyhow::Result<WsRequest> {
+        let mut url = match &self.mode {
+            Mode::OpenAI => {
+                let mut url = self.base_url.join("/v1/realtime")?;
+                url.query_pairs_mut().append_pair("model", model);
+                url
+            }
+            Mode::Azure {
+                api_version,
+                deployments,
+            } => {
+                let (deployment, version) = azure_deployment(deployments, api_version, model);
+                let mut url = self.base_url.join("/openai/realtime")?;
+                url.query_pairs_mut()
+                    .append_pair("api-version", version)
+                    .append_pair("deployment", deployment);
+                url
+            }
+        };
+        let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+        url.set_scheme(scheme)
+            .map_err(|_| anyhow::anyhow!("cannot open a websocket to {url}"))?;
+        let mut req = url.as_str().into_client_request()?;
+        let headers = req.headers_mut();
+        match self.mode {
+            Mode::OpenAI => headers.insert("Authorization", format!("Bearer {key}").parse()?),
+            Mode::Azure { .. } => headers.insert("api-key", key.parse()?),
+        };
+        headers.insert("OpenAI-Beta", "realtime=v1".parse()?);
+        Ok(req)
+    }
+
+    async fn send_responses(
         &self,
-        mut payload: ChatCompletionRequest,
-    ) -> anyhow::Result<impl Stream<Item = anyhow::Result<String>>> {
-        let url = self.base_url.join("/v1/chat/completions")?;
+        payload: &ResponsesRequest,
+    ) -> anyhow::Result<reqwest::Response> {
+        // Azure serves the Responses API from a separate preview surface
+        if let Mode::Azure { .. } = self.mode {
+            return Err(Unsupported {
+                provider: self.name(),
+                operation: "the responses api",
+            }
+            .into());
+        }
+        let (req, key) = self.request(&payload.model, "responses")?;
+        let accept = if payload.stream == Some(true) {
+            "text/event-stream"
+        } else {
+            "application/json"
+        };
+        let res = req
+            .header("Accept", accept)
+            .json(payload)
+            .send()
+            .await
+            .with_context(|| format!("{} send failed", self.name()))?;
+        let res = check_status(self.name(), res).await;
+        self.keys.observe(&key, &res);
+        res
+    }
+}
+
+/// The deployment `model` is served from and the API version to call it with.
+fn azure_deployment<'a>(
+    deployments: &'a HashMap<String, AzureDeployment>,
+    api_version: &'a str,
+    model: &'a str,
+) -> (&'a str, &'a str) {
+    match deployments.get(model) {
+        Some(d) => (
+            d.deployment.as_str(),
+            d.api_version.as_deref().unwrap_or(api_version),
+        ),
+        None => (model, api_version),
+    }
+}
+
+#[async_trait]
+impl Provider for OpenAIProvider {
+    fn name(&self) -> &'static str {
+        match self.mode {
+            Mode::OpenAI => "openai",
+            Mode::Azure { .. } => "azure",
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { streaming: true }
+    }
+
+    async fn chat_stream(&self, mut payload: ChatCompletionRequest) -> anyhow::Result<ChatStream> {
         // ensure streaming
         payload.stream = Some(true);
 
-        let res = self
-            .client
-            .post(url)
-            .bearer_auth(&self.api_key)
+        let (req, key) = self.request(&payload.model, "chat/completions")?;
+        let res = req
             .header("Accept", "text/event-stream")
             .json(&payload)
             .send()
             .await
-            .context("openai send failed")?;
+            .with_context(|| format!("{} send failed", self.name()))?;
+        let res = check_status(self.name(), res).await;
+        self.keys.observe(&key, &res);
+        let res = res?;
 
-        let status = res.status();
-        if !status.is_success() {
-            let body = res.text().await.unwrap_or_default();
-            return Err(anyhow::anyhow!("openai error: {} - {}", status, body));
-        }
-
-        let stream = res
-            .bytes_stream()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e));
-        // Transform into line-based "data: ..." items
-        let s = StreamReader::new(stream);
-        let reader = tokio::io::BufReader::new(s);
-        let lines = reader.lines();
-        let lines = LinesStream::new(lines).map(|l| l.map_err(|e| e.into()));
-        Ok(lines)
+        Ok(Box::pin(parse_sse_stream(body_lines(res))))
     }
 
-    pub async fn chat_completion(
+    async fn chat_completion(
         &self,
         mut payload: ChatCompletionRequest,
     ) -> anyhow::Result<OpenAIChatCompletionResponse> {
-        let url = self.base_url.join("/v1/chat/completions")?;
         payload.stream = Some(false);
 
-        let res = self
-            .client
-            .post(url)
-            .bearer_auth(&self.api_key)
+        let (req, key) = self.request(&payload.model, "chat/completions")?;
+        let res = req
             .header("Accept", "application/json")
             .json(&payload)
             .send()
             .await
-            .context("openai send failed")?;
-
-        let status = res.status();
-        if !status.is_success() {
-            let body = res.text().await.unwrap_or_default();
-            return Err(anyhow::anyhow!("openai error: {} - {}", status, body));
-        }
+            .with_context(|| format!("{} send failed", self.name()))?;
+        let res = check_status(self.name(), res).await;
+        self.keys.observe(&key, &res);
+        let res = res?;
 
         let body = res
             .json::<OpenAIChatCompletionResponse>()
@@ -95,67 +235,294 @@ impl OpenAIProvider {
             .context("failed to parse openai response")?;
         Ok(body)
     }
-}
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ChatCompletionRequest {
-    pub model: String,
-    pub messages: Vec<ChatMessage>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub temperature: Option<f32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub top_p: Option<f32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub max_tokens: Option<u32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub stream: Option<bool>,
-}
+    async fn embeddings(&self, payload: EmbeddingsRequest) -> anyhow::Result<EmbeddingsResponse> {
+        let (req, key) = self.request(&payload.model, "embeddings")?;
+        let res = req
+            .json(&payload)
+            .send()
+            .await
+            .with_context(|| format!("{} send failed", self.name()))?;
+        let res = check_status(self.name(), res).await;
+        self.keys.observe(&key, &res);
+        res?.json::<EmbeddingsResponse>()
+            .await
+            .context("failed to parse openai embeddings response")
+    }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ChatMessage {
-    pub role: String,
-    pub content: String,
-}
+    async fn responses(&self, mut payload: ResponsesRequest) -> anyhow::Result<serde_json::Value> {
+        payload.stream = Some(false);
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct OpenAIStreamChunk {
-    pub id: Option<String>,
-    pub choices: Vec<OpenAIChoice>,
-}
+        let res = self.send_responses(&payload).await?;
+        res.json::<serde_json::Value>()
+            .await
+            .context("failed to parse openai responses payload")
+    }
+
+    async fn responses_stream(
+        &self,
+        mut payload: ResponsesRequest,
+    ) -> anyhow::Result<ResponsesStream> {
+        payload.stream = Some(true);
+
+        let res = self.send_responses(&payload).await?;
+        Ok(Box::pin(parse_event_stream(body_lines(res))))
+    }
+
+    async fn transcription(&self, request: TranscriptionRequest) -> anyhow::Result<Transcript> {
+        let (req, key) = self.request(&request.model, "audio/transcriptions")?;
+        let mut file = request.file.part().await?.file_name(request.filename);
+        if let Some(mime) = request.content_type {
+            file = file.mime_str(&mime)?;
+        }
+        let mut form = reqwest::multipart::Form::new()
+            .text("model", request.model)
+            .part("file", file);
+        for (name, value) in request.fields {
+            form = form.text(name, value);
+        }
+        let res = req
+            .multipart(form)
+            .send()
+            .await
+            .with_context(|| format!("{} send failed", self.name()))?;
+        let res = check_status(self.name(), res).await;
+        self.keys.observe(&key, &res);
+        let res = res?;
+
+        let json = res
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/json"));
+        let body = res
+            .text()
+            .await
+            .context("failed to read openai transcript")?;
+        if json {
+            Ok(Transcript::Json(
+                serde_json::from_str(&body).context("failed to parse openai transcript")?,
+            ))
+        } else {
+            Ok(Transcript::Text(body))
+        }
+    }
+
+    async fn speech(&self, payload: SpeechRequest) -> anyhow::Result<AudioStream> {
+        let (req, key) = self.request(&payload.model, "audio/speech")?;
+        let res = req
+            .json(&payload)
+            .send()
+            .await
+            .with_context(|| format!("{} send failed", self.name()))?;
+        let res = check_status(self.name(), res).await;
+        self.keys.observe(&key, &res);
+        Ok(Box::pin(res?.bytes_stream().map_err(anyhow::Error::from)))
+    }
+
+    async fn image_generation(
+        &self,
+        payload: ImageGenerationRequest,
+    ) -> anyhow::Result<ImagesResponse> {
+        let (req, key) = self.request(&payload.model, "images/generations")?;
+        let res = req
+            .json(&payload)
+            .send()
+            .await
+            .with_context(|| format!("{} send failed", self.name()))?;
+        let res = check_status(self.name(), res).await;
+        self.keys.observe(&key, &res);
+        res?.json::<ImagesResponse>()
+            .await
+            .context("failed to parse openai images response")
+    }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct OpenAIChoice {
-    pub index: Option<u32>,
-    pub delta: Option<OpenAIDelta>,
-    pub finish_reason: Option<String>,
+    async fn realtime(&self, model: &str) -> anyhow::Result<RealtimeSocket> {
+        let key = self.keys.pick();
+        let req = self.realtime_request(model, &key)?;
+        let result = match tokio_tungstenite::connect_async(req).await {
+            Ok((socket, _)) => Ok(socket),
+            Err(WsError::Http(res)) => Err(UpstreamError {
+                provider: self.name(),
+                status: res.status(),
+                body: res
+                    .body()
+                    .as_deref()
+                    .map(String::from_utf8_lossy)
+                    .unwrap_or_default()
+                    .into_owned(),
+                retry_after: None,
+            }
+            .into()),
+            Err(e) => {
+                Err(anyhow::Error::from(e).context(format!("{} connect failed", self.name())))
+            }
+        };
+        self.keys.observe(&key, &result);
+        result
+    }
+
+    async fn files(&self, request: FilesRequest) -> anyhow::Result<reqwest::Response> {
+        // Azure keeps files and batches under its own API surface
+        if let Mode::Azure { .. } = self.mode {
+            return Err(Unsupported {
+                provider: self.name(),
+                operation: "the files and batches api",
+            }
+            .into());
+        }
+        let key = self.keys.pick();
+        let mut url = self.base_url.join(&format!("/v1/{}", request.path))?;
+        url.set_query(request.query.as_deref());
+        let req = self.client.request(request.method, url).bearer_auth(&key);
+        let req = match request.body {
+            FilesBody::Empty => req,
+            FilesBody::Json(body) => req.json(&body),
+            FilesBody::Upload {
+                purpose,
+                filename,
+                content,
+            } => {
+                let file = reqwest::multipart::Part::stream(content)
+                    .file_name(filename)
+                    .mime_str("application/jsonl")?;
+                req.multipart(
+                    reqwest::multipart::Form::new()
+                        .text("purpose", purpose)
+                        .part("file", file),
+                )
+            }
+        };
+        let res = req
+            .send()
+            .await
+            .with_context(|| format!("{} send failed", self.name()))?;
+        let res = check_status(self.name(), res).await;
+        self.keys.observe(&key, &res);
+        res
+    }
+
+    async fn probe(&self, path: Option<&str>) -> anyhow::Result<()> {
+        let key = self.keys.pick();
+        let req = match &self.mode {
+            Mode::OpenAI => {
+                let url = self.base_url.join(path.unwrap_or("/v1/models"))?;
+                self.client.get(url).bearer_auth(&key)
+            }
+            Mode::Azure { api_version, .. } => {
+                let mut url = self.base_url.join(path.unwrap_or("/openai/models"))?;
+                if path.is_none() {
+                    url.query_pairs_mut()
+                        .append_pair("api-version", api_version);
+                }
+                self.client.get(url).header("api-key", &*key)
+            }
+        };
+        probe_request(self.name(), req).await
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct OpenAIDelta {
-    pub role: Option<String>,
-    pub content: Option<String>,
+/// Turns Responses API SSE lines into events. The `event:` lines repeat the
+/// `type` found in each payload, so only `data:` lines are read.
+fn parse_event_stream(
+    lines: impl futures::Stream<Item = anyhow::Result<String>> + Send + 'static,
+) -> impl futures::Stream<Item = anyhow::Result<serde_json::Value>> + Send + 'static {
+    lines.try_filter_map(|line| {
+        future::ready(match sse_data(&line).map(str::trim) {
+            None | Some("") | Some("[DONE]") => Ok(None),
+            Some(data) => serde_json::from_str(data)
+                .map(Some)
+                .map_err(|e| unexpected_payload("openai responses event", data.as_bytes(), &e)),
+        })
+    })
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct OpenAIChatCompletionResponse {
-    pub id: Option<String>,
-    pub object: Option<String>,
-    pub created: Option<i64>,
-    pub model: Option<String>,
-    pub choices: Vec<OpenAIChatCompletionChoice>,
-    pub usage: Option<OpenAIUsage>,
+/// Turns OpenAI-style SSE lines into chunks, stopping at `data: [DONE]`.
+pub(crate) fn parse_sse_stream(
+    lines: impl futures::Stream<Item = anyhow::Result<String>> + Send + 'static,
+) -> impl futures::Stream<Item = anyhow::Result<OpenAIStreamChunk>> + Send + 'static {
+    lines
+        .try_take_while(|line| future::ready(Ok(sse_data(line).map(str::trim) != Some("[DONE]"))))
+        .try_filter_map(|line| future::ready(parse_sse_line(&line)))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct OpenAIChatCompletionChoice {
-    pub index: Option<u32>,
-    pub message: Option<ChatMessage>,
-    pub finish_reason: Option<String>,
+fn parse_sse_line(line: &str) -> anyhow::Result<Option<OpenAIStreamChunk>> {
+    let Some(data) = sse_data(line) else {
+        return Ok(None);
+    };
+    if data.trim().is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_str(data)
+        .map(Some)
+        .map_err(|e| unexpected_payload("openai stream payload", data.as_bytes(), &e))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct OpenAIUsage {
-    pub prompt_tokens: Option<u32>,
-    pub completion_tokens: Option<u32>,
-    pub total_tokens: Option<u32>,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn azure() -> OpenAIProvider {
+        let deployments = HashMap::from([
+            (
+                "gpt-4o".to_string(),
+                AzureDeployment {
+                    deployment: "prod-4o".to_string(),
+                    api_version: Some("2024-10-21".to_string()),
+                },
+            ),
+            (
+                "gpt-4o-mini".to_string(),
+                AzureDeployment {
+                    deployment: "mini".to_string(),
+                    api_version: None,
+                },
+            ),
+        ]);
+        OpenAIProvider::new_azure(
+            KeyPool::single("azure", "az-key".to_string()),
+            "https://example.openai.azure.com",
+            None,
+            deployments,
+        )
+        .unwrap()
+    }
+
+    fn url(provider: &OpenAIProvider, model: &str) -> String {
+        let (req, _) = provider.request(model, "chat/completions").unwrap();
+        req.build().unwrap().url().to_string()
+    }
+
+    #[test]
+    fn azure_models_map_to_deployments() {
+        let provider = azure();
+        assert_eq!(
+            url(&provider, "gpt-4o"),
+            "https://example.openai.azure.com/openai/deployments/prod-4o/chat/completions?api-version=2024-10-21"
+        );
+        assert_eq!(
+            url(&provider, "gpt-4o-mini"),
+            format!("https://example.openai.azure.com/openai/deployments/mini/chat/completions?api-version={DEFAULT_AZURE_API_VERSION}")
+        );
+        assert_eq!(
+            url(&provider, "o3"),
+            format!("https://example.openai.azure.com/openai/deployments/o3/chat/completions?api-version={DEFAULT_AZURE_API_VERSION}")
+        );
+    }
+
+    #[test]
+    fn azure_authenticates_with_api_key_header() {
+        let (req, _) = azure().request("gpt-4o", "embeddings").unwrap();
+        let req = req.build().unwrap();
+        assert_eq!(req.headers()["api-key"], "az-key");
+        assert!(req.headers().get("authorization").is_none());
+
+        let openai =
+            OpenAIProvider::new(KeyPool::single("openai", "sk-test".to_string()), None).unwrap();
+        let (req, _) = openai.request("gpt-4o", "embeddings").unwrap();
+        let req = req.build().unwrap();
+        assert_eq!(req.url().as_str(), "https://api.openai.com/v1/embeddings");
+        assert_eq!(req.headers()["authorization"], "Bearer sk-test");
+        assert!(req.headers().get("api-key").is_none());
+    }
 }
@@ -0,0 +1,481 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures::{future::BoxFuture, StreamExt};
+use rand::Rng;
+
+use crate::config::PoolStrategy;
+
+use super::{
+    peek_first, AudioStream, BoxStream, Capabilities, ChatCompletionRequest, ChatStream,
+    EmbeddingsRequest, EmbeddingsResponse, FilesRequest, ImageGenerationRequest, ImagesResponse,
+    OpenAIChatCompletionResponse, Provider, RealtimeSocket, ResponsesRequest, ResponsesStream,
+    SpeechRequest, Transcript, TranscriptionRequest,
+};
+
+/// One upstream inside a pool.
+pub struct Member {
+    name: String,
+    provider: Arc<dyn Provider>,
+    weight: u32,
+    inflight: AtomicUsize,
+}
+
+impl Member {
+    pub fn new(name: &str, provider: Arc<dyn Provider>, weight: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            provider,
+            weight: weight.max(1),
+            inflight: AtomicUsize::new(0),
+        }
+    }
+
+    /// Outstanding requests per unit of weight, as a fraction to avoid floats.
+    fn load(&self) -> (usize, u32) {
+        (self.inflight.load(Ordering::Relaxed), self.weight)
+    }
+}
+
+fn less_loaded(a: (usize, u32), b: (usize, u32)) -> bool {
+    (a.0 as u64) * (b.1 as u64) < (b.0 as u64) * (a.1 as u64)
+}
+
+/// Spreads requests for one logical upstream over several interchangeable
+/// endpoints or keys.
+pub struct PoolProvider {
+    name: String,
+    strategy: PoolStrategy,
+    members: Vec<Arc<Member>>,
+    // Smooth weighted round-robin counters, one per member
+    current: Mutex<Vec<i64>>,
+    hedge_after: Option<Duration>,
+}
+
+impl PoolProvider {
+    pub fn new(name: &str, strategy: PoolStrategy, members: Vec<Member>) -> anyhow::Result<Self> {
+        if members.is_empty() {
+            anyhow::bail!("pool {name} has no members");
+        }
+        Ok(Self {
+            name: name.to_string(),
+            strategy,
+            current: Mutex::new(vec![0; members.len()]),
+            members: members.into_iter().map(Arc::new).collect(),
+            hedge_after: None,
+        })
+    }
+
+    /// Streams that have not produced a first chunk after `delay` are raced
+    /// against a second member; the slower one is cancelled.
+    pub fn with_hedging(mut self, delay: Duration) -> Self {
+        self.hedge_after = Some(delay);
+        self
+    }
+
+    fn pick(&self) -> Arc<Member> {
+        // Steer around members whose breaker is open; if all are, let the
+        // chosen one fail fast rather than guessing
+        let available = self.candidates(|_| true);
+        if available.is_empty() {
+            return self.choose(&(0..self.members.len()).collect::<Vec<_>>());
+        }
+        self.choose(&available)
+    }
+
+    /// Another available member than `taken`, for a hedged request.
+    fn pick_other(&self, taken: &Arc<Member>) -> Option<Arc<Member>> {
+        let others = self.candidates(|m| !Arc::ptr_eq(m, taken));
+        (!others.is_empty()).then(|| self.choose(&others))
+    }
+
+    fn candidates(&self, keep: impl Fn(&Arc<Member>) -> bool) -> Vec<usize> {
+        (0..self.members.len())
+            .filter(|&i| self.members[i].provider.is_available() && keep(&self.members[i]))
+            .collect()
+    }
+
+    fn choose(&self, candidates: &[usize]) -> Arc<Member> {
+        let n = candidates.len();
+        let idx = match self.strategy {
+            PoolStrategy::WeightedRoundRobin => {
+                let mut current = self.current.lock().expect("pool lock poisoned");
+                let total: i64 = candidates
+                    .iter()
+                    .map(|&i| self.members[i].weight as i64)
+                    .sum();
+                let mut best = candidates[0];
+                for &i in candidates {
+                    current[i] += self.members[i].weight as i64;
+                    if current[i] > current[best] {
+                        best = i;
+                    }
+                }
+                current[best] -= total;
+                best
+            }
+            PoolStrategy::LeastOutstanding => {
+                // Start at a random member so ties don't all land on the first one
+                let offset = rand::thread_rng().gen_range(0..n);
+                (0..n)
+                    .map(|i| candidates[(offset + i) % n])
+                    .reduce(|best, i| {
+                        if less_loaded(self.members[i].load(), self.members[best].load()) {
+                            i
+                        } else {
+                            best
+                        }
+                    })
+                    .unwrap_or(candidates[0])
+            }
+            PoolStrategy::PowerOfTwoChoices if n > 1 => {
+                let picks = rand::seq::index::sample(&mut rand::thread_rng(), n, 2);
+                let (a, b) = (candidates[picks.index(0)], candidates[picks.index(1)]);
+                if less_loaded(self.members[b].load(), self.members[a].load()) {
+                    b
+                } else {
+                    a
+                }
+            }
+            PoolStrategy::PowerOfTwoChoices => candidates[0],
+        };
+        tracing::debug!(pool = %self.name, member = %self.members[idx].name, "pool member selected");
+        self.members[idx].clone()
+    }
+
+    /// Opens a stream on `member` with `open` and waits for its first item.
+    async fn open_stream<T, F, Fut>(
+        &self,
+        member: Arc<Member>,
+        open: F,
+    ) -> anyhow::Result<BoxStream<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(Arc<dyn Provider>) -> Fut,
+        Fut: Future<Output = anyhow::Result<BoxStream<T>>>,
+    {
+        let guard = InFlight::start(&self.name, member.clone());
+        let opened = match open(member.provider.clone()).await {
+            Ok(stream) => peek_first(stream).await,
+            Err(e) => Err(e),
+        };
+        match opened {
+            // The guard moves into the stream so the request stays in flight until it ends
+            Ok(stream) => Ok(Box::pin(stream.inspect(move |item| {
+                if item.is_err() {
+                    guard.record_error();
+                }
+            }))),
+            Err(e) => {
+                guard.record_error();
+                Err(e)
+            }
+        }
+    }
+
+    fn hedge_won(&self) {
+        metrics::counter!("hedges_won_total", "pool" => self.name.clone()).increment(1);
+    }
+}
+
+/// Tracks one outstanding request against a member for as long as it lives.
+struct InFlight {
+    pool: String,
+    member: Arc<Member>,
+}
+
+impl InFlight {
+    fn start(pool: &str, member: Arc<Member>) -> Self {
+        let inflight = member.inflight.fetch_add(1, Ordering::Relaxed) + 1;
+        metrics::counter!("upstream_requests_total", "pool" => pool.to_string(), "member" => member.name.clone())
+            .increment(1);
+        metrics::gauge!("upstream_inflight", "pool" => pool.to_string(), "member" => member.name.clone())
+            .set(inflight as f64);
+        Self {
+            pool: pool.to_string(),
+            member,
+        }
+    }
+
+    fn record_error(&self) {
+        metrics::counter!("upstream_errors_total", "pool" => self.pool.clone(), "member" => self.member.name.clone())
+            .increment(1);
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let inflight = self.member.inflight.fetch_sub(1, Ordering::Relaxed) - 1;
+        metrics::gauge!("upstream_inflight", "pool" => self.pool.clone(), "member" => self.member.name.clone())
+            .set(inflight as f64);
+    }
+}
+
+#[async_trait]
+impl Provider for PoolProvider {
+    fn name(&self) -> &'static str {
+        "pool"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            streaming: self
+                .members
+                .iter()
+                .all(|m| m.provider.capabilities().streaming),
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.members.iter().any(|m| m.provider.is_available())
+    }
+
+    async fn chat_completion(
+        &self,
+        payload: ChatCompletionRequest,
+    ) -> anyhow::Result<OpenAIChatCompletionResponse> {
+        let member = self.pick();
+        let guard = InFlight::start(&self.name, member.clone());
+        let result = member.provider.chat_completion(payload).await;
+        if result.is_err() {
+            guard.record_error();
+        }
+        result
+    }
+
+    async fn chat_stream(&self, payload: ChatCompletionRequest) -> anyhow::Result<ChatStream> {
+        let member = self.pick();
+        let Some(delay) = self.hedge_after else {
+            return self.open_stream(member, chat_stream(payload)).await;
+        };
+
+        let primary = self.open_stream(member.clone(), chat_stream(payload.clone()));
+        tokio::pin!(primary);
+        tokio::select! {
+            result = &mut primary => return result,
+            _ = tokio::time::sleep(delay) => {}
+        }
+        let Some(other) = self.pick_other(&member) else {
+            return primary.await;
+        };
+        tracing::debug!(pool = %self.name, member = %other.name, "hedging slow stream");
+        metrics::counter!("hedges_issued_total", "pool" => self.name.clone()).increment(1);
+
+        // First stream to produce a chunk wins; the other future is dropped,
+        // which closes its upstream connection
+        let hedge = self.open_stream(other, chat_stream(payload));
+        tokio::pin!(hedge);
+        tokio::select! {
+            result = &mut primary => match result {
+                Ok(stream) => Ok(stream),
+                Err(_) => hedge.await.inspect(|_| self.hedge_won()),
+            },
+            result = &mut hedge => match result {
+                Ok(stream) => {
+                    self.hedge_won();
+                    Ok(stream)
+                }
+                Err(_) => primary.await,
+            },
+        }
+    }
+
+    async fn embeddings(&self, payload: EmbeddingsRequest) -> anyhow::Result<EmbeddingsResponse> {
+        let member = self.pick();
+        let guard = InFlight::start(&self.name, member.clone());
+        let result = member.provider.embeddings(payload).await;
+        if result.is_err() {
+            guard.record_error();
+        }
+        result
+    }
+
+    async fn responses(&self, payload: ResponsesRequest) -> anyhow::Result<serde_json::Value> {
+        let member = self.pick();
+        let guard = InFlight::start(&self.name, member.clone());
+        let result = member.provider.responses(payload).await;
+        if result.is_err() {
+            guard.record_error();
+        }
+        result
+    }
+
+    async fn transcription(&self, request: TranscriptionRequest) -> anyhow::Result<Transcript> {
+        let member = self.pick();
+        let guard = InFlight::start(&self.name, member.clone());
+        let result = member.provider.transcription(request).await;
+        if result.is_err() {
+            guard.record_error();
+        }
+        result
+    }
+
+    async fn speech(&self, payload: SpeechRequest) -> anyhow::Result<AudioStream> {
+        let member = self.pick();
+        self.open_stream(
+            member,
+            |provider| async move { provider.speech(payload).await },
+        )
+        .await
+    }
+
+    async fn image_generation(
+        &self,
+        payload: ImageGenerationRequest,
+    ) -> anyhow::Result<ImagesResponse> {
+        let member = self.pick();
+        let guard = InFlight::start(&self.name, member.clone());
+        let result = member.provider.image_generation(payload).await;
+        if result.is_err() {
+            guard.record_error();
+        }
+        result
+    }
+
+    async fn realtime(&self, model: &str) -> anyhow::Result<RealtimeSocket> {
+        let member = self.pick();
+        let guard = InFlight::start(&self.name, member.clone());
+        let result = member.provider.realtime(model).await;
+        if result.is_err() {
+            guard.record_error();
+        }
+        result
+    }
+
+    /// Files live in one upstream account, so they always go to the first member.
+    async fn files(&self, request: FilesRequest) -> anyhow::Result<reqwest::Response> {
+        self.members[0].provider.files(request).await
+    }
+
+    async fn responses_stream(&self, payload: ResponsesRequest) -> anyhow::Result<ResponsesStream> {
+        let member = self.pick();
+        self.open_stream(member, |provider| async move {
+            provider.responses_stream(payload).await
+        })
+        .await
+    }
+}
+
+fn chat_stream(
+    payload: ChatCompletionRequest,
+) -> impl FnOnce(Arc<dyn Provider>) -> BoxFuture<'static, anyhow::Result<ChatStream>> {
+    move |provider| Box::pin(async move { provider.chat_stream(payload).await })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{keys::KeyPool, openai::OpenAIProvider, OpenAIStreamChunk};
+
+    /// Answers with a single chunk whose id is its name, after `delay`.
+    struct Stub {
+        id: &'static str,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl Provider for Stub {
+        fn name(&self) -> &'static str {
+            self.id
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities { streaming: true }
+        }
+
+        async fn chat_completion(
+            &self,
+            _: ChatCompletionRequest,
+        ) -> anyhow::Result<OpenAIChatCompletionResponse> {
+            anyhow::bail!("{} only streams", self.id)
+        }
+
+        async fn chat_stream(&self, _: ChatCompletionRequest) -> anyhow::Result<ChatStream> {
+            tokio::time::sleep(self.delay).await;
+            let chunk = OpenAIStreamChunk {
+                id: Some(self.id.to_string()),
+                ..Default::default()
+            };
+            Ok(Box::pin(futures::stream::once(async { Ok(chunk) })))
+        }
+    }
+
+    fn member(name: &str, weight: u32) -> Member {
+        let provider =
+            OpenAIProvider::new(KeyPool::single("test", "test".to_string()), None).unwrap();
+        Member::new(name, Arc::new(provider), weight)
+    }
+
+    #[test]
+    fn weighted_round_robin_is_smooth() {
+        let pool = PoolProvider::new(
+            "test",
+            PoolStrategy::WeightedRoundRobin,
+            vec![member("a", 3), member("b", 1)],
+        )
+        .unwrap();
+        let picks: String = (0..8).map(|_| pool.pick().name.clone()).collect();
+        assert_eq!(picks, "aabaaaba");
+    }
+
+    #[test]
+    fn least_outstanding_prefers_idle_member() {
+        let pool = PoolProvider::new(
+            "test",
+            PoolStrategy::LeastOutstanding,
+            vec![member("a", 1), member("b", 1)],
+        )
+        .unwrap();
+        let _busy = InFlight::start("test", pool.members[0].clone());
+        for _ in 0..4 {
+            assert_eq!(pool.pick().name, "b");
+        }
+    }
+
+    #[tokio::test]
+    async fn hedge_wins_when_first_member_stalls() {
+        let stub = |id, ms| {
+            Member::new(
+                id,
+                Arc::new(Stub {
+                    id,
+                    delay: Duration::from_millis(ms),
+                }),
+                1,
+            )
+        };
+        let pool = PoolProvider::new(
+            "test",
+            PoolStrategy::WeightedRoundRobin,
+            vec![stub("slow", 5_000), stub("fast", 0)],
+        )
+        .unwrap()
+        .with_hedging(Duration::from_millis(20));
+        let req = ChatCompletionRequest {
+            model: "m".to_string(),
+            messages: vec![],
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            stream: Some(true),
+            extra: Default::default(),
+        };
+        let mut stream = pool.chat_stream(req).await.unwrap();
+        let first = stream.next().await.unwrap().unwrap();
+        assert_eq!(first.id.as_deref(), Some("fast"));
+        // The losing request was cancelled, the winner stays in flight until the stream ends
+        let inflight: Vec<_> = pool
+            .members
+            .iter()
+            .map(|m| m.inflight.load(Ordering::Relaxed))
+            .collect();
+        assert_eq!(inflight, [0, 1]);
+    }
+}
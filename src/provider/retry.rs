@@ -0,0 +1,263 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use rand::Rng;
+
+use crate::config::RetryConfig;
+
+use super::{
+    is_transient, peek_first, AudioStream, Capabilities, ChatCompletionRequest, ChatStream,
+    EmbeddingsRequest, EmbeddingsResponse, FilesRequest, ImageGenerationRequest, ImagesResponse,
+    OpenAIChatCompletionResponse, Provider, RealtimeSocket, ResponsesRequest, ResponsesStream,
+    SpeechRequest, Transcript, TranscriptionRequest, UpstreamError,
+};
+
+/// Retries always allowed per second, however little traffic there is.
+const MIN_RETRIES_PER_SEC: f64 = 1.0;
+/// Most retries that can be saved up during quiet periods.
+const MAX_BALANCE: f64 = 10.0;
+
+/// Token bucket that lets retries add at most `ratio` extra load: each request
+/// deposits `ratio` tokens and each retry withdraws one.
+struct RetryBudget {
+    ratio: f64,
+    // (balance, last refill)
+    state: Mutex<(f64, Instant)>,
+}
+
+impl RetryBudget {
+    fn new(ratio: f64, now: Instant) -> Self {
+        Self {
+            ratio,
+            state: Mutex::new((MAX_BALANCE, now)),
+        }
+    }
+
+    fn deposit(&self) {
+        let mut state = self.state.lock().expect("retry budget lock poisoned");
+        state.0 = (state.0 + self.ratio).min(MAX_BALANCE);
+    }
+
+    fn withdraw(&self, now: Instant) -> bool {
+        let mut state = self.state.lock().expect("retry budget lock poisoned");
+        let refill = now.duration_since(state.1).as_secs_f64() * MIN_RETRIES_PER_SEC;
+        *state = ((state.0 + refill).min(MAX_BALANCE), now);
+        if state.0 < 1.0 {
+            return false;
+        }
+        state.0 -= 1.0;
+        true
+    }
+}
+
+/// Retries transient failures of one upstream with exponential backoff and
+/// full jitter. Streams are only retried until their first chunk arrives.
+pub struct RetryProvider {
+    upstream: String,
+    inner: Arc<dyn Provider>,
+    cfg: RetryConfig,
+    budget: RetryBudget,
+}
+
+impl RetryProvider {
+    pub fn new(upstream: &str, inner: Arc<dyn Provider>, cfg: RetryConfig) -> Self {
+        Self {
+            upstream: upstream.to_string(),
+            inner,
+            cfg,
+            budget: RetryBudget::new(cfg.budget_ratio, Instant::now()),
+        }
+    }
+
+    async fn run<T, F, Fut>(&self, mut attempt: F) -> anyhow::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        self.budget.deposit();
+        let mut retries = 0;
+        loop {
+            let err = match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            if retries >= self.cfg.max_retries || !is_transient(&err) {
+                return Err(err);
+            }
+            // A Retry-After longer than we are willing to wait is left to the caller
+            let Some(delay) = self.delay(retries, &err) else {
+                return Err(err);
+            };
+            if !self.budget.withdraw(Instant::now()) {
+                metrics::counter!("retry_budget_exhausted_total", "upstream" => self.upstream.clone())
+                    .increment(1);
+                return Err(err);
+            }
+            retries += 1;
+            metrics::counter!("upstream_retries_total", "upstream" => self.upstream.clone())
+                .increment(1);
+            tracing::debug!(upstream = %self.upstream, retries, ?delay, error = %err, "retrying upstream");
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    fn delay(&self, retries: u32, err: &anyhow::Error) -> Option<Duration> {
+        let max = Duration::from_millis(self.cfg.max_delay_ms);
+        let retry_after = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<UpstreamError>())
+            .and_then(|e| e.retry_after);
+        if let Some(after) = retry_after {
+            return (after <= max).then_some(after);
+        }
+        let ceiling = self
+            .cfg
+            .base_delay_ms
+            .saturating_mul(1 << retries.min(16))
+            .min(self.cfg.max_delay_ms);
+        Some(Duration::from_millis(
+            rand::thread_rng().gen_range(0..=ceiling),
+        ))
+    }
+}
+
+#[async_trait]
+impl Provider for RetryProvider {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn is_available(&self) -> bool {
+        self.inner.is_available()
+    }
+
+    async fn chat_completion(
+        &self,
+        payload: ChatCompletionRequest,
+    ) -> anyhow::Result<OpenAIChatCompletionResponse> {
+        self.run(|| self.inner.chat_completion(payload.clone()))
+            .await
+    }
+
+    async fn chat_stream(&self, payload: ChatCompletionRequest) -> anyhow::Result<ChatStream> {
+        self.run(|| {
+            let payload = payload.clone();
+            // Errors up to the first chunk are still safe to retry
+            async move { peek_first(self.inner.chat_stream(payload).await?).await }
+        })
+        .await
+    }
+
+    async fn embeddings(&self, payload: EmbeddingsRequest) -> anyhow::Result<EmbeddingsResponse> {
+        self.run(|| self.inner.embeddings(payload.clone())).await
+    }
+
+    async fn responses(&self, payload: ResponsesRequest) -> anyhow::Result<serde_json::Value> {
+        self.run(|| self.inner.responses(payload.clone())).await
+    }
+
+    async fn responses_stream(&self, payload: ResponsesRequest) -> anyhow::Result<ResponsesStream> {
+        self.run(|| {
+            let payload = payload.clone();
+            async move { peek_first(self.inner.responses_stream(payload).await?).await }
+        })
+        .await
+    }
+
+    async fn transcription(&self, request: TranscriptionRequest) -> anyhow::Result<Transcript> {
+        self.run(|| self.inner.transcription(request.clone())).await
+    }
+
+    async fn speech(&self, payload: SpeechRequest) -> anyhow::Result<AudioStream> {
+        self.run(|| {
+            let payload = payload.clone();
+            async move { peek_first(self.inner.speech(payload).await?).await }
+        })
+        .await
+    }
+
+    async fn image_generation(
+        &self,
+        payload: ImageGenerationRequest,
+    ) -> anyhow::Result<ImagesResponse> {
+        self.run(|| self.inner.image_generation(payload.clone()))
+            .await
+    }
+
+    async fn realtime(&self, model: &str) -> anyhow::Result<RealtimeSocket> {
+        self.run(|| self.inner.realtime(model)).await
+    }
+
+    // Not retried: an upload or batch the upstream did receive would be
+    // created twice
+    async fn files(&self, request: FilesRequest) -> anyhow::Result<reqwest::Response> {
+        self.inner.files(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{keys::KeyPool, openai::OpenAIProvider};
+
+    fn upstream_error(retry_after: Option<u64>) -> anyhow::Error {
+        UpstreamError {
+            provider: "openai",
+            status: reqwest::StatusCode::TOO_MANY_REQUESTS,
+            body: String::new(),
+            retry_after: retry_after.map(Duration::from_secs),
+        }
+        .into()
+    }
+
+    #[test]
+    fn backoff_is_jittered_and_honors_retry_after() {
+        let inner = Arc::new(
+            OpenAIProvider::new(KeyPool::single("test", "test".to_string()), None).unwrap(),
+        );
+        let p = RetryProvider::new(
+            "test",
+            inner,
+            RetryConfig {
+                max_retries: 3,
+                base_delay_ms: 100,
+                max_delay_ms: 2000,
+                budget_ratio: 0.2,
+            },
+        );
+        for retries in 0..8 {
+            let ceiling = (100u64 << retries).min(2000);
+            let delay = p.delay(retries, &upstream_error(None)).unwrap();
+            assert!(delay <= Duration::from_millis(ceiling));
+        }
+        assert_eq!(
+            p.delay(0, &upstream_error(Some(1))),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(p.delay(0, &upstream_error(Some(30))), None);
+    }
+
+    #[test]
+    fn budget_limits_retries_to_a_share_of_requests() {
+        let t0 = Instant::now();
+        let budget = RetryBudget::new(0.5, t0);
+        for _ in 0..10 {
+            assert!(budget.withdraw(t0));
+        }
+        assert!(!budget.withdraw(t0));
+        budget.deposit();
+        budget.deposit();
+        assert!(budget.withdraw(t0));
+        assert!(!budget.withdraw(t0));
+        // Slow trickle even without traffic
+        assert!(budget.withdraw(t0 + Duration::from_secs(1)));
+    }
+}
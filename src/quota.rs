@@ -12,6 +12,8 @@ pub struct QuotaManager {
     default_quota: u32,
     window: Duration,
     overrides: HashMap<String, u32>,
+    default_image_quota: u32,
+    image_overrides: HashMap<String, u32>,
 }
 
 impl QuotaManager {
@@ -30,6 +32,8 @@ impl QuotaManager {
             default_quota: cfg.default_quota,
             window: Duration::from_secs(cfg.quota_window_secs),
             overrides: cfg.tenant_quotas.clone(),
+            default_image_quota: cfg.default_image_quota,
+            image_overrides: cfg.tenant_image_quotas.clone(),
         }))
     }
 
@@ -40,10 +44,14 @@ impl QuotaManager {
             .unwrap_or(self.default_quota)
     }
 
-    async fn increment(&self, key: &str) -> Result<i64, redis::RedisError> {
+    async fn increment(&self, key: &str, amount: u32) -> Result<i64, redis::RedisError> {
         let mut conn = self.conn.lock().await;
-        let count: i64 = redis::cmd("INCR").arg(key).query_async(&mut *conn).await?;
-        if count == 1 {
+        let count: i64 = redis::cmd("INCRBY")
+            .arg(key)
+            .arg(amount)
+            .query_async(&mut *conn)
+            .await?;
+        if count == amount as i64 {
             let ttl_secs = self.window.as_secs() as usize;
             let _: () = redis::cmd("EXPIRE")
                 .arg(key)
@@ -54,20 +62,88 @@ impl QuotaManager {
         Ok(count)
     }
 
+    async fn decrement(&self, key: &str, amount: u32) -> Result<(), redis::RedisError> {
+        let mut conn = self.conn.lock().await;
+        redis::cmd("DECRBY")
+            .arg(key)
+            .arg(amount)
+            .query_async(&mut *conn)
+            .await
+    }
+
+    /// Charges `amount` more requests for work already served, such as audio
+    /// whose length is only known once it is transcribed. Never rejects: an
+    /// overrun shows up as 429s on the tenant's next requests.
+    pub async fn add(&self, tenant: &str, amount: u32) -> Result<(), QuotaError> {
+        if amount == 0 {
+            return Ok(());
+        }
+        self.increment(&format!("quota:{tenant}"), amount)
+            .await
+            .map(|_| ())
+            .map_err(|e| QuotaError::Backend(e.into()))
+    }
+
     pub async fn check_and_increment(&self, tenant: &str) -> Result<(), QuotaError> {
-        let limit = self.limit_for(tenant);
+        self.check_and_add(tenant, 1).await
+    }
+
+    /// Charges `amount` requests at once, e.g. the lines of a batch.
+    pub async fn check_and_add(&self, tenant: &str, amount: u32) -> Result<(), QuotaError> {
+        self.charge(&format!("quota:{tenant}"), self.limit_for(tenant), amount)
+            .await
+    }
+
+    /// Charges generated images against the tenant's separate images-per-window quota.
+    pub async fn check_and_add_images(&self, tenant: &str, count: u32) -> Result<(), QuotaError> {
+        let limit = self
+            .image_overrides
+            .get(tenant)
+            .copied()
+            .unwrap_or(self.default_image_quota);
+        match self.charge(&format!("images:{tenant}"), limit, count).await {
+            Err(QuotaError::Exceeded { limit, current }) => {
+                Err(QuotaError::ImagesExceeded { limit, current })
+            }
+            other => other,
+        }
+    }
+
+    /// Gives back requests charged for work that was never served.
+    pub async fn refund(&self, tenant: &str, amount: u32) -> Result<(), QuotaError> {
+        self.decrement(&format!("quota:{tenant}"), amount)
+            .await
+            .map_err(|e| QuotaError::Backend(e.into()))
+    }
+
+    /// Gives back images charged by `check_and_add_images` for a request that
+    /// was refused or produced no images.
+    pub async fn refund_images(&self, tenant: &str, count: u32) -> Result<(), QuotaError> {
+        self.decrement(&format!("images:{tenant}"), count)
+            .await
+            .map_err(|e| QuotaError::Backend(e.into()))
+    }
+
+    /// Adds `amount` to the counter at `key`. A charge that would overrun the
+    /// limit is taken back so it doesn't eat the headroom left for smaller
+    /// requests.
+    async fn charge(&self, key: &str, limit: u32, amount: u32) -> Result<(), QuotaError> {
         if limit == 0 {
             return Err(QuotaError::Exceeded {
                 limit,
                 current: limit,
             });
         }
-        let key = format!("quota:{tenant}");
         let count = self
-            .increment(&key)
+            .increment(key, amount)
             .await
             .map_err(|e| QuotaError::Backend(e.into()))?;
         if count as u32 > limit {
+            if amount > 1 {
+                self.decrement(key, amount)
+                    .await
+                    .map_err(|e| QuotaError::Backend(e.into()))?;
+            }
             return Err(QuotaError::Exceeded {
                 limit,
                 current: count as u32,
@@ -81,6 +157,8 @@ impl QuotaManager {
 pub enum QuotaError {
     #[error("tenant quota exceeded (limit {limit}, current {current})")]
     Exceeded { limit: u32, current: u32 },
+    #[error("tenant image quota exceeded (limit {limit}, current {current})")]
+    ImagesExceeded { limit: u32, current: u32 },
     #[error("quota backend error: {0}")]
     Backend(#[from] anyhow::Error),
 }
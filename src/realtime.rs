@@ -0,0 +1,489 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::Message as UpstreamMessage;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::config::AppConfig;
+use crate::fallback::{self, Served};
+use crate::provider::RealtimeSocket;
+use crate::redact::{redact_text, RedactionStats};
+use crate::responses::Held;
+use crate::{
+    handle_quota_error, model_forbidden, record_upstream, tenant, upstream_error_status,
+    with_upstream_header, AppState,
+};
+
+const ROUTE: &str = "/v1/realtime";
+/// Event fields that carry text wherever they appear: content parts,
+/// transcripts, session and response instructions, tool arguments and outputs.
+const TEXT_FIELDS: &[&str] = &["text", "transcript", "instructions", "arguments", "output"];
+/// Streamed texts whose deltas are held back, with the phase of the event
+/// that carries each whole.
+const HELD_STREAMS: [(&str, &str); 6] = [
+    ("response.text", "done"),
+    ("response.output_text", "done"),
+    ("response.audio_transcript", "done"),
+    ("response.output_audio_transcript", "done"),
+    ("response.function_call_arguments", "done"),
+    ("conversation.item.input_audio_transcription", "completed"),
+];
+
+/// Realtime sessions each tenant has open on this instance, capped at
+/// `REALTIME_MAX_SESSIONS`.
+#[derive(Debug, Clone)]
+pub struct SessionLimiter {
+    max: u32,
+    open: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl SessionLimiter {
+    pub fn new(cfg: &AppConfig) -> Self {
+        Self {
+            max: cfg.realtime_max_sessions,
+            open: Default::default(),
+        }
+    }
+
+    fn try_acquire(&self, tenant: &str) -> Option<SessionPermit> {
+        let mut open = self.open.lock().expect("session lock poisoned");
+        let count = open.entry(tenant.to_string()).or_default();
+        if *count >= self.max {
+            return None;
+        }
+        *count += 1;
+        Some(SessionPermit {
+            tenant: tenant.to_string(),
+            open: self.open.clone(),
+        })
+    }
+}
+
+/// One open session; gives its slot back when dropped.
+struct SessionPermit {
+    tenant: String,
+    open: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl Drop for SessionPermit {
+    fn drop(&mut self) {
+        let mut open = self.open.lock().expect("session lock poisoned");
+        if let Some(count) = open.get_mut(&self.tenant) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.tenant);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RealtimeParams {
+    model: String,
+}
+
+/// Relays an OpenAI Realtime API session over WebSockets. The upstream is
+/// connected before the client's upgrade is accepted, so routing, permission,
+/// quota and session cap failures are plain HTTP errors. Each session is
+/// charged as one request.
+#[instrument(skip(state, headers, ws), fields(tenant = %tenant(&headers),
+                                              model = %params.model,
+                                              route = tracing::field::Empty,
+                                              provider = tracing::field::Empty,
+                                              upstream_model = tracing::field::Empty))]
+pub async fn realtime_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<RealtimeParams>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    metrics::counter!("requests_total", "route" => ROUTE).increment(1);
+
+    if !state.permissions.allows(tenant(&headers), &params.model) {
+        return model_forbidden(ROUTE, &params.model);
+    }
+    let Some(permit) = state.realtime.try_acquire(tenant(&headers)) else {
+        metrics::counter!("realtime_sessions_rejected_total").increment(1);
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(json!({ "error": format!(
+                "too many concurrent realtime sessions (limit={})",
+                state.realtime.max
+            ) })),
+        )
+            .into_response();
+    };
+    if let Some(quota) = state.quota.as_ref() {
+        if let Err(err) = quota.check_and_increment(tenant(&headers)).await {
+            return handle_quota_error(err);
+        }
+    }
+
+    let route = state.router.resolve(&params.model);
+    tracing::Span::current().record("route", route.route.as_str());
+    let served = fallback::dispatch(
+        &state.providers,
+        &route,
+        None,
+        |provider, model| async move { provider.realtime(&model).await },
+    )
+    .await;
+    let Served {
+        value: upstream_socket,
+        upstream,
+    } = match served {
+        Ok(served) => served,
+        Err(e) => {
+            return (
+                upstream_error_status(&e),
+                Json(json!({ "error": e.to_string() })),
+            )
+                .into_response();
+        }
+    };
+    record_upstream(&upstream);
+
+    let limit = Duration::from_secs(state.cfg.realtime_max_session_secs);
+    let labels = SessionLabels {
+        model_route: route.route.clone(),
+        upstream: upstream.provider.clone(),
+    };
+    let response = ws
+        .protocols(["realtime"])
+        .on_upgrade(move |socket| async move {
+            relay(socket, upstream_socket, limit, labels).await;
+            drop(permit);
+        });
+    with_upstream_header(response, &upstream)
+}
+
+struct SessionLabels {
+    model_route: String,
+    upstream: String,
+}
+
+/// Copies events both ways, redacting text events, until either side closes
+/// or the session runs out of time. Deltas from the upstream are held back
+/// by a `DeltaRedaction` for the whole session.
+async fn relay(
+    client: WebSocket,
+    upstream: RealtimeSocket,
+    limit: Duration,
+    labels: SessionLabels,
+) {
+    let session = Uuid::new_v4();
+    let started = Instant::now();
+    metrics::gauge!("realtime_sessions_active").increment(1.0);
+    let (mut client_tx, mut client_rx) = client.split();
+    let (mut upstream_tx, mut upstream_rx) = upstream.split();
+    let (mut client_events, mut upstream_events) = (0u64, 0u64);
+    let mut redactions = RedactionStats::default();
+    let mut deltas = DeltaRedaction::default();
+
+    let deadline = tokio::time::sleep(limit);
+    tokio::pin!(deadline);
+    let end = 'relay: loop {
+        tokio::select! {
+            _ = &mut deadline => {
+                let event = json!({
+                    "type": "error",
+                    "error": { "type": "session_expired", "message": "realtime session duration limit reached" },
+                });
+                let _ = client_tx.send(Message::Text(event.to_string())).await;
+                let _ = client_tx
+                    .send(Message::Close(Some(CloseFrame {
+                        code: close_code::POLICY,
+                        reason: "session duration limit reached".into(),
+                    })))
+                    .await;
+                break "expired";
+            }
+            message = client_rx.next() => {
+                let forwarded = match message {
+                    Some(Ok(Message::Text(text))) => {
+                        let text = match serde_json::from_str::<Value>(&text) {
+                            Ok(mut event) => {
+                                redact_event(&mut event, &mut redactions);
+                                event.to_string()
+                            }
+                            Err(_) => {
+                                let (text, stats) = redact_text(&text);
+                                redactions += stats;
+                                text
+                            }
+                        };
+                        UpstreamMessage::Text(text)
+                    }
+                    Some(Ok(Message::Binary(data))) => UpstreamMessage::Binary(data),
+                    // Pings are answered by the socket itself
+                    Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                    Some(Ok(Message::Close(_))) | None => break "client_closed",
+                    Some(Err(_)) => break "client_error",
+                };
+                client_events += 1;
+                if upstream_tx.send(forwarded).await.is_err() {
+                    break "upstream_error";
+                }
+            }
+            message = upstream_rx.next() => {
+                let forwarded = match message {
+                    Some(Ok(UpstreamMessage::Text(text))) => {
+                        let (events, stats) = deltas.process(&text);
+                        redactions += stats;
+                        events.into_iter().map(Message::Text).collect()
+                    }
+                    Some(Ok(UpstreamMessage::Binary(data))) => vec![Message::Binary(data)],
+                    Some(Ok(UpstreamMessage::Close(_))) | None => break "upstream_closed",
+                    Some(Ok(_)) => continue,
+                    Some(Err(_)) => break "upstream_error",
+                };
+                upstream_events += 1;
+                for message in forwarded {
+                    if client_tx.send(message).await.is_err() {
+                        break 'relay "client_error";
+                    }
+                }
+            }
+        }
+    };
+    let _ = upstream_tx.send(UpstreamMessage::Close(None)).await;
+    let _ = client_tx.close().await;
+
+    let seconds = started.elapsed().as_secs_f64();
+    metrics::gauge!("realtime_sessions_active").decrement(1.0);
+    metrics::counter!("realtime_sessions_total",
+        "model_route" => labels.model_route,
+        "upstream" => labels.upstream,
+        "end" => end
+    )
+    .increment(1);
+    metrics::histogram!("realtime_session_seconds").record(seconds);
+    metrics::histogram!("realtime_session_events", "direction" => "client")
+        .record(client_events as f64);
+    metrics::histogram!("realtime_session_events", "direction" => "upstream")
+        .record(upstream_events as f64);
+    metrics::counter!("redactions_total").increment(redactions.matches as u64);
+    tracing::info!(
+        %session,
+        end,
+        seconds,
+        client_events,
+        upstream_events,
+        redactions = redactions.matches,
+        "realtime session ended"
+    );
+}
+
+/// Redaction state of the events a session receives from the upstream. Text,
+/// transcript and function call argument deltas are held back per content
+/// part, the same way as in Responses streams, while the text at their end
+/// could still be part of an email, card or phone number (arguments until
+/// each JSON value is complete); what is left goes out as one more delta just
+/// before the event carrying the whole text.
+#[derive(Default)]
+struct DeltaRedaction {
+    held: HashMap<(String, &'static str, u64), Held>,
+}
+
+impl DeltaRedaction {
+    /// Redacts an event. Returns the events to send in its place: itself,
+    /// preceded by any held-back delta it releases.
+    fn process(&mut self, raw: &str) -> (Vec<String>, RedactionStats) {
+        let Ok(mut event) = serde_json::from_str::<Value>(raw) else {
+            let (text, stats) = redact_text(raw);
+            return (vec![text], stats);
+        };
+        let mut stats = RedactionStats::default();
+        let mut released = Vec::new();
+        let kind = event["type"].as_str().unwrap_or_default().to_string();
+        let held = kind.rsplit_once('.').and_then(|(stream, phase)| {
+            let (stream, whole) = HELD_STREAMS.into_iter().find(|(s, _)| *s == stream)?;
+            let item = event["item_id"].as_str().unwrap_or_default().to_string();
+            let key = (
+                item,
+                stream,
+                event["content_index"].as_u64().unwrap_or_default(),
+            );
+            Some((key, phase == "delta", phase == whole))
+        });
+        match held {
+            Some((key, true, _)) => {
+                let held = self.held.entry(key).or_insert_with(|| Held::new(&event));
+                if let Some(Value::String(delta)) = event.get_mut("delta") {
+                    let (redacted, s) = held.push(delta);
+                    *delta = redacted;
+                    stats += s;
+                }
+                redact_fields(&mut event, &mut stats);
+                return (vec![event.to_string()], stats);
+            }
+            Some((key, _, true)) => released.extend(self.held.remove(&key)),
+            // Nothing of a response may be left behind once it is over
+            _ if kind == "response.done" => {
+                let mut held: Vec<_> = self.held.drain().collect();
+                held.sort_by(|(a, _), (b, _)| a.cmp(b));
+                released.extend(held.into_iter().map(|(_, held)| held));
+            }
+            _ => {}
+        }
+        let mut events: Vec<String> = released
+            .into_iter()
+            .filter_map(Held::finish)
+            .map(|(delta, s)| {
+                stats += s;
+                delta.to_string()
+            })
+            .collect();
+        redact_event(&mut event, &mut stats);
+        events.push(event.to_string());
+        (events, stats)
+    }
+}
+
+/// Redacts the text a realtime event carries. Deltas are redacted on their
+/// own; those from the upstream are held back by `DeltaRedaction` instead.
+fn redact_event(event: &mut Value, stats: &mut RedactionStats) {
+    let kind = event["type"].as_str().unwrap_or_default();
+    // Audio deltas are base64 audio; every other delta is text
+    let text_delta = !kind.contains("audio") || kind.contains("transcript");
+    if text_delta {
+        if let Some(Value::String(delta)) = event.get_mut("delta") {
+            let (redacted, s) = redact_text(delta);
+            *delta = redacted;
+            *stats += s;
+        }
+    }
+    redact_fields(event, stats);
+}
+
+fn redact_fields(value: &mut Value, stats: &mut RedactionStats) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields {
+                match field {
+                    Value::String(text) if TEXT_FIELDS.contains(&name.as_str()) => {
+                        let (redacted, s) = redact_text(text);
+                        *text = redacted;
+                        *stats += s;
+                    }
+                    _ => redact_fields(field, stats),
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                redact_fields(item, stats);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMAIL: &str = "jane.doe@example.com";
+
+    #[test]
+    fn text_is_redacted_in_both_directions_but_audio_is_not() {
+        let events = [
+            json!({ "type": "conversation.item.create", "item": { "type": "message", "role": "user", "content": [{ "type": "input_text", "text": EMAIL }] } }),
+            json!({ "type": "session.update", "session": { "instructions": EMAIL, "voice": "alloy" } }),
+            json!({ "type": "response.text.delta", "delta": EMAIL }),
+            json!({ "type": "response.audio_transcript.done", "transcript": EMAIL }),
+            json!({ "type": "conversation.item.input_audio_transcription.completed", "transcript": EMAIL }),
+            json!({ "type": "response.function_call_arguments.done", "arguments": format!("{{\"to\":\"{EMAIL}\"}}") }),
+            json!({ "type": "response.done", "response": { "output": [{ "type": "message", "content": [{ "type": "audio", "transcript": EMAIL }] }] } }),
+        ];
+        for mut event in events {
+            let mut stats = RedactionStats::default();
+            redact_event(&mut event, &mut stats);
+            assert_eq!(stats.matches, 1, "{event}");
+            assert!(!event.to_string().contains(EMAIL), "{event}");
+        }
+
+        // Base64 audio could look like anything; it must pass untouched
+        let mut audio = json!({ "type": "response.audio.delta", "delta": EMAIL });
+        redact_event(&mut audio, &mut RedactionStats::default());
+        assert_eq!(audio["delta"], EMAIL);
+    }
+
+    #[test]
+    fn deltas_are_held_back_until_values_are_complete() {
+        let mut deltas = DeltaRedaction::default();
+        let mut process = |event: Value| -> Vec<Value> {
+            let (events, _) = deltas.process(&event.to_string());
+            events
+                .iter()
+                .map(|e| serde_json::from_str(e).unwrap())
+                .collect()
+        };
+        let text = |delta: &str| json!({ "type": "response.audio_transcript.delta", "event_id": "event_1", "item_id": "item_1", "content_index": 0, "delta": delta });
+        let arguments = |delta: &str| json!({ "type": "response.function_call_arguments.delta", "item_id": "item_2", "call_id": "call_1", "delta": delta });
+
+        let mut sent = Vec::new();
+        for event in [
+            text("mail jane."),
+            arguments("{\"to\": \"jane.d"),
+            text("doe@exam"),
+            arguments("oe@example.com\"}"),
+            text("ple.com today, or"),
+            json!({ "type": "response.audio_transcript.done", "item_id": "item_1", "content_index": 0, "transcript": "" }),
+            json!({ "type": "response.function_call_arguments.done", "item_id": "item_2", "call_id": "call_1", "arguments": "" }),
+        ] {
+            sent.extend(process(event));
+        }
+        let spelled = |kind: &str| -> String {
+            sent.iter()
+                .filter(|e| e["type"] == kind)
+                .map(|e| e["delta"].as_str().unwrap())
+                .collect()
+        };
+        let transcript = "mail jane.doe@example.com today, or";
+        assert_eq!(
+            spelled("response.audio_transcript.delta"),
+            redact_text(transcript).0
+        );
+        assert!(!spelled("response.function_call_arguments.delta").contains(EMAIL));
+
+        // The rest of the transcript goes out just before its `.done`, without
+        // repeating the first delta's event id
+        let done = sent
+            .iter()
+            .position(|e| e["type"] == "response.audio_transcript.done")
+            .unwrap();
+        assert_eq!(sent[done - 1]["delta"], "or");
+        assert_eq!(sent[done - 1]["item_id"], "item_1");
+        assert!(sent[done - 1].get("event_id").is_none());
+    }
+
+    #[test]
+    fn sessions_are_capped_per_tenant() {
+        let limiter = SessionLimiter {
+            max: 1,
+            open: Default::default(),
+        };
+        let first = limiter.try_acquire("a").unwrap();
+        assert!(limiter.try_acquire("a").is_none());
+        assert!(limiter.try_acquire("b").is_some());
+        drop(first);
+        assert!(limiter.try_acquire("a").is_some());
+    }
+}
@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use once_cell::sync::Lazy;
 use regex::Regex;
 
@@ -11,13 +13,18 @@ impl std::ops::AddAssign for RedactionStats {
     }
 }
 
+static EMAIL: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)\b([A-Z0-9._%+-]+)@([A-Z0-9.-]+\.[A-Z]{2,})\b").unwrap());
+// Possible credit card numbers: sequences of 12-19 digits optionally separated by spaces/dashes
+static CC: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b(?:\d[ -]*?){12,19}\b").unwrap());
+// Match phone numbers that are not preceded by another digit (look-behind unsupported)
+static PHONE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?m)(^|[^\d])(\+?\d[\d \-]{6,}\d)").unwrap());
+
 pub fn redact_text(input: &str) -> (String, RedactionStats) {
     let mut out = input.to_string();
     let mut stats = RedactionStats::default();
 
     // Emails
-    static EMAIL: Lazy<Regex> =
-        Lazy::new(|| Regex::new(r"(?i)\b([A-Z0-9._%+-]+)@([A-Z0-9.-]+\.[A-Z]{2,})\b").unwrap());
     out = EMAIL
         .replace_all(&out, |caps: &regex::Captures| {
             stats.matches += 1;
@@ -28,8 +35,6 @@ pub fn redact_text(input: &str) -> (String, RedactionStats) {
         })
         .to_string();
 
-    // Possible credit card numbers: sequences of 12-19 digits optionally separated by spaces/dashes
-    static CC: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b(?:\d[ -]*?){12,19}\b").unwrap());
     out = CC
         .replace_all(&out, |caps: &regex::Captures| {
             let raw = caps.get(0).unwrap().as_str();
@@ -45,10 +50,6 @@ pub fn redact_text(input: &str) -> (String, RedactionStats) {
         .to_string();
 
     // Phone numbers: mask groups of 7-15 digits
-    static PHONE: Lazy<Regex> = Lazy::new(|| {
-        // Match phone numbers that are not preceded by another digit (look-behind unsupported)
-        Regex::new(r"(?m)(^|[^\d])(\+?\d[\d \-]{6,}\d)").unwrap()
-    });
     out = PHONE
         .replace_all(&out, |caps: &regex::Captures| {
             stats.matches += 1;
@@ -61,6 +62,154 @@ pub fn redact_text(input: &str) -> (String, RedactionStats) {
     (out, stats)
 }
 
+/// Byte ranges of `input` holding the values `redact_text` masks, for data
+/// that spells the text out piece by piece, such as token logprobs.
+pub fn redaction_spans(input: &str) -> Vec<Range<usize>> {
+    let emails = EMAIL.find_iter(input).map(|m| m.range());
+    let cards = CC
+        .find_iter(input)
+        .filter(|m| {
+            let digits: String = m.as_str().chars().filter(|c| c.is_ascii_digit()).collect();
+            luhn_check(&digits)
+        })
+        .map(|m| m.range());
+    let phones = PHONE
+        .captures_iter(input)
+        .filter_map(|caps| caps.get(2))
+        .map(|m| m.range());
+    emails.chain(cards).chain(phones).collect()
+}
+
+/// Redacts a JSON document that arrives in fragments, such as streamed tool
+/// call arguments. Text is held back until it ends on a token boundary outside
+/// any string, so a value split across fragments is still redacted whole.
+#[derive(Debug, Default)]
+pub struct FragmentRedactor {
+    pending: String,
+}
+
+impl FragmentRedactor {
+    /// Adds a fragment and returns the redacted text that is now complete,
+    /// possibly empty.
+    pub fn push(&mut self, fragment: &str) -> (String, RedactionStats) {
+        self.pending.push_str(fragment);
+        let rest = self.pending.split_off(json_boundary(&self.pending));
+        redact_text(&std::mem::replace(&mut self.pending, rest))
+    }
+
+    /// Returns whatever is still held back, for when the document ends early.
+    pub fn finish(&mut self) -> (String, RedactionStats) {
+        redact_text(&std::mem::take(&mut self.pending))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Redacts prose that arrives in pieces, such as streamed chat content. Text
+/// is held back while it could still be part of an email, card or phone
+/// number, so a value split across pieces is still redacted whole.
+#[derive(Debug, Default)]
+pub struct TextRedactor {
+    pending: String,
+    released: usize,
+    spans: Vec<Range<usize>>,
+}
+
+impl TextRedactor {
+    /// Adds a piece and returns the redacted text that is now complete,
+    /// possibly empty.
+    pub fn push(&mut self, piece: &str) -> (String, RedactionStats) {
+        self.pending.push_str(piece);
+        let mut boundary = text_boundary(&self.pending);
+        // Nothing masked is this long; don't hold a run without breaks forever
+        if self.pending.len() - boundary > MAX_HELD_TEXT {
+            boundary = self.pending.len();
+        }
+        let rest = self.pending.split_off(boundary);
+        let text = std::mem::replace(&mut self.pending, rest);
+        self.release(&text)
+    }
+
+    /// Returns whatever is still held back, for when the text ends.
+    pub fn finish(&mut self) -> (String, RedactionStats) {
+        let text = std::mem::take(&mut self.pending);
+        self.release(&text)
+    }
+
+    /// Bytes of the original text released so far.
+    pub fn released(&self) -> usize {
+        self.released
+    }
+
+    /// Byte ranges of the released original text that were masked.
+    pub fn spans(&self) -> &[Range<usize>] {
+        &self.spans
+    }
+
+    fn release(&mut self, text: &str) -> (String, RedactionStats) {
+        let offset = self.released;
+        let spans = redaction_spans(text).into_iter();
+        self.spans
+            .extend(spans.map(|span| span.start + offset..span.end + offset));
+        self.released += text.len();
+        redact_text(text)
+    }
+}
+
+/// Longest run of text `TextRedactor` holds back waiting for a break.
+const MAX_HELD_TEXT: usize = 256;
+
+/// Byte offset just past the last character of `text` that no masked value
+/// can contain: anything but ASCII letters, digits and `._%+-@`, and spaces
+/// that don't continue a number.
+fn text_boundary(text: &str) -> usize {
+    let mut boundary = 0;
+    let mut in_number = false;
+    for (i, ch) in text.char_indices() {
+        match ch {
+            '0'..='9' => in_number = true,
+            '-' => {}
+            ' ' if in_number => {}
+            c if c.is_ascii_alphabetic() || "._%+@".contains(c) => in_number = false,
+            _ => {
+                in_number = false;
+                boundary = i + ch.len_utf8();
+            }
+        }
+    }
+    boundary
+}
+
+/// Byte offset just past the last string or punctuation token of a JSON
+/// prefix that is known to start outside a string.
+fn json_boundary(json: &str) -> usize {
+    let mut boundary = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, ch) in json.char_indices() {
+        if in_string {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => {
+                    in_string = false;
+                    boundary = i + 1;
+                }
+                _ => {}
+            }
+        } else {
+            match ch {
+                '"' => in_string = true,
+                '{' | '}' | '[' | ']' | ':' | ',' => boundary = i + 1,
+                _ => {}
+            }
+        }
+    }
+    boundary
+}
+
 fn mask_mid(s: &str, keep: usize) -> String {
     if s.len() <= keep {
         return "*".repeat(s.len());
@@ -126,4 +275,42 @@ mod tests {
         assert!(luhn_check("4242424242424242"));
         assert!(!luhn_check("1234567890123456"));
     }
+
+    #[test]
+    fn text_pieces_are_redacted_whole() {
+        let text = "Call 555 123 4567 or mail jane.doe@example.com, ok? Card 4242-4242-4242-4242.";
+        // One character at a time is the worst case
+        let mut redactor = TextRedactor::default();
+        let mut out = String::new();
+        for ch in text.chars() {
+            out.push_str(&redactor.push(&ch.to_string()).0);
+        }
+        out.push_str(&redactor.finish().0);
+        assert_eq!(out, redact_text(text).0);
+        assert_eq!(redactor.released(), text.len());
+
+        let masked: Vec<&str> = redactor.spans().iter().map(|s| &text[s.clone()]).collect();
+        assert!(masked.contains(&"jane.doe@example.com"), "{masked:?}");
+        assert!(masked.contains(&"555 123 4567"), "{masked:?}");
+    }
+
+    #[test]
+    fn fragments_are_redacted_whole() {
+        let fragments = [
+            r#"{"to": "jane.d"#,
+            r#"oe@exam"#,
+            r#"ple.com", "note": "say \"hi\" to "#,
+            r#"jane.doe@example.com", "phone": 555123"#,
+            r#"4567}"#,
+        ];
+        let mut redactor = FragmentRedactor::default();
+        let mut out = String::new();
+        for fragment in fragments {
+            out.push_str(&redactor.push(fragment).0);
+        }
+        out.push_str(&redactor.finish().0);
+        assert_eq!(out, redact_text(&fragments.concat()).0);
+        assert!(!out.contains("jane.doe@"));
+        assert!(!out.contains("5551234567"));
+    }
 }
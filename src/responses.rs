@@ -0,0 +1,524 @@
+use std::{collections::HashMap, convert::Infallible, sync::Arc, time::Duration};
+
+use async_stream::stream;
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    response::{sse::Event, IntoResponse, Response, Sse},
+    Json,
+};
+use futures::StreamExt;
+use serde_json::{json, Value};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::fallback::{self, Served};
+use crate::provider::ResponsesRequest;
+use crate::redact::{redact_text, FragmentRedactor, RedactionStats, TextRedactor};
+use crate::telemetry::track_http_metrics;
+use crate::{
+    handle_quota_error, image_rejected, model_forbidden, record_upstream, tenant,
+    upstream_error_status, with_upstream_header, AppState,
+};
+
+const ROUTE: &str = "/v1/responses";
+
+/// Streamed texts whose deltas are held back, with the field telling the
+/// texts of one output item apart.
+const HELD_STREAMS: [(&str, &str); 5] = [
+    ("response.output_text", "content_index"),
+    ("response.refusal", "content_index"),
+    ("response.reasoning_text", "content_index"),
+    ("response.reasoning_summary_text", "summary_index"),
+    ("response.function_call_arguments", "output_index"),
+];
+
+/// Events that end a response; nothing held back may be left after them.
+const TERMINAL_EVENTS: [&str; 3] = [
+    "response.completed",
+    "response.incomplete",
+    "response.failed",
+];
+
+/// Proxies the OpenAI Responses API to upstreams that speak it natively.
+/// Input items and output text are redacted like chat messages; everything
+/// else in the request and response passes through.
+#[instrument(skip(state, headers, req), fields(tenant = %tenant(&headers),
+                                               model = %req.model,
+                                               route = tracing::field::Empty,
+                                               provider = tracing::field::Empty,
+                                               upstream_model = tracing::field::Empty))]
+pub async fn responses_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(mut req): Json<ResponsesRequest>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+    metrics::counter!("requests_total", "route" => ROUTE).increment(1);
+
+    if !state.permissions.allows(tenant(&headers), &req.model) {
+        return model_forbidden(ROUTE, &req.model);
+    }
+    if let Err(err) = input_images(&req).try_for_each(|url| state.images.check(url)) {
+        return image_rejected(ROUTE, err);
+    }
+    if let Some(quota) = state.quota.as_ref() {
+        if let Err(err) = quota.check_and_increment(tenant(&headers)).await {
+            return handle_quota_error(err);
+        }
+    }
+
+    let redaction_stats = redact_request(&mut req);
+    metrics::counter!("redactions_total").increment(redaction_stats.matches as u64);
+
+    let route = state.router.resolve(&req.model);
+    tracing::Span::current().record("route", route.route.as_str());
+    let model = req.model.clone();
+    // Unlike chat completions, the Responses API only streams when asked to
+    let stream_requested = req.stream.unwrap_or(false);
+
+    if !stream_requested {
+        let served = fallback::dispatch(&state.providers, &route, None, |provider, model| {
+            let req = ResponsesRequest {
+                model,
+                ..req.clone()
+            };
+            async move { provider.responses(req).await }
+        })
+        .await;
+        let Served {
+            value: mut response,
+            upstream,
+        } = match served {
+            Ok(served) => served,
+            Err(e) => {
+                track_http_metrics(ROUTE, &model, &route.route, route.primary(), &request_id);
+                return (
+                    upstream_error_status(&e),
+                    Json(json!({ "error": e.to_string() })),
+                )
+                    .into_response();
+            }
+        };
+
+        record_upstream(&upstream);
+        redact_response(&mut response);
+        track_http_metrics(ROUTE, &model, &route.route, &upstream, &request_id);
+        return with_upstream_header(Json(response).into_response(), &upstream);
+    }
+
+    let served = fallback::dispatch(
+        &state.providers,
+        &route,
+        state.cfg.first_byte_timeout_ms.map(Duration::from_millis),
+        |provider, model| {
+            let req = ResponsesRequest {
+                model,
+                ..req.clone()
+            };
+            async move {
+                let mut stream = provider.responses_stream(req).await?;
+                let first = stream.next().await.transpose()?;
+                Ok((first, stream))
+            }
+        },
+    )
+    .await;
+
+    let keep_alive = axum::response::sse::KeepAlive::new().interval(Duration::from_secs(10));
+    let Served {
+        value: (first_event, mut upstream_stream),
+        upstream,
+    } = match served {
+        Ok(served) => served,
+        Err(e) => {
+            track_http_metrics(ROUTE, &model, &route.route, route.primary(), &request_id);
+            return (
+                upstream_error_status(&e),
+                Json(json!({ "error": e.to_string() })),
+            )
+                .into_response();
+        }
+    };
+    record_upstream(&upstream);
+
+    let stream = stream! {
+        let Some(event) = first_event else {
+            return;
+        };
+        let mut redaction = EventRedaction::default();
+        for event in redaction.process(event) {
+            yield Ok::<_, Infallible>(sse_event(event));
+        }
+
+        while let Some(item) = upstream_stream.next().await {
+            match item {
+                Ok(event) => {
+                    for event in redaction.process(event) {
+                        yield Ok(sse_event(event));
+                    }
+                }
+                Err(e) => {
+                    yield Ok(error_event(&format!("stream error: {e}")));
+                    return;
+                }
+            }
+        }
+        for event in redaction.finish() {
+            yield Ok(sse_event(event));
+        }
+    };
+
+    track_http_metrics(ROUTE, &model, &route.route, &upstream, &request_id);
+    with_upstream_header(
+        Sse::new(stream).keep_alive(keep_alive).into_response(),
+        &upstream,
+    )
+}
+
+/// Frames a redacted event with its `type`, as OpenAI does.
+fn sse_event(event: Value) -> Event {
+    let kind = event["type"].as_str().unwrap_or("message").to_string();
+    Event::default()
+        .event(kind)
+        .data(serde_json::to_string(&event).unwrap_or_default())
+}
+
+/// Redaction state of one streamed response. Text deltas are held back per
+/// content part while the text at their end could still be part of an email,
+/// card or phone number, and function call arguments until each JSON value
+/// is complete; what is left goes out as one more delta just before the
+/// part's `.done` event.
+#[derive(Default)]
+struct EventRedaction {
+    held: HashMap<(u64, &'static str, u64), Held>,
+}
+
+/// Text held back for one streamed part, and the fields identifying the
+/// part for the delta that releases it.
+pub(crate) struct Held {
+    pending: Pending,
+    template: Value,
+}
+
+enum Pending {
+    Text(TextRedactor),
+    Arguments(FragmentRedactor),
+}
+
+impl Held {
+    pub(crate) fn new(delta: &Value) -> Self {
+        let mut template = delta.clone();
+        if let Some(fields) = template.as_object_mut() {
+            fields.retain(|k, _| {
+                k == "type" || (k.ends_with("_id") && k != "event_id") || k.ends_with("_index")
+            });
+        }
+        let pending = if delta["type"] == "response.function_call_arguments.delta" {
+            Pending::Arguments(FragmentRedactor::default())
+        } else {
+            Pending::Text(TextRedactor::default())
+        };
+        Self { pending, template }
+    }
+
+    pub(crate) fn push(&mut self, delta: &str) -> (String, RedactionStats) {
+        match &mut self.pending {
+            Pending::Text(text) => text.push(delta),
+            Pending::Arguments(arguments) => arguments.push(delta),
+        }
+    }
+
+    /// A delta with whatever is still held back, if anything is.
+    pub(crate) fn finish(mut self) -> Option<(Value, RedactionStats)> {
+        let (rest, stats) = match &mut self.pending {
+            Pending::Text(text) => text.finish(),
+            Pending::Arguments(arguments) => arguments.finish(),
+        };
+        if rest.is_empty() {
+            return None;
+        }
+        self.template["delta"] = Value::String(rest);
+        Some((self.template, stats))
+    }
+}
+
+impl EventRedaction {
+    /// Redacts an event. Returns the events to send in its place: itself,
+    /// preceded by any held-back delta it releases.
+    fn process(&mut self, mut event: Value) -> Vec<Value> {
+        let kind = event["type"].as_str().unwrap_or_default().to_string();
+        let mut events = Vec::new();
+        let held = kind.rsplit_once('.').and_then(|(stream, phase)| {
+            let (stream, index) = HELD_STREAMS.into_iter().find(|(s, _)| *s == stream)?;
+            let output = event["output_index"].as_u64().unwrap_or_default();
+            let key = (output, stream, event[index].as_u64().unwrap_or_default());
+            Some((key, phase))
+        });
+        match held {
+            Some((key, "delta")) => {
+                let held = self.held.entry(key).or_insert_with(|| Held::new(&event));
+                if let Some(Value::String(delta)) = event.get_mut("delta") {
+                    *delta = held.push(delta).0;
+                }
+            }
+            Some((key, "done")) => events.extend(
+                self.held
+                    .remove(&key)
+                    .and_then(Held::finish)
+                    .map(|(e, _)| e),
+            ),
+            _ if TERMINAL_EVENTS.contains(&kind.as_str()) => events.extend(self.finish()),
+            _ => {}
+        }
+        redact_event(&mut event);
+        events.push(event);
+        events
+    }
+
+    /// Deltas with everything still held back, for when the stream ends.
+    fn finish(&mut self) -> Vec<Value> {
+        let mut held: Vec<_> = self.held.drain().collect();
+        held.sort_by_key(|(key, _)| *key);
+        held.into_iter()
+            .filter_map(|(_, held)| held.finish().map(|(event, _)| event))
+            .collect()
+    }
+}
+
+fn error_event(message: &str) -> Event {
+    Event::default()
+        .event("error")
+        .data(json!({ "type": "error", "message": message }).to_string())
+}
+
+/// URLs of the `input_image` parts in the request's input items.
+pub(crate) fn input_images(req: &ResponsesRequest) -> impl Iterator<Item = &str> {
+    req.input
+        .as_ref()
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.get("content")?.as_array())
+        .flatten()
+        .filter(|part| part["type"] == "input_image")
+        .filter_map(|part| part.get("image_url")?.as_str())
+}
+
+pub(crate) fn redact_request(req: &mut ResponsesRequest) -> RedactionStats {
+    let mut stats = RedactionStats::default();
+    if let Some(instructions) = req.instructions.as_mut() {
+        let (redacted, s) = redact_text(instructions);
+        *instructions = redacted;
+        stats += s;
+    }
+    match req.input.as_mut() {
+        Some(Value::Array(items)) => {
+            for item in items {
+                redact_item(item, &mut stats);
+            }
+        }
+        Some(text) => redact_string(text, &mut stats),
+        None => {}
+    }
+    stats
+}
+
+pub(crate) fn redact_response(response: &mut Value) {
+    let mut stats = RedactionStats::default();
+    if let Some(Value::Array(items)) = response.get_mut("output") {
+        for item in items {
+            redact_item(item, &mut stats);
+        }
+    }
+}
+
+/// Redacts the text carried by a whole streamed value: finished parts and
+/// items, and the response snapshots sent at the start and end. Deltas are
+/// left to [`EventRedaction`].
+fn redact_event(event: &mut Value) {
+    let mut stats = RedactionStats::default();
+    let kind = event["type"].as_str().unwrap_or_default().to_string();
+    match kind.as_str() {
+        "response.output_text.done"
+        | "response.refusal.done"
+        | "response.reasoning_text.done"
+        | "response.reasoning_summary_text.done" => redact_part(event, &mut stats),
+        "response.function_call_arguments.done" => {
+            if let Some(arguments) = event.get_mut("arguments") {
+                redact_string(arguments, &mut stats);
+            }
+        }
+        "response.content_part.added"
+        | "response.content_part.done"
+        | "response.reasoning_summary_part.added"
+        | "response.reasoning_summary_part.done" => {
+            if let Some(part) = event.get_mut("part") {
+                redact_part(part, &mut stats);
+            }
+        }
+        "response.output_item.added" | "response.output_item.done" => {
+            if let Some(item) = event.get_mut("item") {
+                redact_item(item, &mut stats);
+            }
+        }
+        _ => {
+            if let Some(response) = event.get_mut("response") {
+                redact_response(response);
+            }
+        }
+    }
+}
+
+/// An input or output item: a message (string or content parts), a
+/// reasoning summary, a function call or a tool result.
+fn redact_item(item: &mut Value, stats: &mut RedactionStats) {
+    match item.get_mut("content") {
+        Some(Value::Array(parts)) => {
+            for part in parts {
+                redact_part(part, stats);
+            }
+        }
+        Some(text) => redact_string(text, stats),
+        None => {}
+    }
+    if let Some(Value::Array(parts)) = item.get_mut("summary") {
+        for part in parts {
+            redact_part(part, stats);
+        }
+    }
+    for field in ["arguments", "output"] {
+        if let Some(text) = item.get_mut(field) {
+            redact_string(text, stats);
+        }
+    }
+}
+
+fn redact_part(part: &mut Value, stats: &mut RedactionStats) {
+    for field in ["text", "refusal"] {
+        if let Some(text) = part.get_mut(field) {
+            redact_string(text, stats);
+        }
+    }
+}
+
+fn redact_string(value: &mut Value, stats: &mut RedactionStats) {
+    if let Value::String(text) = value {
+        let (redacted, s) = redact_text(text);
+        *text = redacted;
+        *stats += s;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMAIL: &str = "write to jane.doe@example.com";
+
+    #[test]
+    fn redacts_input_items_and_output_events() {
+        let mut req: ResponsesRequest = serde_json::from_value(json!({
+            "model": "gpt-4.1",
+            "input": [
+                { "role": "user", "content": EMAIL },
+                { "role": "user", "content": [{ "type": "input_text", "text": EMAIL }, { "type": "input_image", "image_url": "https://example.com/a.png" }] },
+                { "type": "function_call", "call_id": "c1", "name": "send", "arguments": format!("{{\"body\":\"{EMAIL}\"}}") },
+                { "type": "function_call_output", "call_id": "c1", "output": EMAIL },
+            ],
+            "tools": [{ "type": "web_search" }],
+        }))
+        .unwrap();
+        assert_eq!(redact_request(&mut req).matches, 4);
+        assert!(!serde_json::to_string(&req).unwrap().contains("jane.doe@"));
+        assert_eq!(req.extra["tools"], json!([{ "type": "web_search" }]));
+        assert_eq!(
+            input_images(&req).collect::<Vec<_>>(),
+            ["https://example.com/a.png"]
+        );
+
+        let mut events = [
+            json!({ "type": "response.output_text.done", "text": EMAIL }),
+            json!({ "type": "response.output_item.done", "item": { "type": "message", "content": [{ "type": "output_text", "text": EMAIL }] } }),
+            json!({ "type": "response.completed", "response": { "output": [{ "type": "message", "content": [{ "type": "refusal", "refusal": EMAIL }] }] } }),
+            json!({ "type": "response.function_call_arguments.done", "arguments": format!("{{\"body\":\"{EMAIL}\"}}") }),
+            json!({ "type": "response.output_item.done", "item": { "type": "function_call", "arguments": format!("{{\"body\":\"{EMAIL}\"}}") } }),
+            json!({ "type": "response.reasoning_summary_text.done", "text": EMAIL }),
+            json!({ "type": "response.output_item.done", "item": { "type": "reasoning", "summary": [{ "type": "summary_text", "text": EMAIL }] } }),
+        ];
+        for event in &mut events {
+            redact_event(event);
+            assert!(!event.to_string().contains("jane.doe@"), "{event}");
+        }
+    }
+
+    #[test]
+    fn text_split_across_deltas_is_redacted() {
+        let delta = |output: u64, text: &str| json!({ "type": "response.output_text.delta", "item_id": format!("msg_{output}"), "output_index": output, "content_index": 0, "delta": text, "sequence_number": 1 });
+        let mut redaction = EventRedaction::default();
+        let mut events = Vec::new();
+        for event in [
+            delta(0, "write to jane"),
+            delta(1, "call 555-12"),
+            delta(0, ".doe@exam"),
+            delta(1, "3-4567 now"),
+            delta(0, "ple.com"),
+            json!({ "type": "response.output_text.done", "item_id": "msg_0", "output_index": 0, "content_index": 0, "text": "write to jane.doe@example.com" }),
+            json!({ "type": "response.completed", "response": { "output": [] } }),
+        ] {
+            events.extend(redaction.process(event));
+        }
+        let text = |output: u64| -> String {
+            events
+                .iter()
+                .filter(|e| {
+                    e["type"] == "response.output_text.delta" && e["output_index"] == output
+                })
+                .filter_map(|e| e["delta"].as_str())
+                .collect()
+        };
+        assert_eq!(text(0), redact_text("write to jane.doe@example.com").0);
+        assert!(
+            !text(1).contains("555") && text(1).ends_with(" now"),
+            "{}",
+            text(1)
+        );
+
+        // The rest of part 0 is released just before its `.done`, part 1's
+        // before the response completes
+        let kinds: Vec<_> = events.iter().filter_map(|e| e["type"].as_str()).collect();
+        assert_eq!(
+            kinds[5..],
+            [
+                "response.output_text.delta",
+                "response.output_text.done",
+                "response.output_text.delta",
+                "response.completed"
+            ]
+        );
+        assert_eq!(events[5]["item_id"], "msg_0");
+        assert!(events[5].get("sequence_number").is_none());
+    }
+
+    #[test]
+    fn function_call_arguments_are_held_until_values_are_complete() {
+        let delta = |text: &str| json!({ "type": "response.function_call_arguments.delta", "item_id": "fc_1", "output_index": 1, "delta": text });
+        let mut redaction = EventRedaction::default();
+        let mut events = Vec::new();
+        for event in [
+            delta("{\"to\":\"jane"),
+            delta(".doe@example.com\",\"n"),
+            delta("\":1}"),
+            json!({ "type": "response.function_call_arguments.done", "item_id": "fc_1", "output_index": 1, "arguments": "{\"to\":\"jane.doe@example.com\",\"n\":1}" }),
+        ] {
+            events.extend(redaction.process(event));
+        }
+        let arguments: String = events.iter().filter_map(|e| e["delta"].as_str()).collect();
+        assert_eq!(
+            arguments,
+            redact_text("{\"to\":\"jane.doe@example.com\",\"n\":1}").0
+        );
+        assert!(!arguments.contains("jane.doe"));
+        assert_eq!(events.last().unwrap()["arguments"], arguments);
+    }
+}
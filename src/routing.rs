@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+
+use crate::config::{RouteConfig, RouteTarget};
+use crate::provider::ProviderRegistry;
+
+/// Maps inbound model names onto an ordered list of providers and the model
+/// name each of them expects.
+///
+/// Exact names win over glob patterns; patterns are tried in configuration
+/// order and anything unmatched goes to the default provider unchanged.
+#[derive(Debug, Clone)]
+pub struct ModelRouter {
+    exact: HashMap<String, RouteConfig>,
+    globs: Vec<RouteConfig>,
+    default_provider: String,
+}
+
+/// Outcome of routing one request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteMatch {
+    /// The pattern that matched, or `default`; used as a metrics label.
+    pub route: String,
+    /// Upstreams to try in order; never empty.
+    pub tiers: Vec<Tier>,
+}
+
+/// One upstream a request can be sent to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tier {
+    pub provider: String,
+    pub model: String,
+}
+
+/// A model name that routes somewhere specific, for `/v1/models`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedModel {
+    pub id: String,
+    /// Upstream of the route's first tier.
+    pub upstream: String,
+}
+
+impl RouteMatch {
+    pub fn primary(&self) -> &Tier {
+        &self.tiers[0]
+    }
+}
+
+impl ModelRouter {
+    pub fn new(routes: &[RouteConfig], providers: &ProviderRegistry) -> anyhow::Result<Self> {
+        for route in routes {
+            if let Some(target) = route
+                .targets
+                .iter()
+                .find(|t| providers.get(&t.provider).is_none())
+            {
+                anyhow::bail!(
+                    "route {} targets provider {} which is not configured",
+                    route.pattern,
+                    target.provider
+                );
+            }
+        }
+        Ok(Self::from_routes(routes, providers.default_name()))
+    }
+
+    fn from_routes(routes: &[RouteConfig], default_provider: &str) -> Self {
+        let mut exact = HashMap::new();
+        let mut globs = Vec::new();
+        for route in routes {
+            if route.pattern.contains(['*', '?']) {
+                globs.push(route.clone());
+            } else {
+                exact.insert(route.pattern.clone(), route.clone());
+            }
+        }
+        Self {
+            exact,
+            globs,
+            default_provider: default_provider.to_string(),
+        }
+    }
+
+    pub fn resolve(&self, model: &str) -> RouteMatch {
+        if let Some(route) = self.exact.get(model) {
+            return RouteMatch {
+                route: route.pattern.clone(),
+                tiers: tiers(&route.targets, model, &[]),
+            };
+        }
+        for route in &self.globs {
+            if let Some(captures) = glob_captures(&route.pattern, model) {
+                return RouteMatch {
+                    route: route.pattern.clone(),
+                    tiers: tiers(&route.targets, model, &captures),
+                };
+            }
+        }
+        RouteMatch {
+            route: "default".to_string(),
+            tiers: vec![Tier {
+                provider: self.default_provider.clone(),
+                model: model.to_string(),
+            }],
+        }
+    }
+
+    /// Every exact route, plus the fixed upstream models of glob routes that
+    /// route back through the same glob (`claude*=anthropic@claude-3-5-haiku`
+    /// lists `claude-3-5-haiku`). Models that only reach the default provider
+    /// are unknown to the gateway and not listed.
+    pub fn listed_models(&self) -> Vec<ListedModel> {
+        let mut models: Vec<ListedModel> = self
+            .exact
+            .values()
+            .map(|route| ListedModel {
+                id: route.pattern.clone(),
+                upstream: route.targets[0].provider.clone(),
+            })
+            .collect();
+        for route in &self.globs {
+            for model in route.targets.iter().filter_map(|t| t.model.as_deref()) {
+                if model.contains('*') || self.resolve(model).route != route.pattern {
+                    continue;
+                }
+                models.push(ListedModel {
+                    id: model.to_string(),
+                    upstream: route.targets[0].provider.clone(),
+                });
+            }
+        }
+        models.sort_by(|a, b| a.id.cmp(&b.id));
+        models.dedup_by(|a, b| a.id == b.id);
+        models
+    }
+}
+
+/// Whether `text` matches a `MODEL_ROUTES`-style glob.
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    glob_captures(pattern, text).is_some()
+}
+
+fn tiers(targets: &[RouteTarget], model: &str, captures: &[&str]) -> Vec<Tier> {
+    targets
+        .iter()
+        .map(|t| Tier {
+            provider: t.provider.clone(),
+            model: match t.model.as_deref() {
+                Some(template) => fill_template(template, captures),
+                None => model.to_string(),
+            },
+        })
+        .collect()
+}
+
+/// Matches `text` against a glob where `*` is any run of characters and `?` is
+/// exactly one. Returns what each `*` matched, in order.
+fn glob_captures<'a>(pattern: &str, text: &'a str) -> Option<Vec<&'a str>> {
+    fn go<'a>(p: &[u8], text: &'a str, at: usize, caps: &mut Vec<&'a str>) -> bool {
+        match p.first() {
+            None => at == text.len(),
+            Some(b'*') => {
+                for end in at..=text.len() {
+                    if !text.is_char_boundary(end) {
+                        continue;
+                    }
+                    caps.push(&text[at..end]);
+                    if go(&p[1..], text, end, caps) {
+                        return true;
+                    }
+                    caps.pop();
+                }
+                false
+            }
+            Some(b'?') => match text[at..].chars().next() {
+                Some(c) => go(&p[1..], text, at + c.len_utf8(), caps),
+                None => false,
+            },
+            Some(&b) => text.as_bytes().get(at) == Some(&b) && go(&p[1..], text, at + 1, caps),
+        }
+    }
+
+    let mut caps = Vec::new();
+    go(pattern.as_bytes(), text, 0, &mut caps).then_some(caps)
+}
+
+/// Substitutes each `*` in `template` with the next captured segment.
+fn fill_template(template: &str, captures: &[&str]) -> String {
+    let mut caps = captures.iter();
+    let mut out = String::with_capacity(template.len());
+    for c in template.chars() {
+        if c == '*' {
+            if let Some(cap) = caps.next() {
+                out.push_str(cap);
+                continue;
+            }
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(pattern: &str, targets: &[(&str, Option<&str>)]) -> RouteConfig {
+        RouteConfig {
+            pattern: pattern.to_string(),
+            targets: targets
+                .iter()
+                .map(|(provider, model)| RouteTarget {
+                    provider: provider.to_string(),
+                    model: model.map(str::to_string),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn glob_matching() {
+        assert_eq!(glob_captures("gpt-4o*", "gpt-4o-mini"), Some(vec!["-mini"]));
+        assert_eq!(
+            glob_captures("claude-?-*", "claude-3-opus"),
+            Some(vec!["opus"])
+        );
+        assert_eq!(glob_captures("local/*", "gpt-4o"), None);
+        assert_eq!(glob_captures("exact", "exact"), Some(vec![]));
+    }
+
+    #[test]
+    fn exact_beats_glob_and_default_catches_rest() {
+        let r = ModelRouter::from_routes(
+            &[
+                route("gpt-4o*", &[("azure", None)]),
+                route("gpt-4o-mini", &[("openai", None)]),
+                route("local/*", &[("ollama", Some("*"))]),
+            ],
+            "openai",
+        );
+        assert_eq!(r.resolve("gpt-4o-mini").primary().provider, "openai");
+        assert_eq!(r.resolve("gpt-4o-2024-08-06").primary().provider, "azure");
+
+        let local = r.resolve("local/llama3.1:8b");
+        assert_eq!(local.primary().provider, "ollama");
+        assert_eq!(local.primary().model, "llama3.1:8b");
+        assert_eq!(local.route, "local/*");
+
+        let fallback = r.resolve("mistral-large");
+        assert_eq!(fallback.route, "default");
+        assert_eq!(fallback.primary().provider, "openai");
+    }
+
+    #[test]
+    fn lists_exact_routes_and_fixed_glob_targets() {
+        let r = ModelRouter::from_routes(
+            &[
+                route("gpt-4o-mini", &[("openai", None)]),
+                route("claude*", &[("anthropic", Some("claude-3-5-haiku"))]),
+                route("local/*", &[("ollama", Some("*"))]),
+                // Routed by the exact entry above, so not listed twice
+                route("gpt-*", &[("azure", Some("gpt-4o-mini"))]),
+            ],
+            "openai",
+        );
+        let listed: Vec<_> = r
+            .listed_models()
+            .into_iter()
+            .map(|m| (m.id, m.upstream))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                ("claude-3-5-haiku".to_string(), "anthropic".to_string()),
+                ("gpt-4o-mini".to_string(), "openai".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn fallback_tiers_keep_order_and_fill_templates() {
+        let r = ModelRouter::from_routes(
+            &[route(
+                "gpt-4o*",
+                &[
+                    ("openai", None),
+                    ("azure", None),
+                    ("ollama", Some("llama3.1")),
+                ],
+            )],
+            "openai",
+        );
+        let m = r.resolve("gpt-4o-mini");
+        let tiers: Vec<_> = m
+            .tiers
+            .iter()
+            .map(|t| (t.provider.as_str(), t.model.as_str()))
+            .collect();
+        assert_eq!(
+            tiers,
+            [
+                ("openai", "gpt-4o-mini"),
+                ("azure", "gpt-4o-mini"),
+                ("ollama", "llama3.1")
+            ]
+        );
+    }
+}
@@ -5,6 +5,8 @@ use opentelemetry_sdk::{propagation::TraceContextPropagator, trace as sdktrace,
 use tracing_opentelemetry::OpenTelemetryLayer;
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
+use crate::routing::Tier;
+
 pub fn init_tracing(cfg: &crate::config::AppConfig) {
     let env_filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new("info,tower_http=info,axum::rejection=trace"));
@@ -30,11 +32,19 @@ pub fn init_metrics() -> anyhow::Result<PrometheusHandle> {
     Ok(handle)
 }
 
-pub fn track_http_metrics(route: &str, model: &str, request_id: &str) {
+pub fn track_http_metrics(
+    route: &str,
+    model: &str,
+    model_route: &str,
+    upstream: &Tier,
+    request_id: &str,
+) {
     metrics::counter!(
         "http_requests_total",
         "route" => route.to_string(),
-        "model" => model.to_string()
+        "model" => model.to_string(),
+        "model_route" => model_route.to_string(),
+        "provider" => upstream.provider.clone()
     )
     .increment(1);
     metrics::gauge!("inflight_requests").increment(1.0);